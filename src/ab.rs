@@ -0,0 +1,153 @@
+use crate::usb::Mix;
+
+/// One of the two A/B compare slots
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    #[default]
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// Two in-memory mixer states that can be flipped between.
+/// The live mixes always belong to the active slot,
+/// the other slot holds the stored state.
+/// Only levels, pans, mutes and solos are compared; mix names and colours
+/// always stay the live ones.
+#[derive(Default)]
+pub struct AbCompare {
+    pub active: Slot,
+    slots: [Option<Vec<Mix>>; 2],
+}
+
+impl AbCompare {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store the live mixes in the active slot and load the other slot.
+    /// If the other slot is still empty it starts out as a copy of the live mixes.
+    /// The caller is responsible for writing the new state to the device.
+    pub fn toggle(&mut self, mixes: &mut Vec<Mix>) {
+        let next = self.active.other();
+        let mut stored = self.slots[next.index()]
+            .take()
+            .unwrap_or_else(|| mixes.clone());
+        keep_labels(mixes, &mut stored);
+        self.slots[self.active.index()] = Some(std::mem::replace(mixes, stored));
+        self.active = next;
+    }
+
+    /// Copy slot A to slot B.
+    /// Returns true if the live mixes were changed and need to be written.
+    pub fn copy_a_to_b(&mut self, mixes: &mut Vec<Mix>) -> bool {
+        match self.active {
+            Slot::A => {
+                self.slots[Slot::B.index()] = Some(mixes.clone());
+                false
+            }
+            Slot::B => match &self.slots[Slot::A.index()] {
+                Some(a) => {
+                    let mut a = a.clone();
+                    keep_labels(mixes, &mut a);
+                    *mixes = a;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+/// Copy the names and colours of the mixes in `from` to `to`
+fn keep_labels(from: &[Mix], to: &mut [Mix]) {
+    for (from, to) in from.iter().zip(to) {
+        to.name = from.name.clone();
+        to.color = from.color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::StripKind;
+
+    fn mixes(fader: f64) -> Vec<Mix> {
+        let mut mix = Mix::new(String::from("MAIN 1-2"), StripKind::Main, 0, 4);
+        mix.strips.channel_strips[0].fader = fader;
+        vec![mix]
+    }
+
+    #[test]
+    fn test_toggle_initializes_b_from_a() {
+        let mut ab = AbCompare::new();
+        let mut live = mixes(-6.0);
+
+        ab.toggle(&mut live);
+
+        assert_eq!(ab.active, Slot::B);
+        assert_eq!(live[0].strips.channel_strips[0].fader, -6.0);
+    }
+
+    #[test]
+    fn test_toggle_swaps_states() {
+        let mut ab = AbCompare::new();
+        let mut live = mixes(-6.0);
+
+        ab.toggle(&mut live);
+        live[0].strips.channel_strips[0].fader = -12.0;
+
+        ab.toggle(&mut live);
+        assert_eq!(ab.active, Slot::A);
+        assert_eq!(live[0].strips.channel_strips[0].fader, -6.0);
+
+        ab.toggle(&mut live);
+        assert_eq!(ab.active, Slot::B);
+        assert_eq!(live[0].strips.channel_strips[0].fader, -12.0);
+    }
+
+    #[test]
+    fn test_toggle_keeps_names() {
+        let mut ab = AbCompare::new();
+        let mut live = mixes(-6.0);
+
+        ab.toggle(&mut live);
+        live[0].name = String::from("Drums");
+        ab.toggle(&mut live);
+        assert_eq!(live[0].name, "Drums");
+        ab.toggle(&mut live);
+        assert_eq!(live[0].name, "Drums");
+    }
+
+    #[test]
+    fn test_copy_a_to_b() {
+        let mut ab = AbCompare::new();
+        let mut live = mixes(-6.0);
+
+        // From A: B is overwritten, live state is untouched
+        assert!(!ab.copy_a_to_b(&mut live));
+        live[0].strips.channel_strips[0].fader = -3.0;
+        ab.toggle(&mut live);
+        assert_eq!(live[0].strips.channel_strips[0].fader, -6.0);
+
+        // From B: live state is replaced by A
+        live[0].strips.channel_strips[0].fader = -20.0;
+        assert!(ab.copy_a_to_b(&mut live));
+        assert_eq!(live[0].strips.channel_strips[0].fader, -3.0);
+    }
+}
@@ -0,0 +1,204 @@
+//! Permissions of remote clients. Every remote change goes through
+//! `PreSonusStudio1824c::apply_remote_change`, and every scene recall through
+//! `PreSonusStudio1824c::recall_remote_scene`, which check it here
+//! before anything is written to the interface.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A kind of change a client can be allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Control {
+    Fader,
+    Pan,
+    /// Mute of channel and bus strips
+    Mute,
+    Solo,
+    /// Mute of the main output, the bus strip of MAIN 1-2
+    MainMute,
+    MainMono,
+    Phantom,
+    /// Line level of inputs 1-2
+    Line,
+}
+
+impl Control {
+    pub const ALL: [Control; 8] = [
+        Control::Fader,
+        Control::Pan,
+        Control::Mute,
+        Control::Solo,
+        Control::MainMute,
+        Control::MainMono,
+        Control::Phantom,
+        Control::Line,
+    ];
+}
+
+/// Change of a strip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StripChange {
+    Fader(f64),
+    Pan(f64),
+    Mute(bool),
+    Solo(bool),
+}
+
+/// A change asked for by a remote client, mixes and strips are indexes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoteChange {
+    Strip {
+        mix: usize,
+        strip: usize,
+        change: StripChange,
+    },
+    MainMono(bool),
+    Phantom(bool),
+    Line(bool),
+}
+
+/// What a client may change. Mixes are numbered from 1, everything is allowed if not set.
+/// A client limited to some mixes without a list of controls can't use the
+/// global controls, and main mute only if MAIN 1-2 is one of its mixes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Permissions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mixes: Option<Vec<usize>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub controls: Option<Vec<Control>>,
+}
+
+/// A change refused by the permissions of a client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denied {
+    Mix(usize),
+    Control(Control),
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denied::Mix(index) => write!(f, "mix {} is not allowed", index + 1),
+            Denied::Control(control) => write!(
+                f,
+                "{} is not allowed",
+                serde_json::to_value(control).unwrap().as_str().unwrap()
+            ),
+        }
+    }
+}
+
+impl Permissions {
+    pub fn allows_mix(&self, index: usize) -> bool {
+        self.mixes
+            .as_ref()
+            .is_none_or(|mixes| mixes.contains(&(index + 1)))
+    }
+
+    pub fn allows_control(&self, control: Control) -> bool {
+        match (&self.controls, control) {
+            (Some(controls), _) => controls.contains(&control),
+            (None, Control::MainMute) => self.allows_mix(0),
+            (None, Control::MainMono | Control::Phantom | Control::Line) => self.mixes.is_none(),
+            (None, _) => true,
+        }
+    }
+
+    /// Check a change of `control` on the mix at `mix`, if any
+    pub fn check(&self, mix: Option<usize>, control: Control) -> Result<(), Denied> {
+        if let Some(mix) = mix.filter(|&mix| !self.allows_mix(mix)) {
+            return Err(Denied::Mix(mix));
+        }
+        if !self.allows_control(control) {
+            return Err(Denied::Control(control));
+        }
+        Ok(())
+    }
+
+    /// Check the recall of a scene, which changes every strip of all `mixes`
+    pub fn check_scene(&self, mixes: usize) -> Result<(), Denied> {
+        if let Some(mix) = (0..mixes).find(|&mix| !self.allows_mix(mix)) {
+            return Err(Denied::Mix(mix));
+        }
+        let strip_controls = [
+            Control::Fader,
+            Control::Pan,
+            Control::Mute,
+            Control::Solo,
+            Control::MainMute,
+        ];
+        match strip_controls
+            .into_iter()
+            .find(|&c| !self.allows_control(c))
+        {
+            Some(control) => Err(Denied::Control(control)),
+            None => Ok(()),
+        }
+    }
+
+    /// Allowed controls, for clients to hide the others
+    pub fn controls(&self) -> Vec<Control> {
+        Control::ALL
+            .into_iter()
+            .filter(|&control| self.allows_control(control))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let drummer: Permissions =
+            serde_json::from_str(r#"{ "mixes": [2], "controls": ["fader", "pan", "mute"] }"#)
+                .unwrap();
+        assert_eq!(drummer.check(Some(1), Control::Fader), Ok(()));
+        assert_eq!(drummer.check(Some(0), Control::Fader), Err(Denied::Mix(0)));
+        assert_eq!(
+            drummer.check(Some(1), Control::Solo),
+            Err(Denied::Control(Control::Solo))
+        );
+        assert_eq!(
+            drummer.check(None, Control::Phantom),
+            Err(Denied::Control(Control::Phantom))
+        );
+        assert_eq!(
+            Denied::Control(Control::MainMute).to_string(),
+            "main-mute is not allowed"
+        );
+        assert_eq!(drummer.controls().len(), 3);
+
+        let monitor: Permissions = serde_json::from_str(r#"{ "mixes": [3] }"#).unwrap();
+        assert_eq!(monitor.check(Some(2), Control::Solo), Ok(()));
+        for control in [Control::MainMono, Control::Phantom, Control::Line] {
+            assert_eq!(monitor.check(None, control), Err(Denied::Control(control)));
+        }
+        assert_eq!(
+            monitor.controls(),
+            [Control::Fader, Control::Pan, Control::Mute, Control::Solo]
+        );
+        let main: Permissions = serde_json::from_str(r#"{ "mixes": [1, 3] }"#).unwrap();
+        assert_eq!(main.check(Some(0), Control::MainMute), Ok(()));
+        assert_eq!(
+            main.check(None, Control::Phantom),
+            Err(Denied::Control(Control::Phantom))
+        );
+
+        assert_eq!(drummer.check_scene(9), Err(Denied::Mix(0)));
+        let all_mixes: Permissions =
+            serde_json::from_str(r#"{ "mixes": [1, 2], "controls": ["fader", "pan"] }"#).unwrap();
+        assert_eq!(
+            all_mixes.check_scene(2),
+            Err(Denied::Control(Control::Mute))
+        );
+
+        let engineer = Permissions::default();
+        assert_eq!(engineer.check_scene(9), Ok(()));
+        assert_eq!(engineer.check(Some(0), Control::MainMute), Ok(()));
+        assert_eq!(engineer.check(None, Control::Phantom), Ok(()));
+        assert_eq!(engineer.controls(), Control::ALL);
+    }
+}
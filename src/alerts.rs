@@ -0,0 +1,366 @@
+//! Alerts on clips, overs and silence of the meters, from the rules in `alerts.json`.
+//! Alerts are shown in the status line, sent as desktop notifications
+//! and can run a command line in the TUI.
+
+use crate::config::{ConfigError, FieldError};
+use crate::usb::CLIP_THRESHOLD;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub const ALERTS_FILE: &str = "alerts.json";
+
+/// How long the status line flashes after an alert
+pub const FLASH_TIME: Duration = Duration::from_secs(3);
+
+/// Meter watched by a rule, numbered from 1 in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum MeterRef {
+    /// Input meter index
+    Input(usize),
+    /// Mix index, both bus meters of the mix
+    Bus(usize),
+}
+
+impl TryFrom<String> for MeterRef {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("expected 'input N' or 'bus N', got '{}'", value);
+        let (kind, number) = value.split_once(' ').ok_or_else(invalid)?;
+        let index = number
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .ok_or_else(invalid)?;
+        match kind {
+            "input" => Ok(MeterRef::Input(index)),
+            "bus" => Ok(MeterRef::Bus(index)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// What a rule watches for
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "alert", rename_all = "kebab-case")]
+pub enum Condition {
+    /// Sample peak at full scale
+    Clip,
+    /// Sample peak above `db` dBFS for at least `for_ms`
+    Over {
+        #[serde(default = "default_over_db")]
+        db: f64,
+        #[serde(default)]
+        for_ms: u64,
+    },
+    /// Sample peak below `db` dBFS for at least `seconds`, only while alerts are armed
+    Silence {
+        #[serde(default = "default_silence_db")]
+        db: f64,
+        seconds: f64,
+    },
+}
+
+fn default_over_db() -> f64 {
+    -1.0
+}
+
+fn default_silence_db() -> f64 {
+    -60.0
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rule {
+    pub meter: MeterRef,
+    #[serde(flatten)]
+    pub condition: Condition,
+    /// Command line to run when the alert is raised, like `:bus fader -20`
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+impl Rule {
+    /// Alert text for the meter called `name`
+    pub fn message(&self, name: &str) -> String {
+        match self.condition {
+            Condition::Clip => format!("Clip on {}", name),
+            Condition::Over { db, for_ms: 0 } => format!("{} over {:.1} dBFS", name, db),
+            Condition::Over { db, for_ms } => {
+                format!("{} over {:.1} dBFS for {} ms", name, db, for_ms)
+            }
+            Condition::Silence { seconds, .. } => format!("{} silent for {} s", name, seconds),
+        }
+    }
+
+    /// True if the condition holds for the sample `peak`
+    fn holds(&self, peak: f64) -> bool {
+        match self.condition {
+            Condition::Clip => peak > CLIP_THRESHOLD,
+            Condition::Over { db, .. } => peak > db,
+            Condition::Silence { db, .. } => peak < db,
+        }
+    }
+
+    /// How long the condition has to hold before the alert is raised
+    fn duration(&self) -> Duration {
+        match self.condition {
+            Condition::Clip => Duration::ZERO,
+            Condition::Over { for_ms, .. } => Duration::from_millis(for_ms),
+            Condition::Silence { seconds, .. } => {
+                Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+            }
+        }
+    }
+}
+
+/// Contents of the alerts file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AlertsFile {
+    pub rules: Vec<Rule>,
+    /// Send desktop notifications with `notify-send`
+    #[serde(default = "default_notify")]
+    pub notify: bool,
+    /// Watch for silence from the start
+    #[serde(default)]
+    pub armed: bool,
+}
+
+fn default_notify() -> bool {
+    true
+}
+
+/// State of a rule between polls
+#[derive(Debug, Clone, Copy, Default)]
+struct RuleState {
+    /// When the condition started to hold
+    since: Option<Instant>,
+    /// The alert was raised for this time the condition holds
+    raised: bool,
+}
+
+pub struct Alerts {
+    rules: Vec<Rule>,
+    states: Vec<RuleState>,
+    notify: bool,
+    /// Silence rules only raise alerts while armed
+    pub armed: bool,
+}
+
+impl Alerts {
+    /// Load the rules from the alerts file in `dir`, None if there is no file.
+    /// Meters are checked against the `inputs` and `mixes` of the interface.
+    /// A front-end that doesn't run the `commands` of the rules warns about them.
+    pub fn load(
+        dir: &Path,
+        inputs: usize,
+        mixes: usize,
+        commands: bool,
+    ) -> Result<Option<Self>, ConfigError> {
+        let alerts = match fs::read_to_string(dir.join(ALERTS_FILE)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+            Ok(contents) => Alerts::parse(&contents, inputs, mixes)?,
+        };
+        if !commands && alerts.rules.iter().any(|rule| rule.command.is_some()) {
+            log::warn!("Ignoring the commands of the alert rules, only the TUI runs them");
+        }
+        Ok(Some(alerts))
+    }
+
+    fn parse(contents: &str, inputs: usize, mixes: usize) -> Result<Self, ConfigError> {
+        let file: AlertsFile = serde_json::from_str(contents)?;
+        let errors: Vec<FieldError> = file
+            .rules
+            .iter()
+            .enumerate()
+            .flat_map(|(i, rule)| {
+                let meter = match rule.meter {
+                    MeterRef::Input(index) if index >= inputs => {
+                        Some(format!("inputs are numbered 1 to {}", inputs))
+                    }
+                    MeterRef::Bus(index) if index >= mixes => {
+                        Some(format!("buses are numbered 1 to {}", mixes))
+                    }
+                    _ => None,
+                };
+                let seconds = match rule.condition {
+                    Condition::Silence { seconds, .. }
+                        if Duration::try_from_secs_f64(seconds).is_err() =>
+                    {
+                        Some("must be a number of seconds from 0".to_string())
+                    }
+                    _ => None,
+                };
+                [("meter", meter), ("seconds", seconds)]
+                    .into_iter()
+                    .filter_map(move |(field, message)| {
+                        Some(FieldError {
+                            path: format!("rules[{}].{}", i, field),
+                            message: message?,
+                        })
+                    })
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
+        }
+
+        Ok(Alerts {
+            states: vec![RuleState::default(); file.rules.len()],
+            rules: file.rules,
+            notify: file.notify,
+            armed: file.armed,
+        })
+    }
+
+    /// Check the rules against the sample peaks of the input and bus meters polled at `now`.
+    /// Returns the rules that raise an alert.
+    pub fn check(&mut self, inputs: &[f64], buses: &[f64], now: Instant) -> Vec<&Rule> {
+        let mut raised = Vec::new();
+        for (rule, state) in self.rules.iter().zip(&mut self.states) {
+            let peak = match rule.meter {
+                MeterRef::Input(index) => inputs.get(index).copied(),
+                MeterRef::Bus(index) => buses.get(index * 2..index * 2 + 2).map(|m| m[0].max(m[1])),
+            };
+            let silence = matches!(rule.condition, Condition::Silence { .. });
+            if !peak.is_some_and(|peak| rule.holds(peak)) || (silence && !self.armed) {
+                *state = RuleState::default();
+                continue;
+            }
+            let since = *state.since.get_or_insert(now);
+            if !state.raised && now.duration_since(since) >= rule.duration() {
+                state.raised = true;
+                raised.push(rule);
+            }
+        }
+        raised
+    }
+
+    /// Send a desktop notification if enabled
+    pub fn notify(&self, message: &str) {
+        if !self.notify {
+            return;
+        }
+        let result = Command::new("notify-send")
+            .args(["--app-name=Baton", "Baton", message])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(e) = result {
+            log::warn!("Failed to send desktop notification: {}", e);
+        }
+    }
+}
+
+impl fmt::Display for MeterRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeterRef::Input(index) => write!(f, "input {}", index + 1),
+            MeterRef::Bus(index) => write!(f, "bus {}", index + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"{
+        "rules": [
+            { "meter": "input 1", "alert": "clip" },
+            { "meter": "bus 2", "alert": "over", "for_ms": 200, "command": ":bus fader -20" },
+            { "meter": "input 3", "alert": "silence", "seconds": 10 }
+        ]
+    }"#;
+
+    fn names(rules: Vec<&Rule>) -> Vec<String> {
+        rules.iter().map(|r| r.meter.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let alerts = Alerts::parse(RULES, 36, 9).unwrap();
+        assert_eq!(alerts.rules[0].meter, MeterRef::Input(0));
+        assert_eq!(
+            alerts.rules[1].condition,
+            Condition::Over {
+                db: -1.0,
+                for_ms: 200
+            }
+        );
+        assert_eq!(alerts.rules[1].command.as_deref(), Some(":bus fader -20"));
+        assert!(alerts.notify);
+        assert!(!alerts.armed);
+
+        match Alerts::parse(RULES, 36, 1) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors[0].path, "rules[1].meter"),
+            _ => panic!("expected an invalid bus"),
+        }
+        assert!(
+            Alerts::parse(
+                r#"{ "rules": [{ "meter": "mic 1", "alert": "clip" }] }"#,
+                36,
+                9
+            )
+            .is_err()
+        );
+        let silence =
+            r#"{ "rules": [{ "meter": "input 1", "alert": "silence", "seconds": 1e30 }] }"#;
+        match Alerts::parse(silence, 36, 9) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors[0].path, "rules[0].seconds"),
+            _ => panic!("expected invalid seconds"),
+        }
+    }
+
+    #[test]
+    fn test_clip_and_over() {
+        let mut alerts = Alerts::parse(RULES, 4, 2).unwrap();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let quiet = [-40.0; 4];
+
+        // A clip is raised once until the meter drops below full scale
+        assert_eq!(
+            names(alerts.check(&[0.0, -40.0, -40.0, -40.0], &quiet, ms(0))),
+            ["input 1"]
+        );
+        assert!(
+            alerts
+                .check(&[0.0, -40.0, -40.0, -40.0], &quiet, ms(50))
+                .is_empty()
+        );
+        alerts.check(&quiet, &quiet, ms(100));
+        assert_eq!(
+            names(alerts.check(&[0.0, -40.0, -40.0, -40.0], &quiet, ms(150))),
+            ["input 1"]
+        );
+
+        // Either side of the bus over -1 dBFS for 200 ms
+        let over = [-40.0, -40.0, -40.0, -0.5];
+        assert!(alerts.check(&quiet, &over, ms(0)).is_empty());
+        assert!(alerts.check(&quiet, &over, ms(150)).is_empty());
+        assert_eq!(names(alerts.check(&quiet, &over, ms(200))), ["bus 2"]);
+    }
+
+    #[test]
+    fn test_silence_while_armed() {
+        let mut alerts = Alerts::parse(RULES, 4, 2).unwrap();
+        let start = Instant::now();
+        let s = |s| start + Duration::from_secs(s);
+        let silent = [-90.0; 4];
+
+        assert!(alerts.check(&silent, &silent, s(0)).is_empty());
+        assert!(alerts.check(&silent, &silent, s(20)).is_empty());
+        alerts.armed = true;
+        assert!(alerts.check(&silent, &silent, s(21)).is_empty());
+        assert_eq!(names(alerts.check(&silent, &silent, s(31))), ["input 3"]);
+    }
+}
@@ -0,0 +1,605 @@
+use crate::config::{self, Paths};
+use crate::device;
+use crate::matrix::format_pan;
+use crate::scene;
+use crate::usb::{PreSonusStudio1824c, StripKind};
+use serde_json::json;
+use std::error::Error;
+
+pub const USAGE: &str = "\
+Usage: baton [--config DIR] [--device SERIAL|N] [COMMAND]
+
+Without a command the mixer is started. With several interfaces connected,
+--device picks one by serial number or by its number in `baton devices`.
+
+Commands:
+  set --mix N --strip S [--fader DB] [--pan PAN] [--mute SWITCH] [--solo SWITCH]
+  mute [--mix N --strip S] [SWITCH]   mute a strip, or the main output
+  phantom SWITCH                      48V phantom power
+  line SWITCH                         inputs 1-2 line level
+  mono SWITCH                         main output mono
+  bypass [on|off]                     route inputs directly to outputs
+  scene save|load NAME
+  scene list
+  dump [--json]                       print the mixer state
+  devices                             list the connected interfaces
+  help
+
+Mixes are numbered from 1 (MAIN 1-2). Strips are numbered from 1,
+or `bus` for the mix output. PAN is -100 to 100, or L30, C, R30.
+SWITCH is on, off or toggle; mute defaults to on.
+";
+
+/// On/off argument of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    On,
+    Off,
+    Toggle,
+}
+
+impl Switch {
+    pub fn apply(self, current: bool) -> bool {
+        match self {
+            Switch::On => true,
+            Switch::Off => false,
+            Switch::Toggle => !current,
+        }
+    }
+}
+
+/// A strip given as `--mix N --strip S`, numbered from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StripAddress {
+    pub mix: usize,
+    /// None for the bus strip of the mix
+    pub strip: Option<usize>,
+}
+
+impl StripAddress {
+    /// Mix and strip index, with the bus strip after the channel strips
+    fn resolve(&self, ps: &PreSonusStudio1824c) -> Result<(usize, usize), String> {
+        let mix = ps
+            .mixes
+            .get(self.mix - 1)
+            .ok_or_else(|| format!("mix must be between 1 and {}", ps.mixes.len()))?;
+        let channels = mix.strips.channel_strips.len();
+        match self.strip {
+            None => Ok((self.mix - 1, channels)),
+            Some(strip) if strip <= channels => Ok((self.mix - 1, strip - 1)),
+            Some(_) => Err(format!("strip must be between 1 and {}, or bus", channels)),
+        }
+    }
+}
+
+/// A one-shot command given on the command line
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Set {
+        target: StripAddress,
+        fader: Option<f64>,
+        pan: Option<f64>,
+        mute: Option<Switch>,
+        solo: Option<Switch>,
+    },
+    Mute {
+        target: Option<StripAddress>,
+        switch: Switch,
+    },
+    Phantom(Switch),
+    Line(Switch),
+    Mono(Switch),
+    Bypass(bool),
+    SceneSave(String),
+    SceneLoad(String),
+    SceneList,
+    Devices,
+    Dump {
+        json: bool,
+    },
+    Help,
+}
+
+/// Parse the command line arguments, without the program name.
+/// Returns None if no command is given and the mixer should be started.
+pub fn parse(args: &[String]) -> Result<Option<Action>, String> {
+    // `--config DIR` and `--device SERIAL` are handled by `config::config_arg`
+    // and `config::device_arg`
+    let mut words = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" || arg == "--device" {
+            iter.next();
+        } else if !arg.starts_with("--config=") && !arg.starts_with("--device=") {
+            words.push(arg.as_str());
+        }
+    }
+
+    let Some((&command, rest)) = words.split_first() else {
+        return Ok(None);
+    };
+    let mut rest = rest.iter().copied();
+
+    let action = match command {
+        "set" => {
+            let mut mix = None;
+            let mut strip = None;
+            let mut fader = None;
+            let mut pan = None;
+            let mut mute = None;
+            let mut solo = None;
+            while let Some(option) = rest.next() {
+                let value = rest
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", option))?;
+                match option {
+                    "--mix" => mix = Some(parse_number(value, "mix")?),
+                    "--strip" => strip = Some(parse_strip(value)?),
+                    "--fader" => fader = Some(parse_fader(value)?),
+                    "--pan" => pan = Some(parse_pan(value)?),
+                    "--mute" => mute = Some(parse_switch(value)?),
+                    "--solo" => solo = Some(parse_switch(value)?),
+                    _ => return Err(format!("unknown option {}", option)),
+                }
+            }
+            let target = StripAddress {
+                mix: mix.ok_or("set needs --mix")?,
+                strip: strip.ok_or("set needs --strip")?,
+            };
+            if fader.is_none() && pan.is_none() && mute.is_none() && solo.is_none() {
+                return Err("set needs --fader, --pan, --mute or --solo".to_string());
+            }
+            Action::Set {
+                target,
+                fader,
+                pan,
+                mute,
+                solo,
+            }
+        }
+        "mute" => {
+            let mut mix = None;
+            let mut strip = None;
+            let mut switch = Switch::On;
+            while let Some(arg) = rest.next() {
+                match arg {
+                    "--mix" | "--strip" => {
+                        let value = rest
+                            .next()
+                            .ok_or_else(|| format!("missing value for {}", arg))?;
+                        if arg == "--mix" {
+                            mix = Some(parse_number(value, "mix")?);
+                        } else {
+                            strip = Some(parse_strip(value)?);
+                        }
+                    }
+                    _ => switch = parse_switch(arg)?,
+                }
+            }
+            let target = match (mix, strip) {
+                (Some(mix), Some(strip)) => Some(StripAddress { mix, strip }),
+                (None, None) => None,
+                _ => return Err("mute needs both --mix and --strip, or neither".to_string()),
+            };
+            Action::Mute { target, switch }
+        }
+        "phantom" => Action::Phantom(parse_switch(single(&mut rest, command)?)?),
+        "line" => Action::Line(parse_switch(single(&mut rest, command)?)?),
+        "mono" => Action::Mono(parse_switch(single(&mut rest, command)?)?),
+        "bypass" => match rest.next() {
+            None | Some("on") => Action::Bypass(true),
+            Some("off") => Action::Bypass(false),
+            Some(arg) => return Err(format!("expected on or off, got '{}'", arg)),
+        },
+        "scene" => match (rest.next(), rest.next()) {
+            (Some("save"), Some(name)) => Action::SceneSave(name.to_string()),
+            (Some("load"), Some(name)) => Action::SceneLoad(name.to_string()),
+            (Some("list"), None) => Action::SceneList,
+            _ => return Err("expected scene save|load NAME, or scene list".to_string()),
+        },
+        "devices" => Action::Devices,
+        "dump" => match rest.next() {
+            None => Action::Dump { json: false },
+            Some("--json") => Action::Dump { json: true },
+            Some(arg) => return Err(format!("unknown option {}", arg)),
+        },
+        "help" | "--help" | "-h" => Action::Help,
+        _ => return Err(format!("unknown command '{}'", command)),
+    };
+
+    match rest.next() {
+        Some(arg) => Err(format!("unexpected argument '{}'", arg)),
+        None => Ok(Some(action)),
+    }
+}
+
+fn single<'a>(args: &mut impl Iterator<Item = &'a str>, command: &str) -> Result<&'a str, String> {
+    args.next()
+        .ok_or_else(|| format!("{} needs on, off or toggle", command))
+}
+
+pub fn parse_switch(value: &str) -> Result<Switch, String> {
+    match value {
+        "on" => Ok(Switch::On),
+        "off" => Ok(Switch::Off),
+        "toggle" => Ok(Switch::Toggle),
+        _ => Err(format!("expected on, off or toggle, got '{}'", value)),
+    }
+}
+
+pub fn parse_number(value: &str, what: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        _ => Err(format!("invalid {} '{}'", what, value)),
+    }
+}
+
+fn parse_strip(value: &str) -> Result<Option<usize>, String> {
+    if value == "bus" {
+        Ok(None)
+    } else {
+        parse_number(value, "strip").map(Some)
+    }
+}
+
+/// Fader level in dB, with an optional `dB` suffix
+pub fn parse_fader(value: &str) -> Result<f64, String> {
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("invalid fader level '{}'", value))
+}
+
+/// Pan as a balance from -100 (left) to 100 (right),
+/// given as a number or as `L30`, `C`, `R30`
+pub fn parse_pan(value: &str) -> Result<f64, String> {
+    // The side gives the sign, `L-30` is not a pan
+    let amount = |v: &str| v.parse::<f64>().ok().filter(|_| !v.starts_with(['+', '-']));
+    let balance = match value.to_ascii_uppercase().as_str() {
+        "C" => Some(0.0),
+        v => {
+            if let Some(left) = v.strip_prefix('L') {
+                amount(left).map(|b| -b)
+            } else if let Some(right) = v.strip_prefix('R') {
+                amount(right)
+            } else {
+                v.parse::<f64>().ok()
+            }
+        }
+    };
+    balance
+        .filter(|b| (-100.0..=100.0).contains(b))
+        .ok_or_else(|| format!("invalid pan '{}'", value))
+}
+
+/// Run a one-shot command against the interface picked by `device`
+/// and its config in `paths`
+pub fn run(action: Action, paths: &Paths, device: Option<&str>) -> Result<(), Box<dyn Error>> {
+    match action {
+        Action::Help => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Action::SceneList => {
+            for name in scene::list(paths)? {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+        Action::Devices => {
+            for (i, entry) in device::list()?.iter().enumerate() {
+                let serial = device::display_serial(&entry.serial);
+                println!("{}  {}  {}", i + 1, serial, entry.product);
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let mut ps = PreSonusStudio1824c::new(device)?;
+    let mut paths = paths.clone();
+    paths.set_device(&ps.serial)?;
+    let paths = &paths;
+    let config_file = paths.config_file();
+    config::load_mixer(&mut ps, &config_file)?;
+    ps.poll_state();
+
+    match action {
+        Action::Set {
+            target,
+            fader,
+            pan,
+            mute,
+            solo,
+        } => {
+            let (mix_index, strip_index) = target.resolve(&ps)?;
+            let strip = ps.mixes[mix_index].strips.iter().nth(strip_index).unwrap();
+            let channel = strip.kind == StripKind::Channel;
+            if !channel && pan.is_some() {
+                return Err("bus strips have no pan".into());
+            }
+            if !channel && solo.is_some() {
+                return Err("bus strips have no solo".into());
+            }
+            if let Some(mute) = mute {
+                mute_strip(&mut ps, mix_index, strip_index, mute);
+            }
+            let mix = &mut ps.mixes[mix_index];
+            let strip = mix.strips.iter_mut().nth(strip_index).unwrap();
+            if let Some(fader) = fader {
+                strip.set_fader(fader);
+            }
+            if let Some(pan) = pan {
+                strip.balance = pan;
+            }
+            match solo {
+                Some(solo) => {
+                    // Solo changes the muting of every strip in the mix
+                    strip.solo = solo.apply(strip.solo);
+                    mix.sync_solo();
+                    write_mix(&mut ps, mix_index);
+                }
+                None => ps.write_channel_fader(mix_index, strip_index),
+            }
+            ps.config().save(&config_file)?;
+        }
+        Action::Mute {
+            target: Some(target),
+            switch,
+        } => {
+            let (mix_index, strip_index) = target.resolve(&ps)?;
+            mute_strip(&mut ps, mix_index, strip_index, switch);
+            ps.write_channel_fader(mix_index, strip_index);
+            ps.config().save(&config_file)?;
+        }
+        Action::Mute {
+            target: None,
+            switch,
+        } => ps.set_main_mute(switch.apply(ps.main_mute)),
+        Action::Phantom(switch) => ps.set_phantom_power(switch.apply(ps.phantom_power)),
+        Action::Line(switch) => ps.set_1_2_line(switch.apply(ps.in_1_2_line)),
+        Action::Mono(switch) => ps.set_main_mono(switch.apply(ps.main_mono)),
+        Action::Bypass(true) => ps.bypass_mixer(),
+        Action::Bypass(false) => ps.write_state(),
+        Action::SceneSave(name) => {
+            let path = scene::save(&ps, paths, &name)?;
+            println!("Saved scene {} to {}", name, path.display());
+        }
+        Action::SceneLoad(name) => {
+            scene::load(&mut ps, paths, &name)?;
+            ps.write_state();
+            ps.config().save(&config_file)?;
+        }
+        Action::Dump { json: true } => println!("{}", serde_json::to_string_pretty(&dump(&ps))?),
+        Action::Dump { json: false } => print_state(&ps),
+        Action::Help | Action::SceneList | Action::Devices => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Mute a strip like the TUI and remote clients do: the main bus with the hardware
+/// main mute, which isn't stored in the config, and the other strips in the mixer
+fn mute_strip(ps: &mut PreSonusStudio1824c, mix_index: usize, strip_index: usize, switch: Switch) {
+    let strip = ps.mixes[mix_index]
+        .strips
+        .iter_mut()
+        .nth(strip_index)
+        .unwrap();
+    if strip.kind == StripKind::Main {
+        ps.set_main_mute(switch.apply(ps.main_mute));
+    } else {
+        strip.mute = switch.apply(strip.mute);
+    }
+}
+
+fn write_mix(ps: &mut PreSonusStudio1824c, mix_index: usize) {
+    for strip_index in 0..=ps.mixes[mix_index].strips.channel_strips.len() {
+        ps.write_channel_fader(mix_index, strip_index);
+    }
+}
+
+fn dump(ps: &PreSonusStudio1824c) -> serde_json::Value {
+    let meter = |m: &crate::usb::Meter| json!({ "level": m.value, "clip": m.clip });
+    json!({
+        "phantom_power": ps.phantom_power,
+        "in_1_2_line": ps.in_1_2_line,
+        "main_mute": ps.main_mute,
+        "main_mono": ps.main_mono,
+        "config": ps.config(),
+        "meters": {
+            "channels": ps.channel_meters.iter().map(meter).collect::<Vec<_>>(),
+            "buses": ps.bus_meters.iter().map(meter).collect::<Vec<_>>(),
+        },
+    })
+}
+
+fn print_state(ps: &PreSonusStudio1824c) {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    println!("Phantom power:   {}", on_off(ps.phantom_power));
+    println!("Inputs 1-2 line: {}", on_off(ps.in_1_2_line));
+    println!("Main mute:       {}", on_off(ps.main_mute));
+    println!("Main mono:       {}", on_off(ps.main_mono));
+
+    for (m, mix) in ps.mixes.iter().enumerate() {
+        println!();
+        println!("{} {}", m + 1, mix.name);
+        for (s, strip) in mix.strips.iter().enumerate() {
+            let (number, name, pan) = match ps.channel_names.get(s) {
+                Some(name) => (
+                    (s + 1).to_string(),
+                    name.as_str(),
+                    format_pan(strip.balance),
+                ),
+                None => ("bus".to_string(), mix.name.as_str(), String::new()),
+            };
+            println!(
+                "  {:>3}  {:<12} {:>6.1} dB  {:<4} {}{}",
+                number,
+                name,
+                strip.fader,
+                pan,
+                if strip.solo { "solo " } else { "" },
+                if strip.mute { "mute" } else { "" },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Option<Action>, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn test_parse_no_command() {
+        assert_eq!(parse_args(""), Ok(None));
+        assert_eq!(parse_args("--config /tmp/baton"), Ok(None));
+        assert_eq!(parse_args("--config=/tmp/baton"), Ok(None));
+        assert_eq!(parse_args("--device SC1E20051014"), Ok(None));
+        assert_eq!(parse_args("--device=2 devices"), Ok(Some(Action::Devices)));
+    }
+
+    #[test]
+    fn test_parse_set() {
+        assert_eq!(
+            parse_args("--config /tmp/baton set --mix 2 --strip 5 --fader -6 --pan L30"),
+            Ok(Some(Action::Set {
+                target: StripAddress {
+                    mix: 2,
+                    strip: Some(5)
+                },
+                fader: Some(-6.0),
+                pan: Some(-30.0),
+                mute: None,
+                solo: None,
+            }))
+        );
+        assert_eq!(
+            parse_args("set --mix 1 --strip bus --mute toggle"),
+            Ok(Some(Action::Set {
+                target: StripAddress {
+                    mix: 1,
+                    strip: None
+                },
+                fader: None,
+                pan: None,
+                mute: Some(Switch::Toggle),
+                solo: None,
+            }))
+        );
+        assert!(parse_args("set --mix 2 --strip 5").is_err());
+        assert!(parse_args("set --mix 0 --strip 5 --fader 0").is_err());
+        assert!(parse_args("set --strip 5 --fader 0").is_err());
+        assert!(parse_args("set --mix 2 --strip 5 --fader").is_err());
+    }
+
+    #[test]
+    fn test_parse_switches() {
+        assert_eq!(
+            parse_args("mute"),
+            Ok(Some(Action::Mute {
+                target: None,
+                switch: Switch::On
+            }))
+        );
+        assert_eq!(
+            parse_args("mute off --mix 3 --strip 1"),
+            Ok(Some(Action::Mute {
+                target: Some(StripAddress {
+                    mix: 3,
+                    strip: Some(1)
+                }),
+                switch: Switch::Off
+            }))
+        );
+        assert!(parse_args("mute --mix 3").is_err());
+        assert_eq!(
+            parse_args("phantom on"),
+            Ok(Some(Action::Phantom(Switch::On)))
+        );
+        assert!(parse_args("phantom").is_err());
+        assert!(parse_args("phantom on off").is_err());
+        assert_eq!(parse_args("bypass"), Ok(Some(Action::Bypass(true))));
+        assert_eq!(parse_args("bypass off"), Ok(Some(Action::Bypass(false))));
+    }
+
+    #[test]
+    fn test_parse_scene_and_dump() {
+        assert_eq!(
+            parse_args("scene load live"),
+            Ok(Some(Action::SceneLoad("live".to_string())))
+        );
+        assert_eq!(parse_args("scene list"), Ok(Some(Action::SceneList)));
+        assert!(parse_args("scene load").is_err());
+        assert_eq!(
+            parse_args("dump --json"),
+            Ok(Some(Action::Dump { json: true }))
+        );
+        assert!(parse_args("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_pan() {
+        assert_eq!(parse_pan("L30"), Ok(-30.0));
+        assert_eq!(parse_pan("r100"), Ok(100.0));
+        assert_eq!(parse_pan("C"), Ok(0.0));
+        assert_eq!(parse_pan("-45"), Ok(-45.0));
+        assert!(parse_pan("L101").is_err());
+        assert!(parse_pan("L-30").is_err());
+        assert!(parse_pan("R+30").is_err());
+        assert!(parse_pan("X").is_err());
+        assert_eq!(format_pan(-30.0), "L30");
+        assert_eq!(format_pan(0.2), "C");
+        assert_eq!(format_pan(100.0), "R100");
+    }
+
+    #[test]
+    fn test_fader() {
+        assert_eq!(parse_fader("-6"), Ok(-6.0));
+        assert_eq!(parse_fader("-6dB"), Ok(-6.0));
+        assert!(parse_fader("loud").is_err());
+        assert!(parse_fader("inf").is_err());
+    }
+
+    #[test]
+    fn test_mute_main_bus() {
+        let mut ps = PreSonusStudio1824c::offline(None);
+        let main = StripAddress {
+            mix: 1,
+            strip: None,
+        }
+        .resolve(&ps)
+        .unwrap();
+        mute_strip(&mut ps, main.0, main.1, Switch::On);
+        assert!(!ps.mixes[0].strips.bus_strip.mute);
+
+        let bus = StripAddress {
+            mix: 2,
+            strip: None,
+        }
+        .resolve(&ps)
+        .unwrap();
+        mute_strip(&mut ps, bus.0, bus.1, Switch::On);
+        assert!(ps.mixes[1].strips.bus_strip.mute);
+
+        let channel = StripAddress {
+            mix: 1,
+            strip: Some(3),
+        }
+        .resolve(&ps)
+        .unwrap();
+        mute_strip(&mut ps, channel.0, channel.1, Switch::Toggle);
+        assert!(ps.mixes[0].strips.channel_strips[2].mute);
+    }
+}
@@ -0,0 +1,497 @@
+use crate::cli::{self, Switch, parse_number, parse_switch};
+use crate::midi_control::StripControl;
+
+/// Built-in commands, for completion
+const COMMANDS: &[&str] = &[
+    "arm", "clear", "clips", "copy", "fader", "map", "mix", "mute", "pan", "quit", "rename",
+    "scene", "set", "solo",
+];
+
+/// Strips of the active mix a command applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    /// The active strip
+    Active,
+    /// Strips `first` to `last`, numbered from 1
+    Strips(usize, usize),
+    /// All channel strips
+    All,
+    /// The bus strip
+    Bus,
+}
+
+impl Range {
+    /// Strip indexes of the range, with the bus strip after the channel strips
+    pub fn indexes(&self, active: usize, channels: usize) -> Result<Vec<usize>, String> {
+        match *self {
+            Range::Active => Ok(vec![active]),
+            Range::Strips(first, last) if last <= channels + 1 => Ok((first - 1..last).collect()),
+            Range::Strips(..) => Err(format!("strips are numbered 1 to {}", channels + 1)),
+            Range::All => Ok((0..channels).collect()),
+            Range::Bus => Ok(vec![channels]),
+        }
+    }
+
+    /// Index of the single strip of the range
+    pub fn index(&self, active: usize, channels: usize) -> Result<usize, String> {
+        match self.indexes(active, channels)?[..] {
+            [index] => Ok(index),
+            _ => Err("expected a single strip".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Mute(Switch),
+    Solo(Switch),
+    Fader(f64),
+    Pan(f64),
+    /// Select a mix, numbered from 1
+    Mix(usize),
+    Rename(String),
+    SceneSave(String),
+    SceneLoad(String),
+    /// Copy the channel strips of a mix to another, numbered from 1
+    CopyMix {
+        from: usize,
+        to: usize,
+    },
+    /// Map a control of a strip to a MIDI CC, channel numbered from 0
+    Map {
+        control: StripControl,
+        channel: u8,
+        cc: u8,
+    },
+    SetPanLaw(f64),
+    /// Clear the clip counters and peaks of the meters
+    ClearMeters,
+    /// Show the clip counts of the meters
+    Clips,
+    /// Arm or disarm the silence alerts
+    Arm(Switch),
+    Quit,
+    /// Any other command, possibly registered by the script
+    Other {
+        name: String,
+        args: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandLine {
+    pub range: Range,
+    pub command: Command,
+}
+
+/// Parse a command line like `:3-8 mute`.
+/// Returns None for an empty line.
+pub fn parse(line: &str) -> Result<Option<CommandLine>, String> {
+    let (range, rest) = split_range(line);
+    let range = parse_range(range)?;
+    let mut words = rest.split_whitespace();
+    let Some(name) = words.next() else {
+        return match range {
+            Range::Active => Ok(None),
+            _ => Err("missing command".to_string()),
+        };
+    };
+    let args: Vec<&str> = words.collect();
+
+    let command = match (name, &args[..]) {
+        ("mute", []) => Command::Mute(Switch::Toggle),
+        ("mute", [switch]) => Command::Mute(parse_switch(switch)?),
+        ("solo", []) => Command::Solo(Switch::Toggle),
+        ("solo", [switch]) => Command::Solo(parse_switch(switch)?),
+        ("fader", [level]) => Command::Fader(cli::parse_fader(level)?),
+        ("pan", [pan]) => Command::Pan(cli::parse_pan(pan)?),
+        ("mix", [mix]) => Command::Mix(parse_number(mix, "mix")?),
+        ("rename", [_, ..]) => {
+            // Keep the spacing of the name
+            let name = rest.trim_start().strip_prefix("rename").unwrap_or_default();
+            Command::Rename(name.trim().to_string())
+        }
+        ("scene", ["save", name]) => Command::SceneSave(name.to_string()),
+        ("scene", ["load", name]) => Command::SceneLoad(name.to_string()),
+        ("copy", ["mix", from, "to", to]) => Command::CopyMix {
+            from: parse_number(from, "mix")?,
+            to: parse_number(to, "mix")?,
+        },
+        ("map", [control, "cc", cc, options @ ..]) => {
+            let channel = match options {
+                [] => 0,
+                ["ch", channel] => parse_midi(channel, "channel", 1..=16)? - 1,
+                _ => return Err("expected map CONTROL cc N [ch N]".to_string()),
+            };
+            Command::Map {
+                control: parse_control(control)?,
+                channel,
+                cc: parse_midi(cc, "cc", 0..=127)?,
+            }
+        }
+        ("set", ["pan-law", db]) => Command::SetPanLaw(cli::parse_fader(db)?),
+        ("set", [option, ..]) if *option != "pan-law" => {
+            return Err(format!("unknown option '{}'", option));
+        }
+        ("clear", []) => Command::ClearMeters,
+        ("clips", []) => Command::Clips,
+        ("arm", []) => Command::Arm(Switch::Toggle),
+        ("arm", [switch]) => Command::Arm(parse_switch(switch)?),
+        ("q" | "quit", []) => Command::Quit,
+        ("mute" | "solo" | "arm", _) => {
+            return Err(format!("expected {} [on|off|toggle]", name));
+        }
+        ("fader", _) => return Err("expected fader DB".to_string()),
+        ("pan", _) => return Err("expected pan L30, C, R30 or -100 to 100".to_string()),
+        ("mix", _) => return Err("expected mix N".to_string()),
+        ("rename", _) => return Err("expected rename NAME".to_string()),
+        ("scene", _) => return Err("expected scene save|load NAME".to_string()),
+        ("copy", _) => return Err("expected copy mix N to M".to_string()),
+        ("map", _) => return Err("expected map CONTROL cc N [ch N]".to_string()),
+        ("set", _) => return Err("expected set pan-law DB".to_string()),
+        ("clear" | "clips", _) => return Err(format!("{} takes no arguments", name)),
+        _ => Command::Other {
+            name: name.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        },
+    };
+
+    let takes_range = matches!(
+        command,
+        Command::Mute(_)
+            | Command::Solo(_)
+            | Command::Fader(_)
+            | Command::Pan(_)
+            | Command::Rename(_)
+            | Command::Map { .. }
+            | Command::ClearMeters
+            | Command::Clips
+    );
+    if range != Range::Active && !takes_range {
+        return Err(format!("{} doesn't take a range", name));
+    }
+
+    Ok(Some(CommandLine { range, command }))
+}
+
+/// Split the leading range, like `3-8` or `%`, from the command
+fn split_range(line: &str) -> (&str, &str) {
+    let line = line.trim_start().trim_start_matches(':').trim_start();
+    if let Some(rest) = line.strip_prefix("bus")
+        && (rest.is_empty() || rest.starts_with(' '))
+    {
+        return ("bus", rest);
+    }
+    let end = line
+        .find(|c: char| !(c.is_ascii_digit() || c == '-' || c == '%'))
+        .unwrap_or(line.len());
+    line.split_at(end)
+}
+
+fn parse_range(range: &str) -> Result<Range, String> {
+    let invalid = || format!("invalid range '{}'", range);
+    match range {
+        "" => Ok(Range::Active),
+        "%" => Ok(Range::All),
+        "bus" => Ok(Range::Bus),
+        _ => {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let first = parse_number(first, "strip").map_err(|_| invalid())?;
+            let last = parse_number(last, "strip").map_err(|_| invalid())?;
+            if first > last {
+                return Err(invalid());
+            }
+            Ok(Range::Strips(first, last))
+        }
+    }
+}
+
+fn parse_midi(value: &str, what: &str, range: std::ops::RangeInclusive<u8>) -> Result<u8, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|v| range.contains(v))
+        .ok_or_else(|| {
+            format!(
+                "{} must be {} to {}, got '{}'",
+                what,
+                range.start(),
+                range.end(),
+                value
+            )
+        })
+}
+
+fn parse_control(value: &str) -> Result<StripControl, String> {
+    match value {
+        "fader" => Ok(StripControl::Fader),
+        "pan" | "balance" => Ok(StripControl::Balance),
+        "mute" => Ok(StripControl::Mute),
+        "solo" => Ok(StripControl::Solo),
+        _ => Err(format!(
+            "expected fader, pan, mute or solo, got '{}'",
+            value
+        )),
+    }
+}
+
+/// Short text of a time span, like `42s`, `3m05s` or `1h20m`
+pub fn format_age(age: std::time::Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
+
+/// Result of completing a command line
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    pub line: String,
+    /// All matches if the completion is ambiguous
+    pub candidates: Vec<String>,
+}
+
+/// Complete the last word of a command line.
+/// `scenes` and `script_commands` are offered where they apply.
+pub fn complete(line: &str, scenes: &[String], script_commands: &[String]) -> Completion {
+    let (_, rest) = split_range(line);
+    let mut words: Vec<&str> = rest.split_whitespace().collect();
+    let partial = if rest.is_empty() || rest.ends_with(' ') {
+        ""
+    } else {
+        words.pop().unwrap_or_default()
+    };
+
+    let options: Vec<&str> = match words[..] {
+        [] => COMMANDS
+            .iter()
+            .copied()
+            .chain(script_commands.iter().map(String::as_str))
+            .collect(),
+        ["mute" | "solo" | "arm"] => vec!["on", "off", "toggle"],
+        ["scene"] => vec!["load", "save"],
+        ["scene", _] => scenes.iter().map(String::as_str).collect(),
+        ["copy"] => vec!["mix"],
+        ["copy", "mix", _] => vec!["to"],
+        ["map"] => vec!["fader", "mute", "pan", "solo"],
+        ["map", _] => vec!["cc"],
+        ["set"] => vec!["pan-law"],
+        _ => Vec::new(),
+    };
+
+    let candidates: Vec<String> = options
+        .into_iter()
+        .filter(|o| o.starts_with(partial))
+        .map(String::from)
+        .collect();
+    let stem = &line[..line.len() - partial.len()];
+    match &candidates[..] {
+        [] => Completion {
+            line: line.to_string(),
+            candidates,
+        },
+        [only] => Completion {
+            line: format!("{}{} ", stem, only),
+            candidates: Vec::new(),
+        },
+        [first, others @ ..] => {
+            let mut prefix = first.as_str();
+            for other in others {
+                let common: usize = prefix
+                    .chars()
+                    .zip(other.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum();
+                prefix = &prefix[..common];
+            }
+            Completion {
+                line: format!("{}{}", stem, prefix),
+                candidates,
+            }
+        }
+    }
+}
+
+/// Previously executed command lines, browsed with Up and Down
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// Entry being shown, None when editing a new line
+    position: Option<usize>,
+}
+
+impl History {
+    const MAX_ENTRIES: usize = 100;
+
+    pub fn push(&mut self, line: &str) {
+        self.position = None;
+        if parse(line).is_ok_and(|l| l.is_none()) || self.entries.last().is_some_and(|l| l == line)
+        {
+            return;
+        }
+        if self.entries.len() == Self::MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.entries.push(line.to_string());
+    }
+
+    /// The entry before the one shown
+    pub fn previous(&mut self) -> Option<&str> {
+        let position = match self.position {
+            None => self.entries.len().checked_sub(1)?,
+            Some(p) => p.saturating_sub(1),
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// The entry after the one shown, None when past the newest entry
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.position = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> CommandLine {
+        parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(command(":fader -12").command, Command::Fader(-12.0));
+        assert_eq!(command(":pan L30").command, Command::Pan(-30.0));
+        assert_eq!(command(":mix 3").command, Command::Mix(3));
+        assert_eq!(
+            command(":rename  Kick  In").command,
+            Command::Rename("Kick  In".to_string())
+        );
+        assert_eq!(
+            command(":scene save verse").command,
+            Command::SceneSave("verse".to_string())
+        );
+        assert_eq!(
+            command(":copy mix 1 to 4").command,
+            Command::CopyMix { from: 1, to: 4 }
+        );
+        assert_eq!(
+            command(":map fader cc 7 ch 2").command,
+            Command::Map {
+                control: StripControl::Fader,
+                channel: 1,
+                cc: 7
+            }
+        );
+        assert_eq!(command(":set pan-law -3").command, Command::SetPanLaw(-3.0));
+        assert_eq!(command(":mute").command, Command::Mute(Switch::Toggle));
+        assert_eq!(command(":arm on").command, Command::Arm(Switch::On));
+        assert_eq!(
+            command(":dim -20").command,
+            Command::Other {
+                name: "dim".to_string(),
+                args: vec!["-20".to_string()]
+            }
+        );
+        assert_eq!(parse(":"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(command(":3-8 mute").range, Range::Strips(3, 8));
+        assert_eq!(command(":5solo on").range, Range::Strips(5, 5));
+        assert_eq!(command(":% fader 0").range, Range::All);
+        assert_eq!(command(":bus mute").range, Range::Bus);
+        assert_eq!(command(":% clear").command, Command::ClearMeters);
+        assert_eq!(command(":1-4 clips").range, Range::Strips(1, 4));
+        assert!(parse(":8-3 mute").is_err());
+        assert!(parse(":3-8 mix 2").is_err());
+        assert!(parse(":3-8").is_err());
+
+        assert_eq!(Range::Strips(3, 5).indexes(0, 8), Ok(vec![2, 3, 4]));
+        assert_eq!(Range::Bus.indexes(0, 8), Ok(vec![8]));
+        assert!(Range::Strips(3, 10).indexes(0, 8).is_err());
+        assert!(Range::All.index(0, 8).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(":fader loud").is_err());
+        assert!(parse(":pan L200").is_err());
+        assert!(parse(":map fader cc 128").is_err());
+        assert!(parse(":map volume cc 7").is_err());
+        assert!(parse(":set colour red").is_err());
+        assert!(parse(":copy mix 1").is_err());
+        assert!(parse(":clear all").is_err());
+    }
+
+    #[test]
+    fn test_complete() {
+        let scenes = vec!["intro".to_string(), "verse".to_string()];
+        let script = vec!["dim".to_string()];
+        let complete = |line: &str| complete(line, &scenes, &script);
+
+        assert_eq!(complete(":fa").line, ":fader ");
+        assert_eq!(complete(":3-8 mu").line, ":3-8 mute ");
+        assert_eq!(complete(":di").line, ":dim ");
+        assert_eq!(complete(":cle").line, ":clear ");
+        assert_eq!(complete(":scene load v").line, ":scene load verse ");
+
+        let ambiguous = complete(":s");
+        assert_eq!(ambiguous.line, ":s");
+        assert_eq!(ambiguous.candidates, vec!["scene", "set", "solo"]);
+        let ambiguous = complete(":m");
+        assert_eq!(ambiguous.line, ":m");
+        assert_eq!(ambiguous.candidates, vec!["map", "mix", "mute"]);
+        assert_eq!(complete(":mute o").line, ":mute o");
+        assert_eq!(complete(":fader -1").line, ":fader -1");
+
+        let scenes = vec!["café-1".to_string(), "café-2".to_string()];
+        let ambiguous = super::complete(":scene load c", &scenes, &script);
+        assert_eq!(ambiguous.line, ":scene load café-");
+        let scenes = vec!["ébauche".to_string(), "écho".to_string()];
+        let ambiguous = super::complete(":scene load ", &scenes, &script);
+        assert_eq!(ambiguous.line, ":scene load é");
+    }
+
+    #[test]
+    fn test_format_age() {
+        use std::time::Duration;
+        assert_eq!(format_age(Duration::from_millis(42_500)), "42s");
+        assert_eq!(format_age(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_age(Duration::from_secs(4800)), "1h20m");
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+        assert_eq!(history.previous(), None);
+
+        history.push(":mute");
+        history.push(":fader -6");
+        history.push(":fader -6");
+        history.push(":");
+
+        assert_eq!(history.previous(), Some(":fader -6"));
+        assert_eq!(history.previous(), Some(":mute"));
+        assert_eq!(history.previous(), Some(":mute"));
+        assert_eq!(history.next(), Some(":fader -6"));
+        assert_eq!(history.next(), None);
+        assert_eq!(history.previous(), Some(":fader -6"));
+    }
+}
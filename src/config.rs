@@ -0,0 +1,749 @@
+use crate::access::Permissions;
+use crate::dbus::DbusSettings;
+use crate::midi_control::MidiMapping;
+use crate::usb::{Mix, PAN_LAW_RANGE, PreSonusStudio1824c, StripColor};
+use crate::web::WebSettings;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.json";
+const MIDI_MAPPING_FILE: &str = "midi_mapping.json";
+const METERING_FILE: &str = "metering.json";
+const METRICS_FILE: &str = "metrics.json";
+const WEB_FILE: &str = "web.json";
+const DBUS_FILE: &str = "dbus.json";
+const PORTS_FILE: &str = "ports.json";
+
+/// Files written by older releases of the TUI, relative to the home directory
+const LEGACY_CONFIG_FILE: &str = ".baton.json";
+const LEGACY_MIDI_MAPPING_FILE: &str = ".baton_midi_mapping.json";
+
+/// Current version of the config file format.
+/// Version 0 is the unversioned format written by older releases.
+pub const CONFIG_VERSION: u64 = 3;
+
+/// Persisted mixer state
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub version: u64,
+    pub channel_names: Vec<String>,
+    pub channel_colors: Vec<Option<StripColor>>,
+    /// Level of a centred channel in dB
+    pub pan_law: f64,
+    pub mixes: Vec<Mix>,
+}
+
+/// A single invalid field in a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// Location of the field, e.g. `mixes[2].strips.channel_strips[5].fader`
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    UnsupportedVersion(u64),
+    Invalid(Vec<FieldError>),
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse(e) => write!(f, "parse error: {}", e),
+            ConfigError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported config version {} (newest supported is {})",
+                v, CONFIG_VERSION
+            ),
+            ConfigError::Invalid(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "invalid config: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl Config {
+    pub fn new(
+        channel_names: Vec<String>,
+        channel_colors: Vec<Option<StripColor>>,
+        pan_law: f64,
+        mixes: Vec<Mix>,
+    ) -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            channel_names,
+            channel_colors,
+            pan_law,
+            mixes,
+        }
+    }
+
+    /// Parse a config file, migrating older formats to the current version
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let value = serde_json::from_str::<Value>(contents)?;
+        let value = migrate(value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Check the config against the shape and ranges of the device mixes
+    pub fn validate(&self, reference: &[Mix], channel_count: usize) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.channel_names.len() != channel_count {
+            errors.push(FieldError {
+                path: "channel_names".to_string(),
+                message: format!(
+                    "expected {} names, found {}",
+                    channel_count,
+                    self.channel_names.len()
+                ),
+            });
+        }
+
+        if self.channel_colors.len() != channel_count {
+            errors.push(FieldError {
+                path: "channel_colors".to_string(),
+                message: format!(
+                    "expected {} colors, found {}",
+                    channel_count,
+                    self.channel_colors.len()
+                ),
+            });
+        }
+
+        if !PAN_LAW_RANGE.contains(&self.pan_law) {
+            errors.push(FieldError {
+                path: "pan_law".to_string(),
+                message: format!(
+                    "{} is outside {}..{}",
+                    self.pan_law,
+                    PAN_LAW_RANGE.start(),
+                    PAN_LAW_RANGE.end()
+                ),
+            });
+        }
+
+        if self.mixes.len() != reference.len() {
+            errors.push(FieldError {
+                path: "mixes".to_string(),
+                message: format!(
+                    "expected {} mixes, found {}",
+                    reference.len(),
+                    self.mixes.len()
+                ),
+            });
+        }
+
+        for (i, (mix, reference_mix)) in self.mixes.iter().zip(reference).enumerate() {
+            let strips = &mix.strips.channel_strips;
+            let reference_strips = &reference_mix.strips.channel_strips;
+            if strips.len() != reference_strips.len() {
+                errors.push(FieldError {
+                    path: format!("mixes[{}].strips.channel_strips", i),
+                    message: format!(
+                        "expected {} strips, found {}",
+                        reference_strips.len(),
+                        strips.len()
+                    ),
+                });
+            }
+
+            for (j, (strip, reference_strip)) in strips.iter().zip(reference_strips).enumerate() {
+                let path = format!("mixes[{}].strips.channel_strips[{}]", i, j);
+                check_range(
+                    &mut errors,
+                    &path,
+                    "fader",
+                    strip.fader,
+                    reference_strip.min,
+                    reference_strip.max,
+                );
+                check_range(&mut errors, &path, "balance", strip.balance, -100.0, 100.0);
+            }
+
+            let reference_bus = &reference_mix.strips.bus_strip;
+            check_range(
+                &mut errors,
+                &format!("mixes[{}].strips.bus_strip", i),
+                "fader",
+                mix.strips.bus_strip.fader,
+                reference_bus.min,
+                reference_bus.max,
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    /// Write the config file atomically
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let serialized = serde_json::to_string_pretty(self)?;
+        write_atomic(path, serialized.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn check_range(
+    errors: &mut Vec<FieldError>,
+    path: &str,
+    field: &str,
+    value: f64,
+    min: f64,
+    max: f64,
+) {
+    if !value.is_finite() || value < min || value > max {
+        errors.push(FieldError {
+            path: format!("{}.{}", path, field),
+            message: format!("{} is outside {}..{}", value, min, max),
+        });
+    }
+}
+
+/// Upgrade a parsed config file to the current version, one version at a time
+fn migrate(mut value: Value) -> Result<Value, ConfigError> {
+    let object = value.as_object_mut().ok_or_else(|| {
+        ConfigError::Invalid(vec![FieldError {
+            path: String::new(),
+            message: "expected a JSON object".to_string(),
+        }])
+    })?;
+
+    loop {
+        let version = match object.get("version") {
+            None => 0,
+            Some(v) => v.as_u64().ok_or_else(|| {
+                ConfigError::Invalid(vec![FieldError {
+                    path: "version".to_string(),
+                    message: format!("expected an unsigned integer, found {}", v),
+                }])
+            })?,
+        };
+
+        match version {
+            CONFIG_VERSION => break,
+            0 => {
+                // Unversioned files already have the version 1 layout
+            }
+            1 => {
+                // Version 2 adds strip colours
+                let channel_count = object
+                    .get("channel_names")
+                    .and_then(Value::as_array)
+                    .map_or(0, Vec::len);
+                object.insert(
+                    "channel_colors".to_string(),
+                    Value::Array(vec![Value::Null; channel_count]),
+                );
+                if let Some(mixes) = object.get_mut("mixes").and_then(Value::as_array_mut) {
+                    for mix in mixes.iter_mut().filter_map(Value::as_object_mut) {
+                        mix.insert("color".to_string(), Value::Null);
+                    }
+                }
+            }
+            2 => {
+                // Version 3 adds the pan law, previously fixed at 0 dB
+                object.insert("pan_law".to_string(), Value::from(0.0));
+            }
+            v => return Err(ConfigError::UnsupportedVersion(v)),
+        }
+
+        log::info!(
+            "Migrating config from version {} to {}",
+            version,
+            version + 1
+        );
+        object.insert("version".to_string(), Value::from(version + 1));
+    }
+
+    Ok(value)
+}
+
+/// Write a file by writing a temporary file next to it and renaming it into place,
+/// so that an interrupted save never leaves a truncated file behind.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
+
+/// Move a config file that failed to load out of the way,
+/// so that it is not overwritten on exit.
+pub fn backup_invalid(path: &Path) -> io::Result<PathBuf> {
+    let mut backup_name = path.as_os_str().to_os_string();
+    backup_name.push(".invalid");
+    let backup_path = PathBuf::from(backup_name);
+    fs::rename(path, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Location of the files shared by the TUI and the GUI
+#[derive(Clone)]
+pub struct Paths {
+    dir: PathBuf,
+    overridden: bool,
+    /// Directory of the mixer config and MIDI mapping of the open interface
+    device_dir: Option<PathBuf>,
+}
+
+impl Paths {
+    /// Use `dir` if given, otherwise `$XDG_CONFIG_HOME/baton`
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        match dir {
+            Some(dir) => Paths {
+                dir,
+                overridden: true,
+                device_dir: None,
+            },
+            None => Paths {
+                dir: dirs::config_dir()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join("baton"),
+                overridden: false,
+                device_dir: None,
+            },
+        }
+    }
+
+    /// Paths from the `--config DIR` command line option
+    pub fn from_args() -> Self {
+        Self::new(config_arg(std::env::args().skip(1)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.device_dir().join(CONFIG_FILE)
+    }
+
+    pub fn midi_mapping_file(&self) -> PathBuf {
+        self.device_dir().join(MIDI_MAPPING_FILE)
+    }
+
+    fn device_dir(&self) -> &Path {
+        self.device_dir.as_deref().unwrap_or(&self.dir)
+    }
+
+    /// Keep the mixer config and MIDI mapping of the interface with `serial`
+    /// in `devices/SERIAL`. The shared files are copied there on first use.
+    /// An interface without a serial number keeps using the shared files.
+    pub fn set_device(&mut self, serial: &str) -> io::Result<()> {
+        if serial.is_empty() {
+            return Ok(());
+        }
+        let dir = self
+            .dir
+            .join("devices")
+            .join(serial.replace(['/', '\\'], "_"));
+        fs::create_dir_all(&dir)?;
+        for file in [CONFIG_FILE, MIDI_MAPPING_FILE] {
+            let (shared, path) = (self.dir.join(file), dir.join(file));
+            if shared.exists() && !path.exists() {
+                log::info!("Copying {} to {}", shared.display(), path.display());
+                fs::copy(&shared, &path)?;
+            }
+        }
+        self.device_dir = Some(dir);
+        Ok(())
+    }
+
+    pub fn metering_file(&self) -> PathBuf {
+        self.dir.join(METERING_FILE)
+    }
+
+    pub fn metrics_file(&self) -> PathBuf {
+        self.dir.join(METRICS_FILE)
+    }
+
+    pub fn web_file(&self) -> PathBuf {
+        self.dir.join(WEB_FILE)
+    }
+
+    pub fn dbus_file(&self) -> PathBuf {
+        self.dir.join(DBUS_FILE)
+    }
+
+    pub fn ports_file(&self) -> PathBuf {
+        self.dir.join(PORTS_FILE)
+    }
+
+    /// Create the config directory and move legacy dotfiles into it
+    pub fn init(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        if self.overridden {
+            return Ok(());
+        }
+
+        if let Some(home) = dirs::home_dir() {
+            migrate_legacy_file(&home.join(LEGACY_CONFIG_FILE), &self.config_file())?;
+            migrate_legacy_file(
+                &home.join(LEGACY_MIDI_MAPPING_FILE),
+                &self.midi_mapping_file(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Value of the `--config DIR` or `--config=DIR` option
+pub fn config_arg(args: impl Iterator<Item = String>) -> Option<PathBuf> {
+    option_arg(args, "--config").map(PathBuf::from)
+}
+
+/// Value of the `--device SERIAL` or `--device N` option, see `device::select`
+pub fn device_arg(args: impl Iterator<Item = String>) -> Option<String> {
+    option_arg(args, "--device")
+}
+
+fn option_arg(mut args: impl Iterator<Item = String>, name: &str) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+fn migrate_legacy_file(legacy: &Path, path: &Path) -> io::Result<()> {
+    if !legacy.exists() || path.exists() {
+        return Ok(());
+    }
+
+    log::info!("Migrating {} to {}", legacy.display(), path.display());
+    if fs::rename(legacy, path).is_err() {
+        // Rename fails across file systems
+        fs::copy(legacy, path)?;
+        fs::remove_file(legacy)?;
+    }
+    Ok(())
+}
+
+/// Load the mixer config into the device state.
+/// Returns false if there is no config file yet.
+/// An invalid file is moved out of the way so that it is not overwritten on exit.
+pub fn load_mixer(ps: &mut PreSonusStudio1824c, path: &Path) -> Result<bool, ConfigError> {
+    let serialized = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        result => result?,
+    };
+
+    if let Err(e) = ps.load_config(&serialized) {
+        match backup_invalid(path) {
+            Ok(backup) => log::warn!("Moved invalid config to {}", backup.display()),
+            Err(e) => log::error!("Failed to back up invalid config: {}", e),
+        }
+        return Err(e);
+    }
+
+    Ok(true)
+}
+
+/// Load a JSON settings file, None if it does not exist.
+/// `validate` returns the fields that don't fit the interface.
+pub fn load_json<T: DeserializeOwned>(
+    path: &Path,
+    validate: impl FnOnce(&T) -> Vec<FieldError>,
+) -> Result<Option<T>, ConfigError> {
+    let value: T = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+        Ok(contents) => serde_json::from_str(&contents)?,
+    };
+    let errors = validate(&value);
+    if !errors.is_empty() {
+        return Err(ConfigError::Invalid(errors));
+    }
+    Ok(Some(value))
+}
+
+/// Mixes of the D-Bus permissions that the interface doesn't have
+pub fn dbus_errors(settings: &DbusSettings, mixes: usize) -> Vec<FieldError> {
+    mix_errors(String::new(), &settings.permissions, mixes).collect()
+}
+
+/// Mixes of the web clients that the interface doesn't have
+pub fn web_errors(settings: &WebSettings, mixes: usize) -> Vec<FieldError> {
+    settings
+        .clients
+        .iter()
+        .enumerate()
+        .flat_map(|(i, client)| mix_errors(format!("clients[{}].", i), &client.permissions, mixes))
+        .collect()
+}
+
+/// Mix numbers of `permissions` that aren't mixes of the interface,
+/// at `prefix` in the settings file
+fn mix_errors(
+    prefix: String,
+    permissions: &Permissions,
+    mixes: usize,
+) -> impl Iterator<Item = FieldError> {
+    let numbers = permissions.mixes.clone().unwrap_or_default();
+    numbers
+        .into_iter()
+        .enumerate()
+        .filter(move |(_, number)| *number == 0 || *number > mixes)
+        .map(move |(j, _)| FieldError {
+            path: format!("{}mixes[{}]", prefix, j),
+            message: format!("mixes are numbered 1 to {}", mixes),
+        })
+}
+
+pub fn save_midi_mapping(mapping: &mut MidiMapping, path: &Path) -> Result<(), ConfigError> {
+    mapping.sort_mappings();
+    let json = serde_json::to_string_pretty(mapping)?;
+    write_atomic(path, json.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::StripKind;
+
+    fn reference() -> Vec<Mix> {
+        vec![
+            Mix::new(String::from("MAIN 1-2"), StripKind::Main, 0, 2),
+            Mix::new(String::from("MIX 3-4"), StripKind::Bus, 1, 2),
+        ]
+    }
+
+    fn config() -> Config {
+        Config::new(
+            vec!["In 1".to_string(), "In 2".to_string()],
+            vec![Some(StripColor::new(0x00, 0x17, 0x07)), None],
+            -3.0,
+            reference(),
+        )
+    }
+
+    #[test]
+    fn test_parse_unversioned() {
+        let mut json = serde_json::to_value(config()).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+
+        let parsed = Config::parse(&json.to_string()).unwrap();
+        assert_eq!(parsed.version, CONFIG_VERSION);
+        assert_eq!(parsed.mixes.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_version_1() {
+        let mut json = serde_json::to_value(config()).unwrap();
+        json["version"] = Value::from(1);
+        json.as_object_mut().unwrap().remove("channel_colors");
+        json.as_object_mut().unwrap().remove("pan_law");
+        json["mixes"][0].as_object_mut().unwrap().remove("color");
+
+        let parsed = Config::parse(&json.to_string()).unwrap();
+        assert_eq!(parsed.version, CONFIG_VERSION);
+        assert_eq!(parsed.channel_colors, vec![None, None]);
+        assert_eq!(parsed.pan_law, 0.0);
+        assert_eq!(parsed.mixes[0].color, None);
+        assert!(parsed.validate(&reference(), 2).is_ok());
+    }
+
+    #[test]
+    fn test_parse_unsupported_version() {
+        let mut json = serde_json::to_value(config()).unwrap();
+        json["version"] = Value::from(CONFIG_VERSION + 1);
+
+        match Config::parse(&json.to_string()) {
+            Err(ConfigError::UnsupportedVersion(v)) => assert_eq!(v, CONFIG_VERSION + 1),
+            _ => panic!("Expected UnsupportedVersion"),
+        }
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let json = serde_json::to_string(&config()).unwrap();
+        let truncated = &json[..json.len() / 2];
+
+        assert!(matches!(
+            Config::parse(truncated),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        assert!(config().validate(&reference(), 2).is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_fields() {
+        let mut config = config();
+        config.mixes[1].strips.channel_strips[0].fader = 42.0;
+        config.mixes[0].strips.channel_strips[1].balance = f64::NAN;
+        config.mixes[1].strips.channel_strips.pop();
+        config.channel_names.pop();
+        config.channel_colors.pop();
+        config.pan_law = -12.0;
+
+        let errors = match config.validate(&reference(), 2) {
+            Err(ConfigError::Invalid(errors)) => errors,
+            _ => panic!("Expected Invalid"),
+        };
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "channel_names",
+                "channel_colors",
+                "pan_law",
+                "mixes[0].strips.channel_strips[1].balance",
+                "mixes[1].strips.channel_strips",
+                "mixes[1].strips.channel_strips[0].fader",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_arg() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(config_arg(args(&[]).into_iter()), None);
+        assert_eq!(
+            config_arg(args(&["--config", "/tmp/baton"]).into_iter()),
+            Some(PathBuf::from("/tmp/baton"))
+        );
+        assert_eq!(
+            config_arg(args(&["--config=/tmp/baton"]).into_iter()),
+            Some(PathBuf::from("/tmp/baton"))
+        );
+        assert_eq!(config_arg(args(&["--config"]).into_iter()), None);
+        assert_eq!(
+            device_arg(args(&["--config", "/tmp/baton", "--device=2"]).into_iter()),
+            Some("2".to_string())
+        );
+        assert_eq!(device_arg(args(&["--devices"]).into_iter()), None);
+    }
+
+    #[test]
+    fn test_set_device() {
+        let dir = std::env::temp_dir().join(format!("baton_device_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "shared").unwrap();
+
+        let mut paths = Paths::new(Some(dir.clone()));
+        paths.set_device("").unwrap();
+        assert_eq!(paths.config_file(), dir.join(CONFIG_FILE));
+        paths.set_device("SC1E20051014").unwrap();
+        let path = dir.join("devices").join("SC1E20051014").join(CONFIG_FILE);
+        assert_eq!(paths.config_file(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "shared");
+        assert!(!paths.midi_mapping_file().exists());
+        assert_eq!(paths.dir(), dir);
+
+        // The device file is kept once it exists
+        fs::write(&path, "device").unwrap();
+        paths.set_device("SC1E20051014").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "device");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_legacy_file() {
+        let dir = std::env::temp_dir().join(format!("baton_legacy_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let legacy = dir.join(LEGACY_CONFIG_FILE);
+        let path = dir.join(CONFIG_FILE);
+        fs::write(&legacy, "legacy").unwrap();
+
+        migrate_legacy_file(&legacy, &path).unwrap();
+        assert!(!legacy.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "legacy");
+
+        // An existing file is never replaced
+        fs::write(&legacy, "older").unwrap();
+        migrate_legacy_file(&legacy, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "legacy");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_json() {
+        let path = std::env::temp_dir().join(format!("baton_web_test_{}.json", std::process::id()));
+        let load = |path: &Path| load_json(path, |settings| web_errors(settings, 9));
+        assert!(matches!(load(&path), Ok(None)));
+
+        let clients = r#"{ "clients": [{ "name": "Drums", "token": "t", "mixes": [2, 10] }] }"#;
+        fs::write(&path, clients).unwrap();
+        let result = load(&path);
+        fs::write(&path, r#"{ "address": "0.0.0.0:8080" }"#).unwrap();
+        let settings = load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(ConfigError::Invalid(errors)) => {
+                let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+                assert_eq!(paths, vec!["clients[0].mixes[1]"]);
+            }
+            _ => panic!("expected an invalid mix"),
+        }
+        assert_eq!(settings.address, "0.0.0.0:8080");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("baton_config_test_{}.json", std::process::id()));
+
+        config().save(&path).unwrap();
+        let loaded = Config::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.channel_names, config().channel_names);
+        assert_eq!(loaded.channel_colors, config().channel_colors);
+        assert_eq!(loaded.mixes.len(), 2);
+    }
+}
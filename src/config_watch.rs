@@ -0,0 +1,477 @@
+//! Watching of the config and MIDI mapping files, merging external changes into the running mixer.
+
+use crate::config::{self, Config, ConfigError, Paths};
+use crate::midi_control::MidiMapping;
+use crate::usb::{PreSonusStudio1824c, Strip};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Detects modifications of a file by polling its modification time
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    interval: Duration,
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        FileWatcher {
+            path,
+            modified,
+            last_check: Instant::now(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True if the file was modified since the last call,
+    /// checked at most once per interval
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            modified.is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Forget about a modification made by ourselves
+    pub fn mark_written(&mut self) {
+        self.modified = modified_time(&self.path);
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watches the config and MIDI mapping files for external modifications
+pub struct ConfigWatcher {
+    config: FileWatcher,
+    midi_mapping: FileWatcher,
+    /// Config as it was when the file was last loaded or saved
+    base: Config,
+    /// Config as it was before external changes conflicted with the running
+    /// state, until the file and the running state agree on them again
+    conflict_base: Option<Config>,
+}
+
+impl ConfigWatcher {
+    pub fn new(paths: &Paths, ps: &PreSonusStudio1824c) -> Self {
+        ConfigWatcher {
+            config: FileWatcher::new(paths.config_file()),
+            midi_mapping: FileWatcher::new(paths.midi_mapping_file()),
+            base: ps.config(),
+            conflict_base: None,
+        }
+    }
+
+    /// Merge external changes of the config file into the running state.
+    /// Changed strips are written to the device if `write` is set.
+    /// Returns a status message if the file changed.
+    pub fn poll_config(&mut self, ps: &mut PreSonusStudio1824c, write: bool) -> Option<String> {
+        if !self.config.changed() {
+            return None;
+        }
+
+        let path = self.config.path().to_path_buf();
+        let disk = match fs::read_to_string(&path)
+            .map_err(ConfigError::from)
+            .and_then(|contents| Config::parse(&contents))
+            .and_then(|disk| {
+                disk.validate(&ps.mixes, ps.channel_names.len())
+                    .map(|_| disk)
+            }) {
+            Ok(disk) => disk,
+            Err(e) => {
+                log::warn!("Ignoring change of {}: {}", path.display(), e);
+                return Some(format!("Ignoring invalid config change: {}", e));
+            }
+        };
+
+        let merge = merge(&self.base, &disk, &ps.config());
+        let changed = merge.changed.len();
+        if !merge.conflicts.is_empty() && self.conflict_base.is_none() {
+            self.conflict_base = Some(self.base.clone());
+        }
+        ps.apply_config(merge.config);
+        if write {
+            for (mix_index, strip_index) in merge.changed {
+                ps.write_channel_fader(mix_index, strip_index);
+            }
+        }
+        self.resolve_conflicts(&disk, &ps.config());
+        self.base = disk;
+
+        log::info!(
+            "Applied external change of {}: {} strips changed",
+            path.display(),
+            changed
+        );
+        if merge.conflicts.is_empty() {
+            Some(format!("Config reloaded: {} strips changed", changed))
+        } else {
+            log::warn!(
+                "Kept running state for conflicting changes: {}",
+                merge.conflicts.join(", ")
+            );
+            Some(format!(
+                "Config reloaded, kept running state for: {}",
+                merge.conflicts.join(", ")
+            ))
+        }
+    }
+
+    /// The MIDI mapping file if it was changed externally
+    pub fn poll_midi_mapping(&mut self) -> Option<Result<MidiMapping, ConfigError>> {
+        if !self.midi_mapping.changed() {
+            return None;
+        }
+
+        match config::load_json(self.midi_mapping.path(), |_| Vec::new()) {
+            Ok(Some(mapping)) => Some(Ok(mapping)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Forget the conflicts once `disk` and `live` agree on all of them
+    fn resolve_conflicts(&mut self, disk: &Config, live: &Config) {
+        if let Some(base) = &self.conflict_base
+            && merge(base, disk, live).conflicts.is_empty()
+        {
+            log::info!("Config file no longer conflicts with the running state");
+            self.conflict_base = None;
+        }
+    }
+
+    /// Save the running state.
+    /// If external changes conflicted with the running state and the file
+    /// still differs from it there, the file is left alone and the running
+    /// state is saved next to it.
+    pub fn save_config(&mut self, ps: &PreSonusStudio1824c) -> Result<PathBuf, ConfigError> {
+        let saved = ps.config();
+        let mut path = self.config.path().to_path_buf();
+        if self.conflict_base.is_some()
+            && let Ok(disk) = fs::read_to_string(&path)
+                .map_err(ConfigError::from)
+                .and_then(|contents| Config::parse(&contents))
+        {
+            self.resolve_conflicts(&disk, &saved);
+        }
+        let conflicts = self.conflict_base.is_some();
+        if conflicts {
+            let mut local = path.into_os_string();
+            local.push(".local");
+            path = PathBuf::from(local);
+            log::warn!(
+                "Config file has conflicting external changes, saving to {}",
+                path.display()
+            );
+        }
+
+        saved.save(&path)?;
+        if !conflicts {
+            self.config.mark_written();
+            self.base = saved;
+        }
+        Ok(path)
+    }
+
+    pub fn midi_mapping_saved(&mut self) {
+        self.midi_mapping.mark_written();
+    }
+}
+
+/// Result of merging an externally modified config into the running state
+pub struct Merge {
+    pub config: Config,
+    /// Strips that changed and need to be written, as (mix index, strip index)
+    pub changed: Vec<(usize, usize)>,
+    /// Fields changed both in the file and in the running state.
+    /// The running state is kept for these.
+    pub conflicts: Vec<String>,
+}
+
+/// Three way merge of the config file with the running state.
+/// `base` is the config as it was when the file was last loaded or saved.
+/// All configs must have the same shape, see `Config::validate`.
+pub fn merge(base: &Config, disk: &Config, live: &Config) -> Merge {
+    let mut config = live.clone();
+    let mut changed = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for i in 0..live.channel_names.len() {
+        let name = &live.channel_names[i];
+        if let Some(value) = merge_value(
+            &base.channel_names[i],
+            &disk.channel_names[i],
+            name,
+            &mut conflicts,
+            || format!("{} name", name),
+        ) {
+            config.channel_names[i] = value;
+        }
+        if let Some(value) = merge_value(
+            &base.channel_colors[i],
+            &disk.channel_colors[i],
+            &live.channel_colors[i],
+            &mut conflicts,
+            || format!("{} colour", name),
+        ) {
+            config.channel_colors[i] = value;
+        }
+    }
+
+    if let Some(value) = merge_value(
+        &base.pan_law,
+        &disk.pan_law,
+        &live.pan_law,
+        &mut conflicts,
+        || "pan law".to_string(),
+    ) {
+        // The pan law changes the levels of every channel strip
+        config.pan_law = value;
+        for (m, mix) in live.mixes.iter().enumerate() {
+            changed.extend((0..mix.strips.channel_strips.len()).map(|s| (m, s)));
+        }
+    }
+
+    for (m, mix) in config.mixes.iter_mut().enumerate() {
+        let (base_mix, disk_mix, live_mix) = (&base.mixes[m], &disk.mixes[m], &live.mixes[m]);
+        let strip_name = |s: usize| {
+            let name = live.channel_names.get(s).unwrap_or(&live_mix.name).clone();
+            format!("{}/{}", live_mix.name, name)
+        };
+
+        if let Some(value) = merge_value(
+            &base_mix.name,
+            &disk_mix.name,
+            &live_mix.name,
+            &mut conflicts,
+            || format!("{} name", live_mix.name),
+        ) {
+            mix.name = value;
+        }
+        if let Some(value) = merge_value(
+            &base_mix.color,
+            &disk_mix.color,
+            &live_mix.color,
+            &mut conflicts,
+            || format!("{} colour", live_mix.name),
+        ) {
+            mix.color = value;
+        }
+
+        let mut solo_changed = false;
+        let strips = base_mix
+            .strips
+            .iter()
+            .zip(disk_mix.strips.iter())
+            .zip(live_mix.strips.iter())
+            .zip(mix.strips.iter_mut())
+            .enumerate();
+        for (s, (((base_strip, disk_strip), live_strip), strip)) in strips {
+            if let Some(value) = merge_value(
+                &StripValues::from(base_strip),
+                &StripValues::from(disk_strip),
+                &StripValues::from(live_strip),
+                &mut conflicts,
+                || strip_name(s),
+            ) {
+                solo_changed |= value.solo != live_strip.solo;
+                value.apply(strip);
+                changed.push((m, s));
+            }
+        }
+
+        if solo_changed {
+            // Solo changes the muting of every strip in the mix
+            mix.sync_solo();
+            changed.retain(|&(mix_index, _)| mix_index != m);
+            changed.extend((0..=mix.strips.channel_strips.len()).map(|s| (m, s)));
+        }
+    }
+    changed.sort_unstable();
+    changed.dedup();
+
+    Merge {
+        config,
+        changed,
+        conflicts,
+    }
+}
+
+/// Returns the value to use if the file changed and the running state did not.
+/// Records a conflict if both changed to different values.
+fn merge_value<T: Clone + PartialEq>(
+    base: &T,
+    disk: &T,
+    live: &T,
+    conflicts: &mut Vec<String>,
+    describe: impl FnOnce() -> String,
+) -> Option<T> {
+    if disk == base || disk == live {
+        None
+    } else if live == base {
+        Some(disk.clone())
+    } else {
+        conflicts.push(describe());
+        None
+    }
+}
+
+/// The persisted values of a strip
+#[derive(Clone, PartialEq)]
+struct StripValues {
+    fader: f64,
+    balance: f64,
+    solo: bool,
+    mute: bool,
+}
+
+impl From<&Strip> for StripValues {
+    fn from(strip: &Strip) -> Self {
+        StripValues {
+            fader: strip.fader,
+            balance: strip.balance,
+            solo: strip.solo,
+            mute: strip.mute,
+        }
+    }
+}
+
+impl StripValues {
+    fn apply(&self, strip: &mut Strip) {
+        strip.fader = self.fader;
+        strip.balance = self.balance;
+        strip.solo = self.solo;
+        strip.mute = self.mute;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::{Mix, StripKind};
+
+    fn config() -> Config {
+        Config::new(
+            vec!["In 1".to_string(), "In 2".to_string()],
+            vec![None, None],
+            0.0,
+            vec![
+                Mix::new(String::from("MAIN 1-2"), StripKind::Main, 0, 2),
+                Mix::new(String::from("MIX 3-4"), StripKind::Bus, 1, 2),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_merge_unchanged() {
+        let merge = merge(&config(), &config(), &config());
+        assert!(merge.changed.is_empty());
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_external_change() {
+        let base = config();
+        let mut disk = config();
+        disk.mixes[1].strips.channel_strips[0].fader = -12.0;
+        disk.mixes[0].strips.bus_strip.mute = true;
+        disk.channel_names[1] = "Kick".to_string();
+        let mut live = config();
+        live.mixes[1].strips.channel_strips[1].fader = -3.0;
+
+        let merge = merge(&base, &disk, &live);
+        assert_eq!(merge.changed, vec![(0, 2), (1, 0)]);
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(merge.config.mixes[1].strips.channel_strips[0].fader, -12.0);
+        assert_eq!(merge.config.mixes[1].strips.channel_strips[1].fader, -3.0);
+        assert!(merge.config.mixes[0].strips.bus_strip.mute);
+        assert_eq!(merge.config.channel_names[1], "Kick");
+    }
+
+    #[test]
+    fn test_merge_conflict_keeps_live() {
+        let base = config();
+        let mut disk = config();
+        disk.mixes[1].strips.channel_strips[0].fader = -12.0;
+        let mut live = config();
+        live.mixes[1].strips.channel_strips[0].fader = -6.0;
+
+        let merge = merge(&base, &disk, &live);
+        assert!(merge.changed.is_empty());
+        assert_eq!(merge.conflicts, vec!["MIX 3-4/In 1".to_string()]);
+        assert_eq!(merge.config.mixes[1].strips.channel_strips[0].fader, -6.0);
+    }
+
+    #[test]
+    fn test_conflict_resolved_when_file_agrees() {
+        let mut watcher = ConfigWatcher {
+            config: FileWatcher::new(PathBuf::from("config.json")),
+            midi_mapping: FileWatcher::new(PathBuf::from("midi_mapping.json")),
+            base: config(),
+            conflict_base: Some(config()),
+        };
+        let mut disk = config();
+        disk.mixes[1].strips.channel_strips[0].fader = -12.0;
+        let mut live = config();
+        live.mixes[1].strips.channel_strips[0].fader = -6.0;
+
+        watcher.resolve_conflicts(&disk, &live);
+        assert!(watcher.conflict_base.is_some());
+
+        disk.mixes[1].strips.channel_strips[0].fader = -6.0;
+        watcher.resolve_conflicts(&disk, &live);
+        assert!(watcher.conflict_base.is_none());
+    }
+
+    #[test]
+    fn test_merge_same_change_is_no_conflict() {
+        let base = config();
+        let mut disk = config();
+        disk.mixes[0].strips.channel_strips[1].balance = 50.0;
+        let live = disk.clone();
+
+        let merge = merge(&base, &disk, &live);
+        assert!(merge.changed.is_empty());
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_pan_law_writes_channel_strips() {
+        let base = config();
+        let mut disk = config();
+        disk.pan_law = -3.0;
+        disk.mixes[0].strips.channel_strips[0].fader = -6.0;
+
+        let merge = merge(&base, &disk, &base);
+        assert_eq!(merge.config.pan_law, -3.0);
+        assert_eq!(merge.changed, vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_merge_solo_writes_whole_mix() {
+        let base = config();
+        let mut disk = config();
+        disk.mixes[1].strips.channel_strips[0].solo = true;
+
+        let merge = merge(&base, &disk, &base);
+        assert_eq!(merge.changed, vec![(1, 0), (1, 1), (1, 2)]);
+        assert!(merge.config.mixes[1].strips.channel_strips[1].mute_by_solo);
+    }
+}
@@ -0,0 +1,132 @@
+//! Connection to the interface: taken offline when it disconnects or stops
+//! answering, and looked for again until it reappears.
+
+use baton_studio::{Command, State};
+use nusb::Device;
+use nusb::transfer::TransferError;
+use std::time::{Duration, Instant};
+
+/// Failed transfers in a row after which the interface counts as gone
+pub const MAX_FAILURES: u32 = 3;
+
+/// How often an offline interface is looked for
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Connection {
+    /// None while offline
+    device: Option<Device>,
+    /// Failed transfers since the last one that worked
+    failures: u32,
+    last_attempt: Option<Instant>,
+}
+
+impl Connection {
+    pub fn online(device: Device) -> Self {
+        Connection {
+            device: Some(device),
+            failures: 0,
+            last_attempt: None,
+        }
+    }
+
+    pub fn offline() -> Self {
+        Connection {
+            device: None,
+            failures: 0,
+            last_attempt: None,
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.device.is_some()
+    }
+
+    /// Send `command` to the interface. Nothing is sent while offline,
+    /// the whole state is written again on reconnect.
+    pub fn send(&mut self, command: &Command) -> Result<(), TransferError> {
+        let Some(device) = &self.device else {
+            return Ok(());
+        };
+        let result = command.send(device);
+        self.record(&result);
+        result
+    }
+
+    /// Read the state of the interface, fails while offline
+    pub fn poll(&mut self, state: &mut State) -> Result<(), TransferError> {
+        let Some(device) = &self.device else {
+            return Err(TransferError::Disconnected);
+        };
+        let result = state.poll(device);
+        self.record(&result);
+        result
+    }
+
+    fn record(&mut self, result: &Result<(), TransferError>) {
+        match result {
+            Ok(()) => self.failures = 0,
+            Err(e) => {
+                if is_lost(e, &mut self.failures) {
+                    log::warn!("Interface disconnected: {}", e);
+                    self.device = None;
+                    self.last_attempt = None;
+                }
+            }
+        }
+    }
+
+    /// Whether to look for the offline interface again at `now`
+    pub fn retry_due(&mut self, now: Instant) -> bool {
+        if self.is_online() || !retry_due(self.last_attempt, now) {
+            return false;
+        }
+        self.last_attempt = Some(now);
+        true
+    }
+
+    /// Use the reopened interface
+    pub fn reconnected(&mut self, device: Device) {
+        self.device = Some(device);
+        self.failures = 0;
+    }
+}
+
+/// Count a failed transfer; whether the interface is gone with it
+fn is_lost(error: &TransferError, failures: &mut u32) -> bool {
+    *failures += 1;
+    *error == TransferError::Disconnected || *failures >= MAX_FAILURES
+}
+
+fn retry_due(last_attempt: Option<Instant>, now: Instant) -> bool {
+    last_attempt.is_none_or(|last| now.duration_since(last) >= RETRY_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_lost() {
+        let mut failures = 0;
+        assert!(!is_lost(&TransferError::Cancelled, &mut failures));
+        assert!(!is_lost(&TransferError::Stall, &mut failures));
+        assert!(is_lost(&TransferError::Cancelled, &mut failures));
+
+        let mut failures = 0;
+        assert!(is_lost(&TransferError::Disconnected, &mut failures));
+    }
+
+    #[test]
+    fn test_retry() {
+        let now = Instant::now();
+        assert!(retry_due(None, now));
+        assert!(!retry_due(Some(now), now + RETRY_INTERVAL / 2));
+        assert!(retry_due(Some(now), now + RETRY_INTERVAL));
+
+        let mut connection = Connection::offline();
+        assert!(!connection.is_online());
+        assert!(connection.retry_due(now));
+        assert!(!connection.retry_due(now));
+        assert!(connection.retry_due(now + RETRY_INTERVAL));
+    }
+}
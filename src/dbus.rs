@@ -0,0 +1,392 @@
+//! Optional D-Bus service on the session bus, enabled by `dbus.json`.
+//! Desktop integrations can control strips, buttons and scenes
+//! and get signals for state changes and clips.
+
+use crate::access::{Permissions, RemoteChange, StripChange};
+use crate::config::Paths;
+use crate::scene;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::blocking::object_server::InterfaceRef;
+use zbus::object_server::SignalEmitter;
+use zbus::{blocking, fdo, interface};
+
+pub const OBJECT_PATH: &str = "/org/baton/Mixer";
+
+/// How long a method waits for the mixer to apply a change
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Settings of `dbus.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DbusSettings {
+    /// Well-known name on the session bus
+    pub name: String,
+    /// What callers may change, everything if not set
+    #[serde(flatten)]
+    pub permissions: Permissions,
+}
+
+impl Default for DbusSettings {
+    fn default() -> Self {
+        DbusSettings {
+            name: "org.baton.Mixer".to_string(),
+            permissions: Permissions::default(),
+        }
+    }
+}
+
+/// A strip as seen on the bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct StripState {
+    pub name: String,
+    pub fader: f64,
+    pub pan: f64,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+/// The mixer as seen on the bus, published after every poll
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MixerState {
+    /// Names and strips of the mixes, the bus strip last
+    pub mixes: Vec<(String, Vec<StripState>)>,
+    pub phantom_power: bool,
+    pub line: bool,
+    pub main_mute: bool,
+    pub main_mono: bool,
+}
+
+/// A change asked for on the bus
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbusCall {
+    Change(RemoteChange),
+    SaveScene(String),
+    RecallScene(String),
+}
+
+/// A call waiting for `PreSonusStudio1824c::poll_state` to apply it
+pub struct DbusRequest {
+    pub call: DbusCall,
+    reply: Sender<Result<(), String>>,
+}
+
+impl DbusRequest {
+    /// Answer the caller
+    pub fn reply(self, result: Result<(), String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// The object served at `OBJECT_PATH`
+struct Mixer {
+    state: Arc<Mutex<MixerState>>,
+    requests: Sender<DbusRequest>,
+    paths: Paths,
+}
+
+impl Mixer {
+    /// Send a call to the mixer and wait for the result
+    fn call(&self, call: DbusCall) -> fdo::Result<()> {
+        let (reply, result) = mpsc::channel();
+        self.requests
+            .send(DbusRequest { call, reply })
+            .map_err(|_| fdo::Error::Failed("the mixer has stopped".to_string()))?;
+        match result.recv_timeout(REPLY_TIMEOUT) {
+            Ok(result) => result.map_err(fdo::Error::Failed),
+            Err(_) => Err(fdo::Error::TimedOut("the mixer did not answer".to_string())),
+        }
+    }
+
+    fn strip_change(&self, mix: u32, strip: u32, change: StripChange) -> fdo::Result<()> {
+        let (mix, strip) = indexes(mix, strip)?;
+        self.call(DbusCall::Change(RemoteChange::Strip { mix, strip, change }))
+    }
+}
+
+/// Mix and strip indexes of numbers from 1
+fn indexes(mix: u32, strip: u32) -> fdo::Result<(usize, usize)> {
+    match (
+        (mix as usize).checked_sub(1),
+        (strip as usize).checked_sub(1),
+    ) {
+        (Some(mix), Some(strip)) => Ok((mix, strip)),
+        _ => Err(fdo::Error::InvalidArgs(
+            "mixes and strips are numbered from 1".to_string(),
+        )),
+    }
+}
+
+#[interface(name = "org.baton.Mixer1")]
+impl Mixer {
+    /// Set the fader of a strip in dB. Mixes and strips are numbered from 1,
+    /// the bus strip comes after the channel strips.
+    fn set_fader(&self, mix: u32, strip: u32, db: f64) -> fdo::Result<()> {
+        self.strip_change(mix, strip, StripChange::Fader(db))
+    }
+
+    /// Pan a channel strip, -100 is left and 100 is right
+    fn set_pan(&self, mix: u32, strip: u32, pan: f64) -> fdo::Result<()> {
+        self.strip_change(mix, strip, StripChange::Pan(pan))
+    }
+
+    fn set_mute(&self, mix: u32, strip: u32, on: bool) -> fdo::Result<()> {
+        self.strip_change(mix, strip, StripChange::Mute(on))
+    }
+
+    fn set_solo(&self, mix: u32, strip: u32, on: bool) -> fdo::Result<()> {
+        self.strip_change(mix, strip, StripChange::Solo(on))
+    }
+
+    /// Name, fader, pan, mute and solo of a strip
+    fn get_strip(&self, mix: u32, strip: u32) -> fdo::Result<(String, f64, f64, bool, bool)> {
+        let (mix, strip) = indexes(mix, strip)?;
+        let state = self.state.lock().unwrap();
+        let strip = state
+            .mixes
+            .get(mix)
+            .and_then(|(_, strips)| strips.get(strip))
+            .ok_or_else(|| fdo::Error::InvalidArgs("no such strip".to_string()))?;
+        Ok((
+            strip.name.clone(),
+            strip.fader,
+            strip.pan,
+            strip.mute,
+            strip.solo,
+        ))
+    }
+
+    fn list_scenes(&self) -> fdo::Result<Vec<String>> {
+        scene::list(&self.paths).map_err(|e| fdo::Error::IOError(e.to_string()))
+    }
+
+    fn save_scene(&self, name: String) -> fdo::Result<()> {
+        self.call(DbusCall::SaveScene(name))
+    }
+
+    /// Load a scene and send it to the interface
+    fn recall_scene(&self, name: String) -> fdo::Result<()> {
+        self.call(DbusCall::RecallScene(name))
+    }
+
+    /// Names of the mixes
+    #[zbus(property)]
+    fn mixes(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.mixes.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    #[zbus(property)]
+    fn phantom_power(&self) -> bool {
+        self.state.lock().unwrap().phantom_power
+    }
+
+    #[zbus(property)]
+    fn set_phantom_power(&self, on: bool) -> zbus::Result<()> {
+        Ok(self.call(DbusCall::Change(RemoteChange::Phantom(on)))?)
+    }
+
+    /// Inputs 1-2 at line level
+    #[zbus(property)]
+    fn line(&self) -> bool {
+        self.state.lock().unwrap().line
+    }
+
+    #[zbus(property)]
+    fn set_line(&self, on: bool) -> zbus::Result<()> {
+        Ok(self.call(DbusCall::Change(RemoteChange::Line(on)))?)
+    }
+
+    #[zbus(property)]
+    fn main_mute(&self) -> bool {
+        self.state.lock().unwrap().main_mute
+    }
+
+    #[zbus(property)]
+    fn set_main_mute(&self, on: bool) -> zbus::Result<()> {
+        let main = self
+            .state
+            .lock()
+            .unwrap()
+            .mixes
+            .first()
+            .map(|(_, s)| s.len());
+        let strip = main.ok_or_else(|| fdo::Error::Failed("no main mix".to_string()))?;
+        Ok(self.strip_change(1, strip as u32, StripChange::Mute(on))?)
+    }
+
+    #[zbus(property)]
+    fn main_mono(&self) -> bool {
+        self.state.lock().unwrap().main_mono
+    }
+
+    #[zbus(property)]
+    fn set_main_mono(&self, on: bool) -> zbus::Result<()> {
+        Ok(self.call(DbusCall::Change(RemoteChange::MainMono(on)))?)
+    }
+
+    /// A strip of any mix has changed, see `GetStrip`
+    #[zbus(signal)]
+    async fn state_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// A meter went over full scale. The group is `input` or `output`,
+    /// channels are numbered from 1.
+    #[zbus(signal)]
+    async fn clip(
+        emitter: &SignalEmitter<'_>,
+        group: &str,
+        channel: u32,
+        name: &str,
+    ) -> zbus::Result<()>;
+}
+
+pub struct DbusServer {
+    _connection: blocking::Connection,
+    mixer: InterfaceRef<Mixer>,
+    state: Arc<Mutex<MixerState>>,
+    requests: Receiver<DbusRequest>,
+    paths: Paths,
+    permissions: Permissions,
+    /// Clip counters at the last `signal_clips`, by group
+    clips: HashMap<&'static str, Vec<u32>>,
+}
+
+impl DbusServer {
+    pub fn start(settings: &DbusSettings, paths: &Paths) -> zbus::Result<Self> {
+        let state = Arc::new(Mutex::new(MixerState::default()));
+        let (sender, requests) = mpsc::channel();
+        let mixer = Mixer {
+            state: state.clone(),
+            requests: sender,
+            paths: paths.clone(),
+        };
+        let connection = blocking::connection::Builder::session()?
+            .name(settings.name.as_str())?
+            .serve_at(OBJECT_PATH, mixer)?
+            .build()?;
+        let mixer = connection
+            .object_server()
+            .interface::<_, Mixer>(OBJECT_PATH)?;
+        Ok(DbusServer {
+            _connection: connection,
+            mixer,
+            state,
+            requests,
+            paths: paths.clone(),
+            permissions: settings.permissions.clone(),
+            clips: HashMap::new(),
+        })
+    }
+
+    /// Calls received since the last call
+    pub fn requests(&self) -> Vec<DbusRequest> {
+        self.requests.try_iter().collect()
+    }
+
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    /// What callers may change
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    /// Publish the mixer state, signalling what has changed
+    pub fn publish(&self, state: MixerState) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), state.clone());
+        if previous == state {
+            return;
+        }
+        let emitter = self.mixer.signal_emitter();
+        let mixer = self.mixer.get();
+        if previous.mixes != state.mixes {
+            self.emit(Mixer::state_changed(emitter));
+        }
+        if previous.phantom_power != state.phantom_power {
+            self.emit(mixer.phantom_power_changed(emitter));
+        }
+        if previous.line != state.line {
+            self.emit(mixer.line_changed(emitter));
+        }
+        if previous.main_mute != state.main_mute {
+            self.emit(mixer.main_mute_changed(emitter));
+        }
+        if previous.main_mono != state.main_mono {
+            self.emit(mixer.main_mono_changed(emitter));
+        }
+        let names = |state: &MixerState| -> Vec<String> {
+            state.mixes.iter().map(|(name, _)| name.clone()).collect()
+        };
+        if names(&previous) != names(&state) {
+            self.emit(mixer.mixes_changed(emitter));
+        }
+    }
+
+    /// Signal the meters of `group` whose clip counters went up.
+    /// `meters` has the name and clip counter of each meter.
+    pub fn signal_clips(&mut self, group: &'static str, meters: &[(&str, u32)]) {
+        let last = self.clips.entry(group).or_default();
+        last.resize(meters.len(), 0);
+        for (i, ((name, clips), last)) in meters.iter().zip(last.iter_mut()).enumerate() {
+            if *clips > *last {
+                let signal = Mixer::clip(self.mixer.signal_emitter(), group, i as u32 + 1, name);
+                if let Err(e) = futures_lite::future::block_on(signal) {
+                    log::warn!("Failed to send a D-Bus signal: {}", e);
+                }
+            }
+            *last = *clips;
+        }
+    }
+
+    fn emit(&self, signal: impl Future<Output = zbus::Result<()>>) {
+        if let Err(e) = futures_lite::future::block_on(signal) {
+            log::warn!("Failed to send a D-Bus signal: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexes() {
+        assert_eq!(indexes(2, 37).unwrap(), (1, 36));
+        assert!(indexes(0, 1).is_err());
+        assert!(indexes(1, 0).is_err());
+    }
+
+    #[test]
+    fn test_call() {
+        let (sender, requests) = mpsc::channel();
+        let mixer = Mixer {
+            state: Arc::new(Mutex::new(MixerState::default())),
+            requests: sender,
+            paths: Paths::new(Some(std::env::temp_dir())),
+        };
+        let applier = std::thread::spawn(move || {
+            let request: DbusRequest = requests.recv().unwrap();
+            let call = request.call.clone();
+            request.reply(Err("mix 12 is not allowed".to_string()));
+            call
+        });
+
+        assert!(mixer.set_fader(12, 3, -6.0).is_err());
+        assert_eq!(
+            applier.join().unwrap(),
+            DbusCall::Change(RemoteChange::Strip {
+                mix: 11,
+                strip: 2,
+                change: StripChange::Fader(-6.0)
+            })
+        );
+        assert!(matches!(
+            mixer.set_mute(1, 1, true),
+            Err(fdo::Error::Failed(_))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+//! Enumeration of the connected interfaces and selection by serial number or index.
+
+use nusb::MaybeFuture;
+use std::io;
+
+const VENDOR_ID: u16 = 0x194f;
+const PRODUCT_ID: u16 = 0x010d;
+
+/// A connected STUDIO1824c
+#[derive(Debug, Clone)]
+pub struct DeviceEntry {
+    /// Serial number, empty if the interface has none
+    pub serial: String,
+    pub product: String,
+    pub info: nusb::DeviceInfo,
+}
+
+/// All connected interfaces, in the order the system lists them
+pub fn list() -> io::Result<Vec<DeviceEntry>> {
+    let devices = nusb::list_devices()
+        .wait()?
+        .filter(|dev| dev.vendor_id() == VENDOR_ID && dev.product_id() == PRODUCT_ID)
+        .map(|info| DeviceEntry {
+            serial: info.serial_number().unwrap_or_default().to_string(),
+            product: info.product_string().unwrap_or("unknown").to_string(),
+            info,
+        })
+        .collect();
+    Ok(devices)
+}
+
+/// Index of the device picked by `selector` among the devices with `serials`:
+/// a serial number, or a number from 1. The first device without a selector.
+pub fn select(serials: &[&str], selector: Option<&str>) -> Result<usize, String> {
+    if serials.is_empty() {
+        return Err("device not found".to_string());
+    }
+    let Some(selector) = selector else {
+        return Ok(0);
+    };
+    let mut matching = (0..serials.len()).filter(|&i| serials[i] == selector);
+    match (matching.next(), matching.next()) {
+        (Some(index), None) if !selector.is_empty() => return Ok(index),
+        (Some(_), _) => {
+            return Err(format!(
+                "serial '{}' is ambiguous, pick the device by number",
+                selector
+            ));
+        }
+        (None, _) => (),
+    }
+    match selector.parse::<usize>() {
+        Ok(number) if (1..=serials.len()).contains(&number) => Ok(number - 1),
+        _ => Err(format!(
+            "no device '{}', connected: {}",
+            selector,
+            serials
+                .iter()
+                .map(|serial| display_serial(serial))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// A serial number for messages and menus
+pub fn display_serial(serial: &str) -> &str {
+    if serial.is_empty() {
+        "no serial"
+    } else {
+        serial
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let serials = ["SC1E20051014", "SC1E21020077"];
+        assert_eq!(select(&serials, None), Ok(0));
+        assert_eq!(select(&serials, Some("SC1E21020077")), Ok(1));
+        assert_eq!(select(&serials, Some("2")), Ok(1));
+        assert!(select(&serials, Some("3")).is_err());
+        assert!(select(&serials, Some("0")).is_err());
+        assert!(select(&serials, Some("SC1")).is_err());
+        assert!(select(&[], None).is_err());
+
+        // Interfaces without or with the same serial are picked by number
+        let serials = ["", ""];
+        assert_eq!(select(&serials, Some("2")), Ok(1));
+        assert!(select(&serials, Some("")).is_err());
+        let serials = ["SC1E20051014", "SC1E20051014"];
+        assert!(select(&serials, Some("SC1E20051014")).is_err());
+    }
+}
@@ -3,15 +3,29 @@ use eframe::egui;
 use flexi_logger::{FileSpec, detailed_format};
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{Read, Write},
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
+mod ab;
+mod access;
+mod alerts;
+mod config;
+mod config_watch;
+mod connection;
+mod dbus;
+mod device;
+mod matrix;
+mod meter_bridge;
+mod meter_log;
+mod metering;
+mod metrics;
 mod midi;
 mod midi_control;
+mod ports;
+mod scene;
 mod usb;
+mod web;
 
 enum StripAction {
     None,
@@ -22,7 +36,7 @@ enum StripAction {
     StartMidiLearnMute,
     StartMidiLearnSolo,
     NameChanged(String),
-    ColorChanged(egui::Color32),
+    ColorChanged(Option<usb::StripColor>),
 }
 
 fn main() -> eframe::Result {
@@ -36,6 +50,14 @@ fn main() -> eframe::Result {
 
     log::info!("Starting Baton GUI");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let device = config::device_arg(args.iter().cloned());
+    let devices = device::list().unwrap_or_else(|e| {
+        log::warn!("Failed to list devices: {}", e);
+        Vec::new()
+    });
+    let switch_device = Arc::new(Mutex::new(None));
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1200.0, 800.0])
@@ -43,16 +65,48 @@ fn main() -> eframe::Result {
         ..Default::default()
     };
 
-    eframe::run_native(
+    let app_switch_device = switch_device.clone();
+    let result = eframe::run_native(
         "Baton Mixer",
         options,
-        Box::new(|cc| Ok(Box::new(BatonApp::new(cc)))),
-    )
+        Box::new(move |cc| {
+            let app = BatonApp::new(cc, device.as_deref(), devices, app_switch_device);
+            Ok(Box::new(app))
+        }),
+    );
+
+    // The window was closed to switch to another device, start again with it
+    if let Some(device) = switch_device.lock().unwrap().take()
+        && let Err(e) = relaunch(&args, &device)
+    {
+        log::error!("Failed to restart with device {}: {}", device, e);
+    }
+    result
+}
+
+/// Start this program again with the same arguments, but `--device device`
+fn relaunch(args: &[String], device: &str) -> std::io::Result<()> {
+    let mut new_args = Vec::with_capacity(args.len() + 2);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--device" {
+            iter.next();
+        } else if !arg.starts_with("--device=") {
+            new_args.push(arg.clone());
+        }
+    }
+    new_args.extend(["--device".to_string(), device.to_string()]);
+    log::info!("Restarting with device {}", device);
+    std::process::Command::new(std::env::current_exe()?)
+        .args(new_args)
+        .spawn()?;
+    Ok(())
 }
 
 struct BatonApp {
     ps: Arc<Mutex<usb::PreSonusStudio1824c>>,
-    config_dir: Option<std::path::PathBuf>,
+    paths: config::Paths,
+    config_watcher: config_watch::ConfigWatcher,
     midi_input: Option<midi::MidiInput>,
     midi_mapping: midi_control::MidiMapping,
     midi_learn_state: midi_control::MidiLearnState,
@@ -61,16 +115,30 @@ struct BatonApp {
     active_strip_index: usize,
     last_tick: Instant,
     tick_rate: Duration,
-    bypass: bool,
     status_message: String,
     clip_indicators: HashMap<String, Instant>, // Track clip times by meter ID
     peak_holds: HashMap<String, (f64, Instant)>, // Track peak values and times by meter ID
-    meter_averages: HashMap<String, Vec<(f64, Instant)>>, // Track meter history for running average
-    strip_colors: HashMap<String, egui::Color32>, // Track custom colors by strip ID (mix_index:strip_index)
+    ab: ab::AbCompare,
+    show_matrix: bool,
+    /// Show the estimated level of each input in the active mix
+    post_fader_meters: bool,
+    meter_bridge: meter_bridge::MeterBridge,
+    alerts: Option<alerts::Alerts>,
+    /// When the last alert was raised, to flash the status bar
+    alert_time: Option<Instant>,
+    /// Interfaces for the device menu
+    devices: Vec<device::DeviceEntry>,
+    /// Serial or number of the device to restart with, set when the window is closed to switch
+    switch_device: Arc<Mutex<Option<String>>>,
 }
 
 impl BatonApp {
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(
+        _cc: &eframe::CreationContext<'_>,
+        device: Option<&str>,
+        devices: Vec<device::DeviceEntry>,
+        switch_device: Arc<Mutex<Option<String>>>,
+    ) -> Self {
         let midi_input = match midi::MidiInput::new() {
             Ok(m) => {
                 log::info!("MIDI input initialized");
@@ -83,59 +151,78 @@ impl BatonApp {
         };
 
         // Initialize config directory
-        let mut config_dir = dirs::config_dir().map(|d| d.join("baton"));
-        if let Some(ref dir) = config_dir {
-            if !dir.exists() {
-                if let Err(e) = std::fs::create_dir_all(dir) {
-                    log::warn!("Failed to create config directory {}: {}", dir.display(), e);
-                    config_dir = None;
-                }
-            }
+        let mut paths = config::Paths::from_args();
+        if let Err(e) = paths.init() {
+            log::warn!(
+                "Failed to initialize config directory {}: {}",
+                paths.dir().display(),
+                e
+            );
         }
 
-        let ps = Arc::new(Mutex::new(
-            usb::PreSonusStudio1824c::new().expect("Failed to open device"),
-        ));
+        let mut status_message = String::new();
+        let ps = usb::PreSonusStudio1824c::new(device).unwrap_or_else(|e| {
+            log::error!("Failed to open device: {}", e);
+            status_message = format!("Interface offline, waiting for it: {}", e);
+            usb::PreSonusStudio1824c::offline(device)
+        });
+        let serial = ps.serial.clone();
+        let ps = Arc::new(Mutex::new(ps));
+        if let Err(e) = paths.set_device(&serial) {
+            log::error!("Failed to set up the config of device {}: {}", serial, e);
+            status_message = format!("Device config not set up: {}", e);
+        }
 
         // Load config
-        let mut midi_mapping = midi_control::MidiMapping::create_default();
-        match config_dir {
-            Some(ref dir) => {
-                let config_file = dir.join("config.json");
-                if let Ok(mut file) = File::open(&config_file) {
-                    let mut serialized = String::new();
-                    if file.read_to_string(&mut serialized).is_ok() {
-                        let mut ps_lock = ps.lock().unwrap();
-                        ps_lock.load_config(&serialized);
-                        ps_lock.write_state();
-                    }
-                }
-
-                // Load MIDI mapping
-                let midi_mapping_file = dir.join("midi_mapping.json");
-                if let Ok(mut file) = File::open(&midi_mapping_file) {
-                    let mut contents = String::new();
-                    file.read_to_string(&mut contents).ok();
-                    match serde_json::from_str(&contents) {
-                        Ok(mapping) => {
-                            midi_mapping = mapping;
-                        }
-                        Err(e) => {
-                            log::warn!(
-                                "Failed to parse MIDI mapping from {}: {}",
-                                midi_mapping_file.display(),
-                                e
-                            );
-                        }
-                    }
-                }
+        let config_file = paths.config_file();
+        let mut ps_lock = ps.lock().unwrap();
+        match config::load_mixer(&mut ps_lock, &config_file) {
+            Ok(true) => ps_lock.write_state(),
+            Ok(false) => (),
+            Err(e) => {
+                log::error!("Failed to load config {}: {}", config_file.display(), e);
+                status_message = format!("Config not loaded: {}", e);
             }
-            None => (),
         }
+        if let Some(message) = ps_lock.start_services(&paths) {
+            status_message = message;
+        }
+        let config_watcher = config_watch::ConfigWatcher::new(&paths, &ps_lock);
+        // The GUI has no command line to run the commands of the rules
+        let alerts = match alerts::Alerts::load(
+            paths.dir(),
+            ps_lock.channel_meters.len(),
+            ps_lock.mixes.len(),
+            false,
+        ) {
+            Ok(alerts) => alerts,
+            Err(e) => {
+                log::error!("Failed to load alerts: {}", e);
+                status_message = format!("Alerts not loaded: {}", e);
+                None
+            }
+        };
+        drop(ps_lock);
+
+        // Load MIDI mapping
+        let midi_mapping_file = paths.midi_mapping_file();
+        let midi_mapping = match config::load_json(&midi_mapping_file, |_| Vec::new()) {
+            Ok(Some(mapping)) => mapping,
+            Ok(None) => midi_control::MidiMapping::create_default(),
+            Err(e) => {
+                log::warn!(
+                    "Failed to parse MIDI mapping from {}: {}",
+                    midi_mapping_file.display(),
+                    e
+                );
+                midi_control::MidiMapping::create_default()
+            }
+        };
 
         Self {
             ps,
-            config_dir,
+            paths,
+            config_watcher,
             midi_input,
             midi_mapping,
             midi_learn_state: midi_control::MidiLearnState::Inactive,
@@ -144,12 +231,48 @@ impl BatonApp {
             active_strip_index: 0,
             last_tick: Instant::now(),
             tick_rate: Duration::from_millis(33),
-            bypass: false,
-            status_message: String::new(),
+            status_message,
             clip_indicators: HashMap::new(),
             peak_holds: HashMap::new(),
-            meter_averages: HashMap::new(),
-            strip_colors: HashMap::new(),
+            ab: ab::AbCompare::new(),
+            show_matrix: false,
+            post_fader_meters: false,
+            meter_bridge: meter_bridge::MeterBridge::new(),
+            alerts,
+            alert_time: None,
+            devices,
+            switch_device,
+        }
+    }
+
+    /// Show and notify the alerts raised by the last poll.
+    /// The commands of the rules are only run by the TUI.
+    fn process_alerts(&mut self) {
+        let Some(alerts) = &mut self.alerts else {
+            return;
+        };
+        let now = Instant::now();
+        let ps = self.ps.lock().unwrap();
+        let inputs: Vec<f64> = ps.channel_meters.iter().map(|m| m.peak).collect();
+        let buses: Vec<f64> = ps.bus_meters.iter().map(|m| m.peak).collect();
+        let messages: Vec<String> = alerts
+            .check(&inputs, &buses, now)
+            .into_iter()
+            .map(|rule| {
+                let name = match rule.meter {
+                    alerts::MeterRef::Input(index) => ps.channel_label(index),
+                    alerts::MeterRef::Bus(index) => &ps.mixes[index].name,
+                };
+                rule.message(name)
+            })
+            .collect();
+        drop(ps);
+
+        for message in messages {
+            log::warn!("Alert: {}", message);
+            alerts.notify(&message);
+            self.status_message = format!("Alert: {}", message);
+            self.alert_time = Some(now);
         }
     }
 
@@ -303,68 +426,134 @@ impl BatonApp {
                 let strip_index = ((value as f64 / 127.0) * 10.0) as usize;
                 self.active_strip_index = strip_index;
             }
+            midi_control::GlobalControl::AbToggle => {
+                if value > 63 {
+                    drop(ps);
+                    self.toggle_ab();
+                }
+            }
+            midi_control::GlobalControl::AbCopyAToB => {
+                if value > 63 {
+                    drop(ps);
+                    self.copy_a_to_b();
+                }
+            }
         }
     }
 
-    fn save_midi_mapping(&mut self) {
-        match self.config_dir {
-            Some(ref dir) => {
-                let midi_mapping_file = dir.join("midi_mapping.json");
-                self.save_midi_mapping_to_file(&midi_mapping_file);
+    fn process_config_changes(&mut self) {
+        let mut ps = self.ps.lock().unwrap();
+        let write = !ps.bypass;
+        if let Some(message) = self.config_watcher.poll_config(&mut ps, write) {
+            self.status_message = message;
+        }
+        drop(ps);
+
+        match self.config_watcher.poll_midi_mapping() {
+            Some(Ok(mapping)) => {
+                self.midi_mapping = mapping;
+                self.status_message = "MIDI mapping reloaded".to_string();
+            }
+            Some(Err(e)) => {
+                log::warn!("Ignoring invalid MIDI mapping change: {}", e);
+                self.status_message = format!("Ignoring invalid MIDI mapping change: {}", e);
             }
             None => (),
         }
     }
 
+    fn toggle_ab(&mut self) {
+        let mut ps = self.ps.lock().unwrap();
+        self.ab.toggle(&mut ps.mixes);
+        if !ps.bypass {
+            ps.write_state();
+        }
+        self.status_message = format!("A/B: {:?} is live", self.ab.active);
+    }
+
+    fn copy_a_to_b(&mut self) {
+        let mut ps = self.ps.lock().unwrap();
+        if self.ab.copy_a_to_b(&mut ps.mixes) && !ps.bypass {
+            ps.write_state();
+        }
+        self.status_message = "A/B: Copied A to B".to_string();
+    }
+
+    fn save_midi_mapping(&mut self) {
+        let midi_mapping_file = self.paths.midi_mapping_file();
+        self.save_midi_mapping_to_file(&midi_mapping_file);
+    }
+
     fn save_midi_mapping_to_file(&mut self, path: &std::path::Path) {
-        self.midi_mapping.sort_mappings();
-        if let Ok(json) = serde_json::to_string_pretty(&self.midi_mapping) {
-            match File::create(path) {
-                Ok(mut file) => {
-                    if let Err(e) = file.write_all(json.as_bytes()) {
-                        self.status_message = format!("Failed to write MIDI mapping: {}", e);
-                        log::error!("Failed to write MIDI mapping to {}: {}", path.display(), e);
-                    } else {
-                        let _ = file.flush();
-                        self.status_message = format!("Saved MIDI mapping to {}", path.display());
-                        log::info!("Saved MIDI mapping to {}", path.display());
-                    }
+        match config::save_midi_mapping(&mut self.midi_mapping, path) {
+            Ok(_) => {
+                if path == self.paths.midi_mapping_file() {
+                    self.config_watcher.midi_mapping_saved();
                 }
-                Err(e) => {
-                    self.status_message = format!("Failed to create file: {}", e);
-                    log::error!("Failed to create file {}: {}", path.display(), e);
+                self.status_message = format!("Saved MIDI mapping to {}", path.display());
+                log::info!("Saved MIDI mapping to {}", path.display());
+            }
+            Err(e) => {
+                self.status_message = format!("Failed to write MIDI mapping: {}", e);
+                log::error!("Failed to write MIDI mapping to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Switch to the config and MIDI mapping of the interface found after an offline start
+    fn use_device_config(&mut self) {
+        let mut ps = self.ps.lock().unwrap();
+        if let Err(e) = self.paths.set_device(&ps.serial) {
+            log::error!("Failed to set up the config of device {}: {}", ps.serial, e);
+            self.status_message = format!("Device config not set up: {}", e);
+            return;
+        }
+        let config_file = self.paths.config_file();
+        match config::load_mixer(&mut ps, &config_file) {
+            Ok(true) => {
+                ps.replace_placeholder_names();
+                if !ps.bypass {
+                    ps.write_state();
                 }
             }
-        } else {
-            self.status_message = "Failed to serialize MIDI mapping".to_string();
-            log::error!("Failed to serialize MIDI mapping");
+            Ok(false) => (),
+            Err(e) => {
+                log::error!("Failed to load config {}: {}", config_file.display(), e);
+                self.status_message = format!("Config not loaded: {}", e);
+            }
+        }
+        self.config_watcher = config_watch::ConfigWatcher::new(&self.paths, &ps);
+        drop(ps);
+
+        let midi_mapping_file = self.paths.midi_mapping_file();
+        match config::load_json(&midi_mapping_file, |_| Vec::new()) {
+            Ok(Some(mapping)) => self.midi_mapping = mapping,
+            Ok(None) => (),
+            Err(e) => {
+                log::warn!(
+                    "Failed to parse MIDI mapping from {}: {}",
+                    midi_mapping_file.display(),
+                    e
+                );
+                self.status_message = format!("MIDI mapping not loaded: {}", e);
+            }
         }
     }
 
     fn load_midi_mapping_from_file(&mut self, path: &std::path::Path) {
-        match File::open(path) {
-            Ok(mut file) => {
-                let mut contents = String::new();
-                if let Err(e) = file.read_to_string(&mut contents) {
-                    self.status_message = format!("Failed to read file: {}", e);
-                    log::error!("Failed to read MIDI mapping from {}: {}", path.display(), e);
-                    return;
-                }
-                match serde_json::from_str::<midi_control::MidiMapping>(&contents) {
-                    Ok(mapping) => {
-                        self.midi_mapping = mapping;
-                        self.status_message = format!("Loaded MIDI mapping from {}", path.display());
-                        log::info!("Loaded MIDI mapping from {}", path.display());
-                    }
-                    Err(e) => {
-                        self.status_message = format!("Failed to parse MIDI mapping: {}", e);
-                        log::error!("Failed to parse MIDI mapping from {}: {}", path.display(), e);
-                    }
-                }
+        match config::load_json(path, |_| Vec::new()) {
+            Ok(Some(mapping)) => {
+                self.midi_mapping = mapping;
+                self.status_message = format!("Loaded MIDI mapping from {}", path.display());
+                log::info!("Loaded MIDI mapping from {}", path.display());
+            }
+            Ok(None) => {
+                self.status_message = format!("File not found: {}", path.display());
+                log::error!("MIDI mapping file {} not found", path.display());
             }
             Err(e) => {
-                self.status_message = format!("Failed to open file: {}", e);
-                log::error!("Failed to open file {}: {}", path.display(), e);
+                self.status_message = format!("Failed to load MIDI mapping: {}", e);
+                log::error!("Failed to load MIDI mapping from {}: {}", path.display(), e);
             }
         }
     }
@@ -373,23 +562,27 @@ impl BatonApp {
         ui: &mut egui::Ui,
         strip: &mut usb::Strip,
         name: &mut String,
-        meter_value: f64,
-        meter_value_right: Option<f64>,
+        meter: &usb::Meter,
+        meter_right: Option<&usb::Meter>,
         available_height: f32,
         clip_indicators: &mut HashMap<String, Instant>,
         peak_holds: &mut HashMap<String, (f64, Instant)>,
-        meter_averages: &mut HashMap<String, Vec<(f64, Instant)>>,
         meter_id: &str,
-        custom_color: Option<egui::Color32>,
+        custom_color: Option<usb::StripColor>,
     ) -> StripAction {
         let mut action = StripAction::None;
+        let meter_value = meter.value;
+        let meter_value_right = meter_right.map(|m| m.value);
 
         // Set background color - use custom color if set, otherwise default based on strip kind
-        let bg_color = custom_color.unwrap_or_else(|| match strip.kind {
+        let default_color = match strip.kind {
             usb::StripKind::Main => egui::Color32::from_rgb(80, 80, 0), // Dark yellow
             usb::StripKind::Bus => egui::Color32::from_rgb(20, 30, 50), // Dark blue
             usb::StripKind::Channel => egui::Color32::TRANSPARENT, // No background for channels
-        });
+        };
+        let bg_color = custom_color
+            .map(|c| egui::Color32::from_rgb(c.r, c.g, c.b))
+            .unwrap_or(default_color);
 
         let frame = egui::Frame::new()
             .fill(bg_color)
@@ -415,30 +608,22 @@ impl BatonApp {
                     ui.label("Choose strip color:");
                     ui.separator();
 
-                    let colors = [
-                        ("Green", egui::Color32::from_rgb(0x00, 0x17, 0x07)), // #001707
-                        ("Blue", egui::Color32::from_rgb(0x00, 0x05, 0x17)),  // #000517
-                        ("Magenta", egui::Color32::from_rgb(0x17, 0x00, 0x10)), // #170010
-                        ("Orange", egui::Color32::from_rgb(0x17, 0x12, 0x00)), // #171200
-                    ];
-
-                    for (label, color) in colors {
-                        if ui
-                            .add(
-                                egui::Button::new(label)
-                                    .fill(color)
-                                    .min_size(egui::vec2(100.0, 25.0)),
-                            )
-                            .clicked()
-                        {
-                            action = StripAction::ColorChanged(color);
-                            ui.close();
-                        }
+                    let mut color = bg_color;
+                    if egui::color_picker::color_picker_color32(
+                        ui,
+                        &mut color,
+                        egui::color_picker::Alpha::Opaque,
+                    ) {
+                        action = StripAction::ColorChanged(Some(usb::StripColor::new(
+                            color.r(),
+                            color.g(),
+                            color.b(),
+                        )));
                     }
 
                     ui.separator();
                     if ui.button("Reset to default").clicked() {
-                        action = StripAction::ColorChanged(egui::Color32::TRANSPARENT);
+                        action = StripAction::ColorChanged(None);
                         ui.close();
                     }
                 });
@@ -648,7 +833,7 @@ impl BatonApp {
                     egui::Color32::from_rgb(255, 200, 0),
                 );
 
-                // Check for clipping (meter value above -0.1 dB) and update peak holds
+                // Check for clipping (sample peak above -0.1 dB) and update peak holds
                 let clip_threshold = -0.1;
                 let now = Instant::now();
                 let peak_hold_duration = Duration::from_millis(500);
@@ -658,41 +843,28 @@ impl BatonApp {
                 let left_peak_entry = peak_holds.entry(left_key.clone()).or_insert((-50.0, now));
                 // Reset if expired, otherwise update if new value is higher
                 if now.duration_since(left_peak_entry.1) >= peak_hold_duration
-                    || meter_value > left_peak_entry.0
+                    || meter.peak > left_peak_entry.0
                 {
-                    *left_peak_entry = (meter_value, now);
+                    *left_peak_entry = (meter.peak, now);
                 }
-                if meter_value > clip_threshold {
+                if meter.peak > clip_threshold {
                     clip_indicators.insert(left_key.clone(), now);
                 }
 
-                // Update running average for left/mono channel
-                let avg_duration = Duration::from_secs(3);
-                let left_history = meter_averages.entry(left_key.clone()).or_default();
-                left_history.push((meter_value, now));
-                // Remove values older than 3 seconds
-                left_history.retain(|(_, time)| now.duration_since(*time) < avg_duration);
-
                 // Update right peak hold if stereo
-                if let Some(right_val) = meter_value_right {
+                if let Some(right) = meter_right {
                     let right_key = format!("{}_R", meter_id);
                     let right_peak_entry =
                         peak_holds.entry(right_key.clone()).or_insert((-50.0, now));
                     // Reset if expired, otherwise update if new value is higher
                     if now.duration_since(right_peak_entry.1) >= peak_hold_duration
-                        || right_val > right_peak_entry.0
+                        || right.peak > right_peak_entry.0
                     {
-                        *right_peak_entry = (right_val, now);
+                        *right_peak_entry = (right.peak, now);
                     }
-                    if right_val > clip_threshold {
+                    if right.peak > clip_threshold {
                         clip_indicators.insert(right_key.clone(), now);
                     }
-
-                    // Update running average for right channel
-                    let right_history = meter_averages.entry(right_key.clone()).or_default();
-                    right_history.push((right_val, now));
-                    // Remove values older than 3 seconds
-                    right_history.retain(|(_, time)| now.duration_since(*time) < avg_duration);
                 }
 
                 // Clear clip indicators if meter was clicked or double-clicked
@@ -788,26 +960,6 @@ impl BatonApp {
                                 egui::Stroke::new(2.0, egui::Color32::WHITE),
                             );
                         }
-
-                        // Draw running average line
-                        let avg_key = format!("{}_{}", meter_id, channel_suffix);
-                        let history = meter_averages.get(&avg_key);
-                        if let Some(history) = history.filter(|h| !h.is_empty()) {
-                            let avg_val = history.iter().map(|(val, _)| val).sum::<f64>()
-                                / history.len() as f64;
-                            if avg_val > -50.0 && avg_val < 10.0 {
-                                let avg_normalized = (avg_val + 50.0) / 60.0;
-                                let avg_y = meter_rect.max.y
-                                    - (avg_normalized * fader_height as f64) as f32;
-                                painter.line_segment(
-                                    [
-                                        egui::pos2(meter_rect.min.x, avg_y),
-                                        egui::pos2(meter_rect.max.x, avg_y),
-                                    ],
-                                    egui::Stroke::new(4.0, egui::Color32::from_rgb(79, 0, 255)),
-                                );
-                            }
-                        }
                     };
 
                 // Draw left meter (or mono meter)
@@ -929,6 +1081,153 @@ impl BatonApp {
     }
 }
 
+/// Change made to a crosspoint in the routing matrix
+enum MatrixAction {
+    Select,
+    Changed,
+    MuteToggled,
+}
+
+impl BatonApp {
+    /// Move the matrix cursor and edit the selected crosspoint with the keyboard
+    fn handle_matrix_keys(&mut self, ctx: &egui::Context) {
+        if ctx.memory(|m| m.focused().is_some()) {
+            return;
+        }
+        let (left, right, up, down, plus, minus, mute) = ctx.input(|i| {
+            (
+                i.key_pressed(egui::Key::ArrowLeft),
+                i.key_pressed(egui::Key::ArrowRight),
+                i.key_pressed(egui::Key::ArrowUp),
+                i.key_pressed(egui::Key::ArrowDown),
+                i.key_pressed(egui::Key::Plus) || i.key_pressed(egui::Key::Equals),
+                i.key_pressed(egui::Key::Minus),
+                i.key_pressed(egui::Key::M),
+            )
+        });
+
+        let mut ps = self.ps.lock().unwrap();
+        let strips = ps.channel_names.len() + 1;
+        let mixes = ps.mixes.len();
+        let d_strip = right as isize - left as isize;
+        let d_mix = down as isize - up as isize;
+        self.active_strip_index = matrix::step(self.active_strip_index, d_strip, strips);
+        self.active_mix_index = matrix::step(self.active_mix_index, d_mix, mixes);
+
+        let (mix, index) = (self.active_mix_index, self.active_strip_index);
+        let strip = ps.mixes[mix].strips.iter_mut().nth(index).unwrap();
+        if plus || minus {
+            let fader = strip.fader + if plus { 1.0 } else { -1.0 };
+            strip.set_fader(fader);
+            ps.write_channel_fader(mix, index);
+        }
+        if mute {
+            Self::toggle_crosspoint_mute(&mut ps, mix, index);
+        }
+    }
+
+    fn toggle_crosspoint_mute(ps: &mut usb::PreSonusStudio1824c, mix: usize, index: usize) {
+        let strip = ps.mixes[mix].strips.iter_mut().nth(index).unwrap();
+        if strip.kind == usb::StripKind::Main {
+            let main_mute = ps.main_mute;
+            ps.set_main_mute(!main_mute);
+        } else {
+            strip.mute = !strip.mute;
+            ps.write_channel_fader(mix, index);
+        }
+    }
+
+    /// All mixes as rows and all strips as columns, with level, pan and mute of each crosspoint
+    fn draw_matrix(&mut self, ui: &mut egui::Ui) {
+        let mut ps = self.ps.lock().unwrap();
+        let channel_names = ps.channel_labels();
+        let mut actions = Vec::new();
+
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("routing_matrix")
+                .striped(true)
+                .spacing([4.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("");
+                    for name in channel_names.iter().map(String::as_str).chain(["Out"]) {
+                        ui.label(egui::RichText::new(name).small().strong());
+                    }
+                    ui.end_row();
+
+                    for (m, mix) in ps.mixes.iter_mut().enumerate() {
+                        ui.label(egui::RichText::new(&mix.name).strong());
+                        for (s, strip) in mix.strips.iter_mut().enumerate() {
+                            let selected =
+                                m == self.active_mix_index && s == self.active_strip_index;
+                            let fill = if selected {
+                                egui::Color32::DARK_GREEN
+                            } else if strip.solo {
+                                egui::Color32::from_rgb(100, 100, 0)
+                            } else {
+                                egui::Color32::TRANSPARENT
+                            };
+                            let cell = egui::Frame::new()
+                                .fill(fill)
+                                .inner_margin(egui::Margin::same(2))
+                                .show(ui, |ui| {
+                                    ui.set_width(48.0);
+                                    let min = strip.min;
+                                    let level = ui.add(
+                                        egui::DragValue::new(&mut strip.fader)
+                                            .range(strip.min..=strip.max)
+                                            .speed(0.1)
+                                            .custom_formatter(|v, _| matrix::format_level(v, min)),
+                                    );
+                                    let pan = if strip.kind == usb::StripKind::Channel {
+                                        ui.add(
+                                            egui::DragValue::new(&mut strip.balance)
+                                                .range(-100.0..=100.0)
+                                                .speed(1.0)
+                                                .custom_formatter(|v, _| matrix::format_pan(v)),
+                                        )
+                                    } else {
+                                        ui.label("")
+                                    };
+                                    let muted = strip.mute || strip.mute_by_solo;
+                                    let mute =
+                                        ui.add(egui::Button::new("M").small().fill(if muted {
+                                            egui::Color32::RED
+                                        } else {
+                                            egui::Color32::DARK_GRAY
+                                        }));
+
+                                    if level.changed() || pan.changed() {
+                                        actions.push((m, s, MatrixAction::Changed));
+                                    }
+                                    if mute.clicked() {
+                                        actions.push((m, s, MatrixAction::MuteToggled));
+                                    }
+                                    if level.clicked() || level.drag_started() || pan.drag_started()
+                                    {
+                                        actions.push((m, s, MatrixAction::Select));
+                                    }
+                                });
+                            if cell.response.interact(egui::Sense::click()).clicked() {
+                                actions.push((m, s, MatrixAction::Select));
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+
+        for (mix, index, action) in actions {
+            self.active_mix_index = mix;
+            self.active_strip_index = index;
+            match action {
+                MatrixAction::Select => {}
+                MatrixAction::Changed => ps.write_channel_fader(mix, index),
+                MatrixAction::MuteToggled => Self::toggle_crosspoint_mute(&mut ps, mix, index),
+            }
+        }
+    }
+}
+
 impl eframe::App for BatonApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Check for MIDI learn timeout (5 seconds)
@@ -945,9 +1244,24 @@ impl eframe::App for BatonApp {
         // Poll device state periodically
         if self.last_tick.elapsed() >= self.tick_rate {
             let mut ps = self.ps.lock().unwrap();
+            let online = ps.is_online();
+            let serial_known = !ps.serial.is_empty();
             ps.poll_state();
+            let found_serial = !serial_known && !ps.serial.is_empty();
+            if ps.is_online() != online {
+                self.status_message = if online {
+                    "Interface disconnected, waiting for it to reconnect".to_string()
+                } else {
+                    "Interface reconnected".to_string()
+                };
+            }
             drop(ps);
+            if found_serial {
+                self.use_device_config();
+            }
+            self.process_alerts();
             self.process_midi_messages();
+            self.process_config_changes();
             self.last_tick = Instant::now();
         }
 
@@ -982,12 +1296,55 @@ impl eframe::App for BatonApp {
                     }
                 });
 
+                ui.menu_button("Device", |ui| {
+                    let current = self.ps.lock().unwrap().serial.clone();
+                    for (i, entry) in self.devices.iter().enumerate() {
+                        // Interfaces without a serial of their own are picked by number
+                        let same_serial = self
+                            .devices
+                            .iter()
+                            .filter(|d| d.serial == entry.serial)
+                            .count();
+                        let unique = !entry.serial.is_empty() && same_serial == 1;
+                        let selected = unique && entry.serial == current;
+                        let serial = device::display_serial(&entry.serial);
+                        let label = format!("{} ({})", serial, entry.product);
+                        if ui.radio(selected, label).clicked() {
+                            if !selected {
+                                let selector = match unique {
+                                    true => entry.serial.clone(),
+                                    false => (i + 1).to_string(),
+                                };
+                                // The window is closed and the GUI restarted with the device
+                                *self.switch_device.lock().unwrap() = Some(selector);
+                                ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
+                            }
+                            ui.close();
+                        }
+                    }
+                    ui.separator();
+                    if ui.button("Refresh").clicked() {
+                        match device::list() {
+                            Ok(devices) => self.devices = devices,
+                            Err(e) => {
+                                log::error!("Failed to list devices: {}", e);
+                                self.status_message = format!("Devices not listed: {}", e);
+                            }
+                        }
+                    }
+                });
+
                 ui.separator();
             });
 
             ui.horizontal(|ui| {
                 ui.heading("Baton Mixer");
 
+                if !self.ps.lock().unwrap().is_online() {
+                    ui.colored_label(egui::Color32::RED, "OFFLINE")
+                        .on_hover_text("The interface is not connected, waiting for it");
+                }
+
                 ui.separator();
 
                 // Mix selector
@@ -1058,23 +1415,101 @@ impl eframe::App for BatonApp {
                 }
 
                 if ui
-                    .add(egui::Button::new("Bypass").fill(if self.bypass {
+                    .add(egui::Button::new("Bypass").fill(if ps.bypass {
                         egui::Color32::LIGHT_BLUE
                     } else {
                         egui::Color32::DARK_GRAY
                     }))
                     .clicked()
                 {
-                    self.bypass = !self.bypass;
-                    if self.bypass {
+                    ps.bypass = !ps.bypass;
+                    if ps.bypass {
                         ps.bypass_mixer();
                     } else {
                         ps.write_state();
                     }
                 }
 
+                drop(ps);
+
+                ui.separator();
+
+                // A/B compare
+                for slot in [ab::Slot::A, ab::Slot::B] {
+                    let live = self.ab.active == slot;
+                    if ui
+                        .add(egui::Button::new(format!("{:?}", slot)).fill(if live {
+                            egui::Color32::DARK_GREEN
+                        } else {
+                            egui::Color32::DARK_GRAY
+                        }))
+                        .clicked()
+                        && !live
+                    {
+                        self.toggle_ab();
+                    }
+                }
+                if ui.button("A → B").clicked() {
+                    self.copy_a_to_b();
+                }
+
                 ui.separator();
 
+                if ui
+                    .add(egui::Button::new("Matrix").fill(if self.show_matrix {
+                        egui::Color32::DARK_GREEN
+                    } else {
+                        egui::Color32::DARK_GRAY
+                    }))
+                    .clicked()
+                {
+                    self.show_matrix = !self.show_matrix;
+                }
+
+                if ui
+                    .add(
+                        egui::Button::new("Post-fader").fill(if self.post_fader_meters {
+                            egui::Color32::DARK_GREEN
+                        } else {
+                            egui::Color32::DARK_GRAY
+                        }),
+                    )
+                    .on_hover_text("Show the estimated level of each input in this mix")
+                    .clicked()
+                {
+                    self.post_fader_meters = !self.post_fader_meters;
+                }
+
+                if let Some(alerts) = &mut self.alerts
+                    && ui
+                        .checkbox(&mut alerts.armed, "Silence alerts")
+                        .on_hover_text("Raise the silence alerts of alerts.json")
+                        .changed()
+                {
+                    self.status_message = if alerts.armed {
+                        "Silence alerts armed".to_string()
+                    } else {
+                        "Silence alerts disarmed".to_string()
+                    };
+                }
+
+                if ui
+                    .add(
+                        egui::Button::new("Meter Bridge").fill(if self.meter_bridge.open {
+                            egui::Color32::DARK_GREEN
+                        } else {
+                            egui::Color32::DARK_GRAY
+                        }),
+                    )
+                    .clicked()
+                {
+                    self.meter_bridge.open = !self.meter_bridge.open;
+                }
+
+                ui.separator();
+
+                let mut ps = self.ps.lock().unwrap();
+
                 // Reset solo button
                 let solo_exists = ps.mixes[self.active_mix_index].has_solo();
                 if ui
@@ -1126,10 +1561,26 @@ impl eframe::App for BatonApp {
         // Status bar
         egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.label(&self.status_message);
+                let flashing = self.alert_time.is_some_and(|time| {
+                    let elapsed = time.elapsed();
+                    elapsed < alerts::FLASH_TIME && elapsed.as_millis() / 250 % 2 == 0
+                });
+                if flashing {
+                    ui.colored_label(egui::Color32::RED, &self.status_message);
+                } else {
+                    ui.label(&self.status_message);
+                }
             });
         });
 
+        self.meter_bridge.show(ctx, &self.ps);
+
+        if self.show_matrix {
+            self.handle_matrix_keys(ctx);
+            egui::CentralPanel::default().show(ctx, |ui| self.draw_matrix(ui));
+            return;
+        }
+
         let mut strip_actions = Vec::new();
 
         egui::SidePanel::right("right_panel")
@@ -1142,8 +1593,8 @@ impl eframe::App for BatonApp {
                 let ps = self.ps.lock().unwrap();
                 let bus_name = ps.mixes[self.active_mix_index].name.clone();
                 // Get both left and right bus meters
-                let bus_meter_left = ps.bus_meters[self.active_mix_index * 2].value;
-                let bus_meter_right = ps.bus_meters[self.active_mix_index * 2 + 1].value;
+                let bus_meter_left = ps.bus_meters[self.active_mix_index * 2].clone();
+                let bus_meter_right = ps.bus_meters[self.active_mix_index * 2 + 1].clone();
                 drop(ps);
 
                 let mut ps = self.ps.lock().unwrap();
@@ -1154,18 +1605,16 @@ impl eframe::App for BatonApp {
                 let mut bus_name_mut = bus_name.clone();
                 let meter_id = format!("bus_{}", self.active_mix_index);
                 let bus_strip_index = mix.strips.channel_strips.len();
-                let strip_id = format!("{}:{}", self.active_mix_index, bus_strip_index);
-                let custom_color = self.strip_colors.get(&strip_id).copied();
+                let custom_color = mix.color;
                 let bus_action = Self::draw_strip(
                     ui,
                     bus_strip,
                     &mut bus_name_mut,
-                    bus_meter_left,
-                    Some(bus_meter_right),
+                    &bus_meter_left,
+                    Some(&bus_meter_right),
                     available_height,
                     &mut self.clip_indicators,
                     &mut self.peak_holds,
-                    &mut self.meter_averages,
                     &meter_id,
                     custom_color,
                 );
@@ -1183,33 +1632,40 @@ impl eframe::App for BatonApp {
                 ui.horizontal(|ui| {
                     // Collect strip data
                     let ps = self.ps.lock().unwrap();
-                    let strip_data: Vec<(String, f64)> = ps
-                        .channel_names
-                        .iter()
+                    let strip_data: Vec<(String, usb::Meter, Option<usb::StripColor>)> = ps
+                        .channel_labels()
+                        .into_iter()
                         .zip(ps.channel_meters.iter())
-                        .map(|(name, meter)| (name.clone(), meter.value))
+                        .zip(ps.channel_colors.iter())
+                        .map(|((name, meter), color)| (name, meter.clone(), *color))
                         .collect();
                     drop(ps);
 
                     let mut ps = self.ps.lock().unwrap();
+                    let pan_law = ps.pan_law;
                     let mix = &mut ps.mixes[self.active_mix_index];
 
-                    // Draw channel strips (mono - no right meter)
+                    // Draw channel strips, mono or with the estimated levels in the mix
                     for (i, strip) in mix.strips.channel_strips.iter_mut().enumerate() {
-                        let (mut name, meter_value) = strip_data[i].clone();
+                        let (mut name, meter, custom_color) = strip_data[i].clone();
+                        let (meter, meter_right) = if self.post_fader_meters {
+                            let gains = metering::post_fader_gains(strip, pan_law);
+                            let left = meter.with_gain(gains.map(|(left, _)| left));
+                            let right = meter.with_gain(gains.map(|(_, right)| right));
+                            (left, Some(right))
+                        } else {
+                            (meter, None)
+                        };
                         let meter_id = format!("ch_{}", i);
-                        let strip_id = format!("{}:{}", self.active_mix_index, i);
-                        let custom_color = self.strip_colors.get(&strip_id).copied();
                         let action = Self::draw_strip(
                             ui,
                             strip,
                             &mut name,
-                            meter_value,
-                            None,
+                            &meter,
+                            meter_right.as_ref(),
                             available_height,
                             &mut self.clip_indicators,
                             &mut self.peak_holds,
-                            &mut self.meter_averages,
                             &meter_id,
                             custom_color,
                         );
@@ -1295,13 +1751,11 @@ impl eframe::App for BatonApp {
                     }
                 }
                 StripAction::ColorChanged(color) => {
-                    let strip_id = format!("{}:{}", self.active_mix_index, strip_index);
-                    if color == egui::Color32::TRANSPARENT {
-                        // Reset to default - remove custom color
-                        self.strip_colors.remove(&strip_id);
+                    // Channel colours are shared by all mixes, bus colours belong to the mix
+                    if strip_index < ps.channel_colors.len() {
+                        ps.channel_colors[strip_index] = color;
                     } else {
-                        // Set custom color
-                        self.strip_colors.insert(strip_id, color);
+                        ps.mixes[self.active_mix_index].color = color;
                     }
                 }
                 StripAction::None => {}
@@ -1313,21 +1767,17 @@ impl eframe::App for BatonApp {
         log::info!("Saving configuration...");
 
         // Save config
-        match self.config_dir {
-            Some(ref dir) => {
-                let config_file = dir.join("config.json");
-                {
-                    let ps = self.ps.lock().unwrap();
-                    if let Ok(serialized) = serde_json::to_string_pretty(&*ps) {
-                        if let Ok(mut file) = File::create(&config_file) {
-                            let _ = file.write_all(serialized.as_bytes());
-                            let _ = file.flush();
-                        }
-                    }
-                }
-            }
+        let mut ps = self.ps.lock().unwrap();
+        match self.config_watcher.save_config(&ps) {
+            Ok(path) => log::info!("Saved config to {}", path.display()),
+            Err(e) => log::error!("Failed to save config: {}", e),
+        }
+        match ps.stop_meter_log() {
+            Some(Ok(path)) => log::info!("Saved meter log summary to {}", path.display()),
+            Some(Err(e)) => log::error!("Failed to save meter log summary: {}", e),
             None => (),
-        };
+        }
+        drop(ps);
 
         // Save MIDI mapping
         self.save_midi_mapping();
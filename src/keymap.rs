@@ -0,0 +1,531 @@
+use crate::config::ConfigError;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const KEYMAP_FILE: &str = "keymap.json";
+
+/// Named actions that keys can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Help,
+    CommandLine,
+    Rename,
+    StripLeft,
+    StripRight,
+    FaderUp,
+    FaderDown,
+    FaderUpFine,
+    FaderDownFine,
+    FaderUpCoarse,
+    FaderDownCoarse,
+    PanLeft,
+    PanRight,
+    PanLeftCoarse,
+    PanRightCoarse,
+    PanCenter,
+    Mute,
+    Solo,
+    Bypass,
+    AbToggle,
+    AbCopy,
+    ClearClips,
+    ClearStrip,
+    Phantom,
+    Line,
+    MainMute,
+    MainMono,
+    LearnFader,
+    LearnBalance,
+    LearnMute,
+    LearnSolo,
+    Cancel,
+    StripWider,
+    StripNarrower,
+    MeterGrow,
+    MeterShrink,
+    Matrix,
+    BusMeters,
+    PostFaderMeters,
+    MixPrevious,
+    MixNext,
+}
+
+/// Actions with their names in the keymap file and descriptions, in help order
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::Quit, "quit", "Quit"),
+    (Action::Help, "help", "Show key bindings"),
+    (Action::CommandLine, "command-line", "Enter command mode"),
+    (Action::Rename, "rename", "Rename strip"),
+    (Action::StripLeft, "strip-left", "Select strip to the left"),
+    (
+        Action::StripRight,
+        "strip-right",
+        "Select strip to the right",
+    ),
+    (Action::FaderUp, "fader-up", "Volume +1 dB"),
+    (Action::FaderDown, "fader-down", "Volume -1 dB"),
+    (Action::FaderUpFine, "fader-up-fine", "Volume +0.1 dB"),
+    (Action::FaderDownFine, "fader-down-fine", "Volume -0.1 dB"),
+    (Action::FaderUpCoarse, "fader-up-coarse", "Volume +10 dB"),
+    (
+        Action::FaderDownCoarse,
+        "fader-down-coarse",
+        "Volume -10 dB",
+    ),
+    (Action::PanLeft, "pan-left", "Pan left 1"),
+    (Action::PanRight, "pan-right", "Pan right 1"),
+    (Action::PanLeftCoarse, "pan-left-coarse", "Pan left 10"),
+    (Action::PanRightCoarse, "pan-right-coarse", "Pan right 10"),
+    (Action::PanCenter, "pan-center", "Center pan"),
+    (Action::Mute, "mute", "Mute"),
+    (Action::Solo, "solo", "Solo"),
+    (Action::Bypass, "bypass", "Bypass"),
+    (Action::AbToggle, "ab-toggle", "Toggle A/B mixer state"),
+    (Action::AbCopy, "ab-copy", "Copy A to B"),
+    (Action::ClearClips, "clear-clips", "Clear clip indicators"),
+    (
+        Action::ClearStrip,
+        "clear-strip",
+        "Clear meters of the active strip",
+    ),
+    (Action::Phantom, "phantom", "Toggle 48V phantom power"),
+    (Action::Line, "line", "Toggle 1-2 line input mode"),
+    (Action::MainMute, "main-mute", "Toggle Main output mute"),
+    (Action::MainMono, "main-mono", "Toggle Main output mono"),
+    (Action::LearnFader, "learn-fader", "MIDI Learn - Fader"),
+    (
+        Action::LearnBalance,
+        "learn-balance",
+        "MIDI Learn - Balance",
+    ),
+    (Action::LearnMute, "learn-mute", "MIDI Learn - Mute"),
+    (Action::LearnSolo, "learn-solo", "MIDI Learn - Solo"),
+    (Action::Cancel, "cancel", "Cancel MIDI Learn"),
+    (Action::StripWider, "strip-wider", "Wider strips"),
+    (Action::StripNarrower, "strip-narrower", "Narrower strips"),
+    (Action::MeterGrow, "meter-grow", "Taller meters"),
+    (Action::MeterShrink, "meter-shrink", "Shorter meters"),
+    (Action::Matrix, "matrix", "Toggle routing matrix"),
+    (Action::BusMeters, "bus-meters", "Toggle output meters"),
+    (
+        Action::PostFaderMeters,
+        "post-fader-meters",
+        "Toggle post-fader input meters",
+    ),
+    (Action::MixPrevious, "mix-previous", "Select previous mix"),
+    (Action::MixNext, "mix-next", "Select next mix"),
+];
+
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("q", "quit"),
+    ("?", "help"),
+    (":", "command-line"),
+    ("r", "rename"),
+    ("left", "strip-left"),
+    ("right", "strip-right"),
+    ("up", "fader-up"),
+    ("down", "fader-down"),
+    ("shift+up", "fader-up-fine"),
+    ("shift+down", "fader-down-fine"),
+    ("ctrl+up", "fader-up-coarse"),
+    ("ctrl+down", "fader-down-coarse"),
+    ("x", "pan-left"),
+    ("v", "pan-right"),
+    ("ctrl+x", "pan-left-coarse"),
+    ("ctrl+v", "pan-right-coarse"),
+    ("c", "pan-center"),
+    ("m", "mute"),
+    ("s", "solo"),
+    ("b", "bypass"),
+    ("a", "ab-toggle"),
+    ("A", "ab-copy"),
+    ("space", "clear-clips"),
+    ("C", "clear-strip"),
+    ("p", "phantom"),
+    ("l", "line"),
+    ("u", "main-mute"),
+    ("o", "main-mono"),
+    ("F", "learn-fader"),
+    ("B", "learn-balance"),
+    ("M", "learn-mute"),
+    ("S", "learn-solo"),
+    ("esc", "cancel"),
+    ("ctrl+right", "strip-wider"),
+    ("ctrl+left", "strip-narrower"),
+    ("pagedown", "meter-grow"),
+    ("pageup", "meter-shrink"),
+    ("tab", "matrix"),
+    ("O", "bus-meters"),
+    ("P", "post-fader-meters"),
+    ("[", "mix-previous"),
+    ("]", "mix-next"),
+    ("1", ":mix 1"),
+    ("2", ":mix 2"),
+    ("3", ":mix 3"),
+    ("4", ":mix 4"),
+    ("5", ":mix 5"),
+    ("6", ":mix 6"),
+    ("7", ":mix 7"),
+    ("8", ":mix 8"),
+    ("9", ":mix 9"),
+];
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS
+            .iter()
+            .find(|(_, name, _)| *name == s)
+            .map(|(action, _, _)| *action)
+            .ok_or_else(|| format!("unknown action '{}'", s))
+    }
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(action, _, _)| *action == self)
+            .map_or("", |(_, _, description)| description)
+    }
+}
+
+/// What a key does: a named action, or a command line like `:mute`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Action(Action),
+    Command(String),
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(':') {
+            Ok(Binding::Command(s.to_string()))
+        } else {
+            s.parse().map(Binding::Action)
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Action(action) => write!(f, "{}", action.description()),
+            Binding::Command(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+/// A key with modifiers, like `ctrl+x`.
+/// Shift is part of the character for character keys, `shift+a` is `A`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    fn new(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        KeyChord { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(event: KeyEvent) -> Self {
+        KeyChord::new(event.code, event.modifiers)
+    }
+}
+
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Up, "up"),
+    (KeyCode::Down, "down"),
+    (KeyCode::Left, "left"),
+    (KeyCode::Right, "right"),
+    (KeyCode::PageUp, "pageup"),
+    (KeyCode::PageDown, "pagedown"),
+    (KeyCode::Home, "home"),
+    (KeyCode::End, "end"),
+    (KeyCode::Insert, "insert"),
+    (KeyCode::Delete, "delete"),
+    (KeyCode::Backspace, "backspace"),
+    (KeyCode::Enter, "enter"),
+    (KeyCode::Tab, "tab"),
+    (KeyCode::Esc, "esc"),
+    (KeyCode::Char(' '), "space"),
+];
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key '{}'", s);
+        // Split at `+`, except for the `+` key itself
+        let (modifier_names, key) = match s.rsplit_once('+') {
+            _ if s == "+" => ("", s),
+            Some((modifiers, "")) => (modifiers.strip_suffix('+').ok_or_else(invalid)?, "+"),
+            Some((modifiers, key)) => (modifiers, key),
+            None => ("", s),
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for name in modifier_names.split('+').filter(|n| !n.is_empty()) {
+            modifiers |= match name.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+
+        let lower = key.to_ascii_lowercase();
+        let mut chars = key.chars();
+        let code = if let Some(&(code, _)) = KEY_NAMES.iter().find(|(_, name)| *name == lower) {
+            code
+        } else if let (Some(c), None) = (chars.next(), chars.next()) {
+            if modifiers.contains(KeyModifiers::SHIFT) {
+                KeyCode::Char(c.to_ascii_uppercase())
+            } else {
+                KeyCode::Char(c)
+            }
+        } else if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            KeyCode::F(n)
+        } else {
+            return Err(invalid());
+        };
+
+        Ok(KeyChord::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => {
+                let name = KEY_NAMES
+                    .iter()
+                    .find(|(c, _)| *c == code)
+                    .map_or("?", |(_, name)| name);
+                let mut chars = name.chars();
+                let first = chars.next().map(|c| c.to_ascii_uppercase());
+                write!(f, "{}{}", first.unwrap_or_default(), chars.as_str())
+            }
+        }
+    }
+}
+
+/// Key bindings of the TUI
+pub struct Keymap {
+    bindings: HashMap<KeyChord, Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(key, binding)| (key.parse().unwrap(), binding.parse().unwrap()))
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// The default bindings, changed by the keymap file in `dir` if there is one.
+    /// The file maps keys to action names or command lines,
+    /// and `null` removes a default binding.
+    pub fn load(dir: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(dir.join(KEYMAP_FILE)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Keymap::default()),
+            Err(e) => Err(e.into()),
+            Ok(contents) => Keymap::parse(&contents),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let entries: BTreeMap<String, Option<String>> = serde_json::from_str(contents)?;
+        let mut keymap = Keymap::default();
+        let mut errors = Vec::new();
+        let mut seen = HashMap::new();
+
+        for (key, binding) in entries {
+            let field_error = |message: String| crate::config::FieldError {
+                path: key.clone(),
+                message,
+            };
+            let chord = match key.parse::<KeyChord>() {
+                Ok(chord) => chord,
+                Err(e) => {
+                    errors.push(field_error(e));
+                    continue;
+                }
+            };
+            if let Some(other) = seen.insert(chord, key.clone()) {
+                errors.push(field_error(format!("same key as '{}'", other)));
+                continue;
+            }
+            match binding.map(|b| b.parse::<Binding>()) {
+                None => {
+                    keymap.bindings.remove(&chord);
+                }
+                Some(Ok(binding)) => {
+                    keymap.bindings.insert(chord, binding);
+                }
+                Some(Err(e)) => errors.push(field_error(e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(keymap)
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    pub fn get(&self, event: KeyEvent) -> Option<&Binding> {
+        self.bindings.get(&KeyChord::from(event))
+    }
+
+    /// Keys bound to an action, sorted for display
+    fn keys_for(&self, binding: &Binding) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, b)| *b == binding)
+            .map(|(chord, _)| chord.to_string())
+            .collect();
+        keys.sort_by_key(|k| (k.len(), k.clone()));
+        keys
+    }
+
+    /// The first key bound to an action, for hints
+    pub fn key_for(&self, action: Action) -> Option<String> {
+        self.keys_for(&Binding::Action(action)).into_iter().next()
+    }
+
+    /// Bindings as (keys, description), actions first and then commands
+    pub fn help(&self) -> Vec<(String, String)> {
+        let mut commands: Vec<&String> = self
+            .bindings
+            .values()
+            .filter_map(|b| match b {
+                Binding::Command(command) => Some(command),
+                Binding::Action(_) => None,
+            })
+            .collect();
+        commands.sort();
+        commands.dedup();
+
+        ACTIONS
+            .iter()
+            .map(|(action, _, _)| Binding::Action(*action))
+            .chain(commands.into_iter().map(|c| Binding::Command(c.clone())))
+            .filter_map(|binding| {
+                let keys = self.keys_for(&binding);
+                (!keys.is_empty()).then(|| (keys.join(", "), binding.to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_parse_chords() {
+        let chord = |s: &str| s.parse::<KeyChord>().unwrap();
+        assert_eq!(
+            chord("ctrl+x"),
+            KeyChord::new(KeyCode::Char('x'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(chord("shift+a"), chord("A"));
+        assert_eq!(chord("Shift+Up").to_string(), "Shift+Up");
+        assert_eq!(chord("ctrl++").to_string(), "Ctrl++");
+        assert_eq!(chord("+").to_string(), "+");
+        assert_eq!(chord("f5").to_string(), "F5");
+        assert_eq!(chord("space").to_string(), "Space");
+        assert!("hyper+x".parse::<KeyChord>().is_err());
+        assert!("foo".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.bindings.len(), DEFAULT_BINDINGS.len());
+        assert_eq!(
+            keymap.get(event(KeyCode::Char('F'), KeyModifiers::SHIFT)),
+            Some(&Binding::Action(Action::LearnFader))
+        );
+        assert_eq!(
+            keymap.get(event(KeyCode::Down, KeyModifiers::CONTROL)),
+            Some(&Binding::Action(Action::FaderDownCoarse))
+        );
+        assert_eq!(
+            keymap.get(event(KeyCode::Char('3'), KeyModifiers::NONE)),
+            Some(&Binding::Command(":mix 3".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_keymap() {
+        let keymap = Keymap::parse(
+            r#"{ "p": null, "ctrl+p": "phantom", "f5": ":scene load verse", "?": "quit" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            keymap.get(event(KeyCode::Char('p'), KeyModifiers::NONE)),
+            None
+        );
+        assert_eq!(keymap.key_for(Action::Phantom), Some("Ctrl+p".to_string()));
+        assert_eq!(keymap.key_for(Action::Help), None);
+        assert_eq!(keymap.key_for(Action::Quit), Some("?".to_string()));
+        assert!(
+            keymap
+                .help()
+                .contains(&("F5".to_string(), ":scene load verse".to_string()))
+        );
+        assert!(
+            keymap
+                .help()
+                .contains(&("?, q".to_string(), "Quit".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_keymap_errors() {
+        let errors =
+            match Keymap::parse(r#"{ "ctrl+x": "pan", "hyper+x": "mute", "control+x": null }"#) {
+                Err(ConfigError::Invalid(errors)) => errors,
+                _ => panic!("Expected Invalid"),
+            };
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["ctrl+x", "hyper+x"]);
+    }
+}
@@ -2,29 +2,49 @@ use flexi_logger::{FileSpec, detailed_format};
 use pan::Pan;
 use ratatui::{
     DefaultTerminal, Frame,
-    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    layout::{Constraint, Layout, Margin, Rect},
+    crossterm::event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        MouseButton, MouseEvent, MouseEventKind,
+    },
+    crossterm::execute,
+    layout::{Constraint, Layout, Margin, Position, Rect},
     style::{Color, Style, Stylize},
     text::{Line, Span},
-    widgets::{Bar, BarChart, BarGroup, Block, Paragraph},
+    widgets::{Bar, BarChart, BarGroup, Block, Clear, Paragraph},
 };
-use std::{
-    env,
-    fs::File,
-    io::{Read, Write},
-    time::{Duration, Instant},
-};
-use std::{io, path::Path};
+use std::io;
+use std::time::{Duration, Instant};
 use tui_input::Input;
 use tui_input::backend::crossterm::EventHandler;
 use usb::StripKind;
 
+use crate::metering::{Scale, Zone, post_fader_gains};
 use crate::midi_control::{GlobalControl, StripTarget};
 
+mod ab;
+mod access;
+mod alerts;
+mod cli;
+mod command;
+mod config;
+mod config_watch;
+mod connection;
+mod dbus;
+mod device;
+mod keymap;
+mod matrix;
+mod meter_log;
+mod metering;
+mod metrics;
 mod midi;
 mod midi_control;
+mod mouse;
 mod pan;
+mod ports;
+mod scene;
+mod script;
 mod usb;
+mod web;
 
 fn main() -> io::Result<()> {
     let _logger = flexi_logger::Logger::try_with_env()
@@ -36,13 +56,48 @@ fn main() -> io::Result<()> {
         .unwrap();
 
     log::info!("Starting Baton");
+    let paths = config::Paths::from_args();
+    if let Err(e) = paths.init() {
+        log::error!(
+            "Failed to initialize config directory {}: {}",
+            paths.dir().display(),
+            e
+        );
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let device = config::device_arg(args.iter().cloned());
+    match cli::parse(&args) {
+        Ok(None) => (),
+        Ok(Some(action)) => {
+            if let Err(e) = cli::run(action, &paths, device.as_deref()) {
+                log::error!("Command failed: {}", e);
+                eprintln!("baton: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("baton: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+
     let mut terminal = ratatui::init();
-    let app_result = App::new().run(&mut terminal);
+    if let Err(e) = execute!(io::stdout(), EnableMouseCapture) {
+        log::warn!("Failed to enable mouse capture: {}", e);
+    }
+    let app_result = App::new(paths, device.as_deref()).run(&mut terminal);
+    let _ = execute!(io::stdout(), DisableMouseCapture);
     ratatui::restore();
     log::info!("Ending Baton");
     app_result
 }
 
+/// Columns of the mix names and crosspoints in the routing matrix
+const MATRIX_NAME_WIDTH: u16 = 10;
+const MATRIX_CELL_WIDTH: u16 = 6;
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 enum InputMode {
     #[default]
@@ -62,16 +117,34 @@ pub struct App {
     ps: usb::PreSonusStudio1824c,
     tick_rate: Duration,
     last_tick: Instant,
-    bypass: bool,
     input: Input,
     input_mode: InputMode,
     midi_input: Option<midi::MidiInput>,
     midi_mapping: midi_control::MidiMapping,
     midi_learn_state: midi_control::MidiLearnState,
+    ab: ab::AbCompare,
+    paths: config::Paths,
+    config_watcher: config_watch::ConfigWatcher,
+    script: script::ScriptHost,
+    command_history: command::History,
+    keymap: keymap::Keymap,
+    alerts: Option<alerts::Alerts>,
+    /// When the last alert was raised, to flash the status line
+    alert_time: Option<Instant>,
+    /// Scroll position of the key bindings overlay, when shown
+    help_scroll: Option<u16>,
+    mouse_areas: mouse::MouseAreas,
+    drag: Option<mouse::Drag>,
+    /// Show the routing matrix instead of the strips of the active mix
+    matrix: bool,
+    /// Show the meters of all outputs instead of the input meters
+    bus_meters: bool,
+    /// Show the estimated level of each input in the active mix
+    post_fader_meters: bool,
 }
 
 impl App {
-    fn new() -> Self {
+    fn new(mut paths: config::Paths, device: Option<&str>) -> Self {
         let midi_input = match midi::MidiInput::new() {
             Ok(m) => {
                 log::info!("MIDI input initialized");
@@ -83,19 +156,62 @@ impl App {
             }
         };
 
+        let mut status_line = String::with_capacity(256);
+        let mut ps = usb::PreSonusStudio1824c::new(device).unwrap_or_else(|e| {
+            log::error!("Failed to open device: {}", e);
+            status_line = format!("Interface offline, waiting for it: {}", e);
+            usb::PreSonusStudio1824c::offline(device)
+        });
+        if let Err(e) = paths.set_device(&ps.serial) {
+            log::error!("Failed to set up the config of device {}: {}", ps.serial, e);
+            status_line = format!("Device config not set up: {}", e);
+        }
+
         // Load or create MIDI mapping
-        let midi_mapping_file = match env::var("HOME") {
-            Ok(h) => format!("{h}/.baton_midi_mapping.json"),
-            Err(_) => ".baton_midi_mapping.json".to_string(),
+        let midi_mapping = match config::load_json(&paths.midi_mapping_file(), |_| Vec::new()) {
+            Ok(Some(mapping)) => mapping,
+            Ok(None) => midi_control::MidiMapping::create_default(),
+            Err(e) => {
+                log::warn!("Failed to load MIDI mapping: {}", e);
+                midi_control::MidiMapping::create_default()
+            }
         };
 
-        let midi_mapping = if let Ok(mut file) = File::open(&midi_mapping_file) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).ok();
-            serde_json::from_str(&contents)
-                .unwrap_or_else(|_| midi_control::MidiMapping::create_default())
-        } else {
-            midi_control::MidiMapping::create_default()
+        // Load config
+        let config_file = paths.config_file();
+        match config::load_mixer(&mut ps, &config_file) {
+            Ok(true) => ps.write_state(),
+            Ok(false) => (),
+            Err(e) => {
+                log::error!("Failed to load config {}: {}", config_file.display(), e);
+                status_line = format!("Config not loaded: {}", e);
+            }
+        }
+        if let Some(message) = ps.start_services(&paths) {
+            status_line = message;
+        }
+        let config_watcher = config_watch::ConfigWatcher::new(&paths, &ps);
+        let script = script::ScriptHost::new(paths.dir());
+        let keymap = match keymap::Keymap::load(paths.dir()) {
+            Ok(keymap) => keymap,
+            Err(e) => {
+                log::error!("Failed to load keymap: {}", e);
+                status_line = format!("Keymap not loaded: {}", e);
+                keymap::Keymap::default()
+            }
+        };
+        let alerts = match alerts::Alerts::load(
+            paths.dir(),
+            ps.channel_meters.len(),
+            ps.mixes.len(),
+            true,
+        ) {
+            Ok(alerts) => alerts,
+            Err(e) => {
+                log::error!("Failed to load alerts: {}", e);
+                status_line = format!("Alerts not loaded: {}", e);
+                None
+            }
         };
 
         let mut app = App {
@@ -105,18 +221,33 @@ impl App {
             first_strip_index: 0,
             strip_width: 5,
             meter_heigth: 20,
-            status_line: String::with_capacity(256),
-            ps: usb::PreSonusStudio1824c::new().expect("Failed to open device"),
+            status_line,
+            ps,
             last_tick: Instant::now(),
             tick_rate: Duration::from_millis(100),
-            bypass: false,
             input: Input::default(),
             input_mode: InputMode::Normal,
             midi_input,
             midi_mapping,
             midi_learn_state: midi_control::MidiLearnState::Inactive,
+            ab: ab::AbCompare::new(),
+            paths,
+            config_watcher,
+            script,
+            command_history: command::History::default(),
+            keymap,
+            alerts,
+            alert_time: None,
+            help_scroll: None,
+            mouse_areas: mouse::MouseAreas::default(),
+            drag: None,
+            matrix: false,
+            bus_meters: false,
+            post_fader_meters: false,
         };
 
+        let outcome = app.script.load(&mut app.ps);
+        app.apply_script_outcome(outcome);
         app.set_active_strip(app.active_strip_index as isize);
         app
     }
@@ -140,24 +271,6 @@ impl App {
 
     /// runs the application's main loop until the user quits
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
-        // Load config
-        let config_file = match env::var("HOME") {
-            Ok(h) => format!("{h}/.baton.json"),
-            Err(_) => "baton.json".to_string(),
-        };
-        let path = Path::new(&config_file);
-        let file = File::open(&config_file);
-
-        match file {
-            Err(_) => (),
-            Ok(mut f) => {
-                let mut serialized = String::new();
-                f.read_to_string(&mut serialized).unwrap();
-                self.ps.load_config(&serialized);
-                self.ps.write_state();
-            }
-        }
-
         while !self.exit {
             terminal.draw(|frame| self.draw(frame))?;
             let timeout = self.tick_rate.saturating_sub(self.last_tick.elapsed());
@@ -172,20 +285,149 @@ impl App {
         }
 
         // Save config
-        let serialized = serde_json::to_string_pretty(&self.ps).unwrap();
-        let mut file = File::create(path).unwrap();
-        file.write_all(serialized.as_bytes()).unwrap();
-        file.flush().unwrap();
+        match self.config_watcher.save_config(&self.ps) {
+            Ok(path) => log::info!("Saved config to {}", path.display()),
+            Err(e) => log::error!("Failed to save config: {}", e),
+        }
 
         // Save MIDI mapping
         self.save_midi_mapping();
 
+        match self.ps.stop_meter_log() {
+            Some(Ok(path)) => log::info!("Saved meter log summary to {}", path.display()),
+            Some(Err(e)) => log::error!("Failed to save meter log summary: {}", e),
+            None => (),
+        }
+
         Ok(())
     }
 
     fn on_tick(&mut self) {
+        let online = self.ps.is_online();
+        let serial_known = !self.ps.serial.is_empty();
         self.ps.poll_state();
+        if self.ps.is_online() != online {
+            self.status_line = if online {
+                "Interface disconnected, waiting for it to reconnect".to_string()
+            } else {
+                "Interface reconnected".to_string()
+            };
+            if !serial_known && !self.ps.serial.is_empty() {
+                self.use_device_config();
+            }
+        }
+        self.process_alerts();
         self.process_midi_messages();
+        self.process_config_changes();
+        self.process_script();
+    }
+
+    /// Switch to the config and MIDI mapping of the interface found after an offline start
+    fn use_device_config(&mut self) {
+        if let Err(e) = self.paths.set_device(&self.ps.serial) {
+            log::error!(
+                "Failed to set up the config of device {}: {}",
+                self.ps.serial,
+                e
+            );
+            self.status_line = format!("Device config not set up: {}", e);
+            return;
+        }
+        let config_file = self.paths.config_file();
+        match config::load_mixer(&mut self.ps, &config_file) {
+            Ok(true) => {
+                self.ps.replace_placeholder_names();
+                if !self.ps.bypass {
+                    self.ps.write_state();
+                }
+            }
+            Ok(false) => (),
+            Err(e) => {
+                log::error!("Failed to load config {}: {}", config_file.display(), e);
+                self.status_line = format!("Config not loaded: {}", e);
+            }
+        }
+        match config::load_json(&self.paths.midi_mapping_file(), |_| Vec::new()) {
+            Ok(Some(mapping)) => self.midi_mapping = mapping,
+            Ok(None) => (),
+            Err(e) => {
+                log::warn!("Failed to load MIDI mapping: {}", e);
+                self.status_line = format!("MIDI mapping not loaded: {}", e);
+            }
+        }
+        self.config_watcher = config_watch::ConfigWatcher::new(&self.paths, &self.ps);
+    }
+
+    /// Show, notify and run the commands of the alerts raised by the last poll
+    fn process_alerts(&mut self) {
+        let Some(alerts) = &mut self.alerts else {
+            return;
+        };
+        let now = Instant::now();
+        let inputs: Vec<f64> = self.ps.channel_meters.iter().map(|m| m.peak).collect();
+        let buses: Vec<f64> = self.ps.bus_meters.iter().map(|m| m.peak).collect();
+        let raised: Vec<(String, Option<String>)> = alerts
+            .check(&inputs, &buses, now)
+            .into_iter()
+            .map(|rule| {
+                let name = match rule.meter {
+                    alerts::MeterRef::Input(index) => self.ps.channel_label(index),
+                    alerts::MeterRef::Bus(index) => &self.ps.mixes[index].name,
+                };
+                (rule.message(name), rule.command.clone())
+            })
+            .collect();
+
+        for (message, command) in raised {
+            log::warn!("Alert: {}", message);
+            if let Some(alerts) = &self.alerts {
+                alerts.notify(&message);
+            }
+            self.status_line = format!("Alert: {}", message);
+            self.alert_time = Some(now);
+            if let Some(command) = command {
+                self.run_command_line(&command);
+            }
+        }
+    }
+
+    fn process_script(&mut self) {
+        if let Some(outcome) = self.script.poll(&mut self.ps) {
+            self.apply_script_outcome(outcome);
+        }
+        let outcome = self.script.tick(&mut self.ps);
+        self.apply_script_outcome(outcome);
+    }
+
+    /// Write the strips changed by the script and show its status
+    fn apply_script_outcome(&mut self, outcome: script::Outcome) {
+        if !self.ps.bypass {
+            for (mix_index, strip_index) in outcome.changed {
+                self.ps.write_channel_fader(mix_index, strip_index);
+            }
+        }
+        if let Some(status) = outcome.status {
+            self.status_line = status;
+        }
+    }
+
+    fn process_config_changes(&mut self) {
+        let write = !self.ps.bypass;
+        if let Some(message) = self.config_watcher.poll_config(&mut self.ps, write) {
+            self.status_line = message;
+        }
+
+        match self.config_watcher.poll_midi_mapping() {
+            Some(Ok(mapping)) => {
+                self.midi_mapping = mapping;
+                self.status_line = "MIDI mapping reloaded".to_string();
+            }
+            Some(Err(e)) => {
+                log::warn!("Ignoring invalid MIDI mapping change: {}", e);
+                self.status_line = format!("Ignoring invalid MIDI mapping change: {}", e);
+            }
+            None => (),
+        }
     }
 
     // Add method to start learning
@@ -254,6 +496,13 @@ impl App {
                         continue;
                     }
 
+                    let (handled, outcome) =
+                        self.script.midi(&mut self.ps, channel, controller, value);
+                    self.apply_script_outcome(outcome);
+                    if handled {
+                        continue;
+                    }
+
                     // Normal MIDI processing
                     log::debug!(
                         "MIDI CC: channel={}, controller={}, value={}",
@@ -282,17 +531,15 @@ impl App {
 
     // Add method to save MIDI mapping
     fn save_midi_mapping(&mut self) {
-        let midi_mapping_file = match env::var("HOME") {
-            Ok(h) => format!("{h}/.baton_midi_mapping.json"),
-            Err(_) => ".baton_midi_mapping.json".to_string(),
-        };
-
-        self.midi_mapping.sort_mappings();
-        if let Ok(json) = serde_json::to_string_pretty(&self.midi_mapping) {
-            if let Ok(mut file) = File::create(&midi_mapping_file) {
-                let _ = file.write_all(json.as_bytes());
-                let _ = file.flush();
-            }
+        let midi_mapping_file = self.paths.midi_mapping_file();
+
+        match config::save_midi_mapping(&mut self.midi_mapping, &midi_mapping_file) {
+            Ok(_) => self.config_watcher.midi_mapping_saved(),
+            Err(e) => log::error!(
+                "Failed to save MIDI mapping {}: {}",
+                midi_mapping_file.display(),
+                e
+            ),
         }
     }
 
@@ -326,6 +573,16 @@ impl App {
                 let strip_index = ((value as f64 / 127.0) * 10.0) as usize;
                 self.set_active_strip(strip_index as isize);
             }
+            GlobalControl::AbToggle => {
+                if value > 63 {
+                    self.toggle_ab();
+                }
+            }
+            GlobalControl::AbCopyAToB => {
+                if value > 63 {
+                    self.copy_a_to_b();
+                }
+            }
         }
     }
 
@@ -396,8 +653,17 @@ fn draw(&mut self, frame: &mut Frame) {
     let status_line = Line::from(self.status_line.as_str()).left_aligned();
 
     // Autoscroll left and right
-    let strips_width = strips_area.inner(Margin::new(1, 1)).width;
-    let strip_display_cap = strips_width / (self.strip_width + 1) - 1;
+    let matrix_area = meters_area.union(strips_area);
+    let strip_display_cap = if self.matrix {
+        let cells_width = matrix_area
+            .inner(Margin::new(1, 1))
+            .width
+            .saturating_sub(MATRIX_NAME_WIDTH);
+        (cells_width / (MATRIX_CELL_WIDTH + 1)).max(1)
+    } else {
+        let strips_width = strips_area.inner(Margin::new(1, 1)).width;
+        strips_width / (self.strip_width + 1) - 1
+    };
     while self.active_strip_index < self.first_strip_index {
         self.first_strip_index -= 1;
     }
@@ -407,42 +673,45 @@ fn draw(&mut self, frame: &mut Frame) {
 
     // Compose state text
     let spacer = Span::from(" | ").reset();
-    let mut phantom: Span = Span::from("p: 48V");
+    let mut phantom: Span = Span::from(self.key_label(keymap::Action::Phantom, "48V"));
     if self.ps.state.phantom == 0x01 {
         phantom = phantom.style(Style::new().bold().black().on_blue());
     } else {
         phantom = phantom.style(Style::new().reset());
     }
 
-    let mut line: Span = Span::from("l: 1-2 Line");
+    let mut line: Span = Span::from(self.key_label(keymap::Action::Line, "1-2 Line"));
     if self.ps.state.line == 0x01 {
         line = line.style(Style::new().bold().black().on_blue());
     } else {
         line = line.style(Style::new().reset());
     }
 
-    let mut mute: Span = Span::from("u: Mute");
+    let mut mute: Span = Span::from(self.key_label(keymap::Action::MainMute, "Mute"));
     if self.ps.state.mute == 0x01 {
         mute = mute.style(Style::new().bold().black().on_red());
     } else {
         mute = mute.style(Style::new().reset());
     }
 
-    let mut mono: Span = Span::from("o: Mono");
+    let mut mono: Span = Span::from(self.key_label(keymap::Action::MainMono, "Mono"));
     if self.ps.state.mono == 0x01 {
         mono = mono.style(Style::new().bold().black().on_yellow());
     } else {
         mono = mono.style(Style::new().reset());
     }
 
-    let mut bypass: Span = Span::from("b: Bypass");
-    if self.bypass {
+    let mut bypass: Span = Span::from(self.key_label(keymap::Action::Bypass, "Bypass"));
+    if self.ps.bypass {
         bypass = bypass.style(Style::new().bold().black().on_light_blue());
     } else {
         bypass = bypass.style(Style::new().reset());
     }
 
-    let state_line = Line::from(vec![
+    let ab = Span::from(self.key_label(keymap::Action::AbToggle, &format!("{:?}", self.ab.active)))
+        .style(Style::new().bold().black().on_green());
+
+    let mut state_line = Line::from(vec![
         phantom,
         spacer.clone(),
         line,
@@ -450,23 +719,67 @@ fn draw(&mut self, frame: &mut Frame) {
         mute,
         spacer.clone(),
         mono,
-        spacer,
+        spacer.clone(),
         bypass,
+        spacer,
+        ab,
     ]);
 
+    // Remember where the toggles are for mouse clicks
+    let toggle_actions = [
+        keymap::Action::Phantom,
+        keymap::Action::Line,
+        keymap::Action::MainMute,
+        keymap::Action::MainMono,
+        keymap::Action::Bypass,
+        keymap::Action::AbToggle,
+    ];
+    let mut x = state_area.x;
+    self.mouse_areas.toggles.clear();
+    for (i, span) in state_line.spans.iter().enumerate() {
+        let width = span.width() as u16;
+        if i % 2 == 0 {
+            self.mouse_areas.toggles.push((x, x + width, toggle_actions[i / 2]));
+        }
+        x += width;
+    }
+    self.mouse_areas.state = state_area;
+
+    if !self.ps.is_online() {
+        state_line.push_span(Span::from(" | ").reset());
+        state_line.push_span(Span::from("OFFLINE").style(Style::new().bold().white().on_red()));
+    }
     frame.render_widget(state_line, state_area);
-    frame.render_widget(
-        self.meters_barchart(&self.ps.mixes[self.active_mix_index]),
-        meters_area,
-    );
+    if self.matrix {
+        self.mouse_areas.meters = Rect::default();
+        self.mouse_areas.pans = Rect::default();
+        self.mouse_areas.faders = Rect::default();
+        self.mouse_areas.matrix = matrix_area;
+        self.mouse_areas.matrix_first_mix = self.render_matrix(frame, matrix_area);
+    } else {
+        self.mouse_areas.meters = meters_area;
+        self.mouse_areas.pans = pan_area;
+        self.mouse_areas.faders = strips_area;
+        self.mouse_areas.matrix = Rect::default();
+
+        if self.bus_meters {
+            self.mouse_areas.meters = Rect::default();
+            frame.render_widget(self.bus_meters_barchart(meters_area.width), meters_area);
+        } else {
+            frame.render_widget(
+                self.meters_barchart(&self.ps.mixes[self.active_mix_index]),
+                meters_area,
+            );
+        }
     
-    // Render pan widgets for each visible channel strip
-    self.render_pan_widgets(frame, pan_area);
+        // Render pan widgets for each visible channel strip
+        self.render_pan_widgets(frame, pan_area);
     
-    frame.render_widget(
-        self.faders_barchart(&self.ps.mixes[self.active_mix_index]),
-        strips_area,
-    );
+        frame.render_widget(
+            self.faders_barchart(&self.ps.mixes[self.active_mix_index]),
+            strips_area,
+        );
+    }
 
     if self.input_mode == InputMode::Rename || self.input_mode == InputMode::Command {
         let title = format!("{:?}", self.input_mode);
@@ -484,13 +797,157 @@ fn draw(&mut self, frame: &mut Frame) {
         frame.set_cursor_position((status_area.x + x as u16, status_area.y + 1))
     } else {
         frame.render_widget(
-            Paragraph::new(status_line).block(Block::bordered().title("Status")),
+            Paragraph::new(status_line)
+                .style(self.status_style())
+                .block(Block::bordered().title("Status")),
             status_area,
         );
     }
 
-    let help_text = Line::from("q: Quit | Arrows: Navigate | s: Solo | m: Mute | r: Rename | Shift+F/B/M/S: MIDI Learn Fader/Balance/Mute/Solo");
-    frame.render_widget(help_text, help_area);
+    let hints: Vec<String> = [
+        keymap::Action::Help,
+        keymap::Action::Quit,
+        keymap::Action::Solo,
+        keymap::Action::Mute,
+        keymap::Action::Rename,
+        keymap::Action::CommandLine,
+    ]
+    .into_iter()
+    .filter_map(|action| {
+        let key = self.keymap.key_for(action)?;
+        Some(format!("{}: {}", key, action.description()))
+    })
+    .collect();
+    frame.render_widget(Line::from(hints.join(" | ")), help_area);
+
+    if let Some(scroll) = self.help_scroll {
+        self.render_help(frame, scroll);
+    }
+}
+
+/// Draw all mixes as rows and all strips as columns, returns the first visible mix
+fn render_matrix(&self, frame: &mut Frame, area: Rect) -> usize {
+    let block = Block::bordered().title(Line::from("Routing matrix").centered().bold());
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let channels = self.ps.channel_names.len();
+    let cells_width = inner.width.saturating_sub(MATRIX_NAME_WIDTH);
+    let visible = (cells_width / (MATRIX_CELL_WIDTH + 1)) as usize;
+    let strips = self.first_strip_index..(self.first_strip_index + visible).min(channels + 1);
+    let cell = |text: &str| {
+        let text: String = text.chars().take(MATRIX_CELL_WIDTH as usize).collect();
+        format!("{:>width$} ", text, width = MATRIX_CELL_WIDTH as usize)
+    };
+    let name = |text: &str| {
+        let text: String = text.chars().take(MATRIX_NAME_WIDTH as usize - 1).collect();
+        format!("{:<width$}", text, width = MATRIX_NAME_WIDTH as usize)
+    };
+
+    // Keep the active mix visible when the matrix is taller than the screen
+    let rows = (inner.height.saturating_sub(1) / 2).max(1) as usize;
+    let first_mix = (self.active_mix_index + 1).saturating_sub(rows);
+
+    let mut header = vec![Span::from(name(""))];
+    for s in strips.clone() {
+        let label = if s < channels {
+            self.ps.channel_label(s)
+        } else {
+            "Out"
+        };
+        let mut span = Span::from(cell(label));
+        if s == self.active_strip_index {
+            span = span.bold();
+        }
+        header.push(span);
+    }
+    let mut lines = vec![Line::from(header)];
+
+    for (m, mix) in self.ps.mixes.iter().enumerate().skip(first_mix).take(rows) {
+        let mut mix_name = Span::from(name(&mix.name));
+        if m == self.active_mix_index {
+            mix_name = mix_name.bold();
+        }
+        let mut levels = vec![mix_name];
+        let mut pans = vec![Span::from(name(""))];
+        for (s, strip) in mix.strips.iter().enumerate().skip(strips.start).take(strips.len()) {
+            let mut style = Style::new();
+            if strip.mute_by_solo {
+                style = style.fg(Color::Red);
+            }
+            if strip.mute {
+                style = style.black().on_red();
+            }
+            if strip.solo {
+                style = style.black().on_yellow();
+            }
+            if m == self.active_mix_index && s == self.active_strip_index {
+                style = style.black().on_green();
+            }
+            levels.push(Span::styled(cell(&matrix::format_level(strip.fader, strip.min)), style));
+            let pan = if strip.kind == StripKind::Channel {
+                matrix::format_pan(strip.balance)
+            } else {
+                String::new()
+            };
+            pans.push(Span::styled(cell(&pan), style.dim()));
+        }
+        lines.push(Line::from(levels));
+        lines.push(Line::from(pans));
+    }
+
+    frame.render_widget(Paragraph::new(lines), inner);
+    first_mix
+}
+
+/// Status line style, flashing for a while after an alert
+fn status_style(&self) -> Style {
+    match self.alert_time.map(|time| time.elapsed()) {
+        Some(elapsed) if elapsed < alerts::FLASH_TIME && elapsed.as_millis() / 250 % 2 == 0 => {
+            Style::new().bold().white().on_red()
+        }
+        _ => Style::new(),
+    }
+}
+
+/// State bar label prefixed with the key bound to its action
+fn key_label(&self, action: keymap::Action, label: &str) -> String {
+    match self.keymap.key_for(action) {
+        Some(key) => format!("{}: {}", key, label),
+        None => label.to_string(),
+    }
+}
+
+fn render_help(&self, frame: &mut Frame, scroll: u16) {
+    let help = self.keymap.help();
+    let key_width = help.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+    let lines: Vec<Line> = help
+        .iter()
+        .map(|(keys, description)| {
+            Line::from(vec![
+                Span::from(format!("{:<width$}  ", keys, width = key_width)).bold(),
+                Span::from(description.as_str()),
+            ])
+        })
+        .collect();
+
+    let area = frame.area();
+    let width = (key_width as u16 + 40).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let area = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let max_scroll = (lines.len() as u16 + 2).saturating_sub(height);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .scroll((scroll.min(max_scroll), 0))
+            .block(Block::bordered().title("Key bindings (Esc to close)")),
+        area,
+    );
 }
 
 fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
@@ -552,21 +1009,147 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
         match event {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
                 match self.input_mode {
+                    InputMode::Normal if self.help_scroll.is_some() => {
+                        self.handle_help_key_event(key_event)
+                    }
                     InputMode::Normal => self.handle_key_event(key_event),
                     InputMode::Rename | InputMode::Command => match key_event.code {
                         KeyCode::Enter => self.push_message(),
                         KeyCode::Esc => self.stop_editing(),
+                        KeyCode::Tab if self.input_mode == InputMode::Command => {
+                            self.complete_command()
+                        }
+                        KeyCode::Up if self.input_mode == InputMode::Command => {
+                            self.previous_command()
+                        }
+                        KeyCode::Down if self.input_mode == InputMode::Command => {
+                            self.next_command()
+                        }
                         _ => {
                             self.input.handle_event(&event);
                         }
                     },
                 }
             }
+            Event::Mouse(mouse_event)
+                if self.input_mode == InputMode::Normal && self.help_scroll.is_none() =>
+            {
+                self.handle_mouse_event(mouse_event)
+            }
             _ => {}
         };
         Ok(())
     }
 
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        let (x, y) = (mouse_event.column, mouse_event.row);
+        let position = Position::new(x, y);
+        let channels = self.ps.mixes[self.active_mix_index]
+            .strips
+            .channel_strips
+            .len();
+        let areas = &self.mouse_areas;
+        let faders = self.chart_bars(areas.faders, channels + 1);
+        let pans = self.chart_bars(areas.pans, channels);
+
+        match mouse_event.kind {
+            MouseEventKind::Down(button) => {
+                if areas.matrix.contains(position) {
+                    let cells = mouse::Bars {
+                        x: areas.matrix.x + 1 + MATRIX_NAME_WIDTH,
+                        first: self.first_strip_index,
+                        width: MATRIX_CELL_WIDTH,
+                        count: channels + 1,
+                    };
+                    let mix = mouse::matrix_row(areas.matrix, y, areas.matrix_first_mix);
+                    if let (Some((strip, _)), Some(mix)) = (cells.at(x), mix)
+                        && mix < self.ps.mixes.len()
+                    {
+                        self.set_active_mix(mix);
+                        self.set_active_strip(strip as isize);
+                        if button == MouseButton::Right {
+                            self.toggle_mute();
+                        }
+                    }
+                } else if areas.state.contains(position) {
+                    let toggle = areas
+                        .toggles
+                        .iter()
+                        .find(|(start, end, _)| (*start..*end).contains(&x));
+                    if let Some(&(_, _, action)) = toggle {
+                        self.perform(action);
+                    }
+                } else if areas.meters.contains(position) {
+                    let meters = self.chart_bars(areas.meters, channels + 2);
+                    if let Some((index, _)) = meters.at(x) {
+                        self.clear_clip_indicator(index);
+                    }
+                } else if areas.pans.contains(position) && button == MouseButton::Left {
+                    if let Some((index, column)) = pans.at(x) {
+                        self.set_active_strip(index as isize);
+                        self.set_balance(mouse::pan_balance(column, self.strip_width));
+                        self.drag = Some(mouse::Drag::Pan(index));
+                    }
+                } else if areas.faders.contains(position)
+                    && let Some((index, _)) = faders.at(x)
+                {
+                    let label = mouse::is_chart_label(areas.faders, y);
+                    let level = mouse::chart_level(areas.faders, y);
+                    self.set_active_strip(index as isize);
+                    match button {
+                        MouseButton::Left if label => self.toggle_mute(),
+                        MouseButton::Right if label => self.toggle_solo(),
+                        MouseButton::Left => {
+                            if let Some(level) = level {
+                                self.set_active_fader(level);
+                                self.drag = Some(mouse::Drag::Fader(index));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) => match self.drag {
+                Some(mouse::Drag::Fader(index)) if index == self.active_strip_index => {
+                    if let Some(level) = mouse::drag_level(areas.faders, y) {
+                        self.set_active_fader(level);
+                    }
+                }
+                Some(mouse::Drag::Pan(index)) if index == self.active_strip_index => {
+                    self.set_balance(mouse::pan_balance(pans.column(index, x), self.strip_width));
+                }
+                _ => {}
+            },
+            MouseEventKind::Up(_) => self.drag = None,
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let delta = if mouse_event.kind == MouseEventKind::ScrollUp {
+                    1.0
+                } else {
+                    -1.0
+                };
+                if areas.matrix.contains(position) {
+                    self.increment_fader(delta);
+                } else if areas.pans.contains(position) {
+                    if let Some((index, _)) = pans.at(x) {
+                        self.set_active_strip(index as isize);
+                        self.increment_balance(delta);
+                    }
+                } else if areas.faders.contains(position)
+                    && let Some((index, _)) = faders.at(x)
+                {
+                    self.set_active_strip(index as isize);
+                    self.increment_fader(delta);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Bars of a chart with `count` bars, as drawn in the last frame
+    fn chart_bars(&self, area: Rect, count: usize) -> mouse::Bars {
+        mouse::chart_bars(area, self.first_strip_index, self.strip_width, count)
+    }
+
     fn push_message(&mut self) {
         match self.input_mode {
             InputMode::Rename => self.execute_rename(),
@@ -595,14 +1178,237 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
     }
 
     fn execute_command(&mut self) {
-        let command = self.input.value_and_reset();
-        match command.as_str() {
-            ":mute" => self.toggle_mute(),
-            ":solo" => self.toggle_solo(),
-            _ => (),
+        let line = self.input.value_and_reset();
+        self.input_mode = InputMode::Normal;
+        self.command_history.push(&line);
+        self.run_command_line(&line);
+    }
+
+    fn run_command_line(&mut self, line: &str) {
+        let result = match command::parse(line) {
+            Ok(Some(command_line)) => self.run_command(command_line),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.status_line = format!("{}: {}", line.trim(), e);
         }
+    }
 
-        self.input_mode = InputMode::Normal;
+    fn run_command(&mut self, command_line: command::CommandLine) -> Result<(), String> {
+        let mix_index = self.active_mix_index;
+        let channels = self.ps.mixes[mix_index].strips.channel_strips.len();
+        let range = command_line.range;
+
+        match command_line.command {
+            command::Command::Mute(switch) => {
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    let strip = self.ps.mixes[mix_index]
+                        .strips
+                        .iter_mut()
+                        .nth(index)
+                        .unwrap();
+                    if strip.kind == StripKind::Main {
+                        self.ps.set_main_mute(switch.apply(self.ps.main_mute));
+                    } else {
+                        strip.mute = switch.apply(strip.mute);
+                        self.ps.write_channel_fader(mix_index, index);
+                    }
+                }
+            }
+            command::Command::Solo(switch) => {
+                let mix = &mut self.ps.mixes[mix_index];
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    let strip = mix
+                        .strips
+                        .channel_strips
+                        .get_mut(index)
+                        .ok_or("bus strips have no solo")?;
+                    strip.solo = switch.apply(strip.solo);
+                }
+                mix.sync_solo();
+                self.write_mix(mix_index);
+            }
+            command::Command::Fader(db) => {
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    let strip = self.ps.mixes[mix_index]
+                        .strips
+                        .iter_mut()
+                        .nth(index)
+                        .unwrap();
+                    strip.set_fader(db);
+                    self.ps.write_channel_fader(mix_index, index);
+                }
+            }
+            command::Command::Pan(balance) => {
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    let strip = self.ps.mixes[mix_index]
+                        .strips
+                        .channel_strips
+                        .get_mut(index)
+                        .ok_or("bus strips have no pan")?;
+                    strip.balance = balance;
+                    self.ps.write_channel_fader(mix_index, index);
+                }
+            }
+            command::Command::Mix(number) => {
+                if number > self.ps.mixes.len() {
+                    return Err(format!("mixes are numbered 1 to {}", self.ps.mixes.len()));
+                }
+                self.set_active_mix(number - 1);
+            }
+            command::Command::Rename(name) => {
+                let index = range.index(self.active_strip_index, channels)?;
+                match self.ps.channel_names.get_mut(index) {
+                    Some(channel_name) => *channel_name = name,
+                    None => self.ps.mixes[mix_index].name = name,
+                }
+            }
+            command::Command::SceneSave(name) => {
+                scene::save(&self.ps, &self.paths, &name).map_err(|e| e.to_string())?;
+                self.status_line = format!("Saved scene {}", name);
+            }
+            command::Command::SceneLoad(name) => {
+                scene::load(&mut self.ps, &self.paths, &name).map_err(|e| e.to_string())?;
+                if !self.ps.bypass {
+                    self.ps.write_state();
+                }
+                self.status_line = format!("Loaded scene {}", name);
+            }
+            command::Command::CopyMix { from, to } => {
+                let count = self.ps.mixes.len();
+                if from > count || to > count {
+                    return Err(format!("mixes are numbered 1 to {}", count));
+                }
+                let source = self.ps.mixes[from - 1].strips.channel_strips.clone();
+                let target = &mut self.ps.mixes[to - 1];
+                for (strip, source) in target.strips.channel_strips.iter_mut().zip(source) {
+                    strip.fader = source.fader;
+                    strip.balance = source.balance;
+                    strip.solo = source.solo;
+                    strip.mute = source.mute;
+                }
+                target.sync_solo();
+                self.write_mix(to - 1);
+                self.status_line = format!(
+                    "Copied {} to {}",
+                    self.ps.mixes[from - 1].name,
+                    self.ps.mixes[to - 1].name
+                );
+            }
+            command::Command::Map {
+                control,
+                channel,
+                cc,
+            } => {
+                let strip_index = range.index(self.active_strip_index, channels)?;
+                let midi = midi_control::MidiControl { channel, cc };
+                let target = StripTarget {
+                    mix_index,
+                    strip_index,
+                    control,
+                };
+                self.midi_mapping.remove_mapping(&midi);
+                self.midi_mapping.map_strip(
+                    midi,
+                    target,
+                    midi_control::MidiMapping::default_range_for_control(&control),
+                );
+                self.save_midi_mapping();
+                self.status_line =
+                    format!("Mapped channel {} CC {} to {:?}", channel + 1, cc, control);
+            }
+            command::Command::SetPanLaw(db) => {
+                if !usb::PAN_LAW_RANGE.contains(&db) {
+                    return Err(format!(
+                        "pan law must be {} to {} dB",
+                        usb::PAN_LAW_RANGE.start(),
+                        usb::PAN_LAW_RANGE.end()
+                    ));
+                }
+                self.ps.pan_law = db;
+                if !self.ps.bypass {
+                    self.ps.write_state();
+                }
+                self.status_line = format!("Pan law {} dB", db);
+            }
+            command::Command::ClearMeters => {
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    for meter in self.strip_meters_mut(index) {
+                        meter.clear();
+                    }
+                }
+            }
+            command::Command::Clips => {
+                let now = Instant::now();
+                let mut clips = Vec::new();
+                for index in range.indexes(self.active_strip_index, channels)? {
+                    let meters = self.strip_meters(index);
+                    let Some(last) = meters.iter().filter_map(|m| m.last_clip).max() else {
+                        continue;
+                    };
+                    let count: u32 = meters.iter().map(|m| m.clips).sum();
+                    let age = command::format_age(now.duration_since(last));
+                    clips.push(format!(
+                        "{} {}x, last {} ago",
+                        self.strip_name(index),
+                        count,
+                        age
+                    ));
+                }
+                self.status_line = if clips.is_empty() {
+                    "No clips".to_string()
+                } else {
+                    clips.join("; ")
+                };
+            }
+            command::Command::Arm(switch) => {
+                let alerts = self.alerts.as_mut().ok_or("no alerts in alerts.json")?;
+                alerts.armed = switch.apply(alerts.armed);
+                self.status_line = if alerts.armed {
+                    "Silence alerts armed".to_string()
+                } else {
+                    "Silence alerts disarmed".to_string()
+                };
+            }
+            command::Command::Quit => self.exit(),
+            command::Command::Other { name, args } => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                let outcome = self
+                    .script
+                    .command(&mut self.ps, &name, &args)
+                    .ok_or("unknown command")?;
+                self.apply_script_outcome(outcome);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_mix(&mut self, mix_index: usize) {
+        for strip_index in 0..=self.ps.mixes[mix_index].strips.channel_strips.len() {
+            self.ps.write_channel_fader(mix_index, strip_index);
+        }
+    }
+
+    fn complete_command(&mut self) {
+        let scenes = scene::list(&self.paths).unwrap_or_default();
+        let completion = command::complete(self.input.value(), &scenes, &self.script.commands());
+        if !completion.candidates.is_empty() {
+            self.status_line = completion.candidates.join("  ");
+        }
+        self.input = Input::new(completion.line);
+    }
+
+    fn previous_command(&mut self) {
+        if let Some(line) = self.command_history.previous() {
+            self.input = Input::new(line.to_string());
+        }
+    }
+
+    fn next_command(&mut self) {
+        let line = self.command_history.next().unwrap_or(":").to_string();
+        self.input = Input::new(line);
     }
 
     fn stop_editing(&mut self) {
@@ -638,122 +1444,139 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
     fn init_input_command(&mut self) {
         self.input = Input::new(String::from(':'));
         self.input_mode = InputMode::Command;
+        self.command_history.reset();
     }
 
     fn handle_key_event(&mut self, key_event: KeyEvent) {
-        match key_event.code {
-            KeyCode::Char('q') => self.exit(),
-            KeyCode::Char('l') => self.toggle_1_2_line(),
-            KeyCode::Char('u') => self.toggle_main_mute(),
-            KeyCode::Char('o') => self.toggle_main_mono(),
-            KeyCode::Char('p') => self.toggle_phantom_power(),
-            KeyCode::Char('1') => self.set_active_mix(0),
-            KeyCode::Char('2') => self.set_active_mix(1),
-            KeyCode::Char('3') => self.set_active_mix(2),
-            KeyCode::Char('4') => self.set_active_mix(3),
-            KeyCode::Char('5') => self.set_active_mix(4),
-            KeyCode::Char('6') => self.set_active_mix(5),
-            KeyCode::Char('7') => self.set_active_mix(6),
-            KeyCode::Char('8') => self.set_active_mix(7),
-            KeyCode::Char('9') => self.set_active_mix(8),
-            KeyCode::Char('m') => self.toggle_mute(),
-            KeyCode::Char('s') => self.toggle_solo(),
-            KeyCode::Char('b') => self.toggle_bypass(),
-            KeyCode::Char(' ') => self.clear_clip_indicators(),
-            KeyCode::Char('F') => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.start_midi_learn(midi_control::StripControl::Fader);
-                }
-            }
-            KeyCode::Char('B') => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.start_midi_learn(midi_control::StripControl::Balance);
-                }
-            }
-            KeyCode::Char('M') => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.start_midi_learn(midi_control::StripControl::Mute);
-                }
-            }
-            KeyCode::Char('S') => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.start_midi_learn(midi_control::StripControl::Solo);
-                }
-            }
-            KeyCode::Esc => {
+        match self.keymap.get(key_event).cloned() {
+            Some(keymap::Binding::Action(action)) => self.perform(action),
+            Some(keymap::Binding::Command(line)) => self.run_command_line(&line),
+            None => {}
+        }
+    }
+
+    fn perform(&mut self, action: keymap::Action) {
+        use keymap::Action;
+        match action {
+            Action::Quit => self.exit(),
+            Action::Help => self.help_scroll = Some(0),
+            Action::CommandLine => self.start_editing(InputMode::Command),
+            Action::Rename => self.start_editing(InputMode::Rename),
+            Action::StripLeft => self.decrement_strip(),
+            Action::StripRight => self.increment_strip(),
+            Action::FaderUp => self.increment_fader(1.0),
+            Action::FaderDown => self.increment_fader(-1.0),
+            Action::FaderUpFine => self.increment_fader(0.1),
+            Action::FaderDownFine => self.increment_fader(-0.1),
+            Action::FaderUpCoarse => self.increment_fader(10.0),
+            Action::FaderDownCoarse => self.increment_fader(-10.0),
+            Action::PanLeft => self.increment_balance(-1.0),
+            Action::PanRight => self.increment_balance(1.0),
+            Action::PanLeftCoarse => self.increment_balance(-10.0),
+            Action::PanRightCoarse => self.increment_balance(10.0),
+            Action::PanCenter => self.center_balance(),
+            Action::Mute => self.toggle_mute(),
+            Action::Solo => self.toggle_solo(),
+            Action::Bypass => self.toggle_bypass(),
+            Action::AbToggle => self.toggle_ab(),
+            Action::AbCopy => self.copy_a_to_b(),
+            Action::ClearClips => self.clear_clip_indicators(),
+            Action::ClearStrip => self.clear_strip_meters(),
+            Action::Phantom => self.toggle_phantom_power(),
+            Action::Line => self.toggle_1_2_line(),
+            Action::MainMute => self.toggle_main_mute(),
+            Action::MainMono => self.toggle_main_mono(),
+            Action::LearnFader => self.start_midi_learn(midi_control::StripControl::Fader),
+            Action::LearnBalance => self.start_midi_learn(midi_control::StripControl::Balance),
+            Action::LearnMute => self.start_midi_learn(midi_control::StripControl::Mute),
+            Action::LearnSolo => self.start_midi_learn(midi_control::StripControl::Solo),
+            Action::Cancel => {
                 // Cancel learn mode if active
                 if self.midi_learn_state != midi_control::MidiLearnState::Inactive {
                     self.midi_learn_state = midi_control::MidiLearnState::Inactive;
                     self.status_line = "MIDI Learn cancelled".to_string();
                 }
             }
-            KeyCode::Char('r') => self.start_editing(InputMode::Rename),
-            KeyCode::Char(':') => self.start_editing(InputMode::Command),
-            KeyCode::PageDown => self.increment_meter_heigth(1),
-            KeyCode::PageUp => self.increment_meter_heigth(-1),
-            KeyCode::Char('x') => {
-                if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_balance(-10.0);
-                } else {
-                    self.increment_balance(-1.0);
-                }
-            }
-            KeyCode::Char('c') => self.center_balance(),
-            KeyCode::Char('v') => {
-                if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_balance(10.0);
-                } else {
-                    self.increment_balance(1.0);
-                }
-            }
-            KeyCode::Down => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.increment_fader(-0.1);
-                } else if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_fader(-10.0);
-                } else {
-                    self.increment_fader(-1.0);
-                }
-            }
-            KeyCode::Up => {
-                if key_event.modifiers == KeyModifiers::SHIFT {
-                    self.increment_fader(0.1);
-                } else if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_fader(10.0);
-                } else {
-                    self.increment_fader(1.0);
-                }
-            }
-            KeyCode::Left => {
-                if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_strip_width(-2);
-                } else {
-                    self.decrement_strip();
-                }
-            }
-            KeyCode::Right => {
-                if key_event.modifiers == KeyModifiers::CONTROL {
-                    self.increment_strip_width(2);
-                } else {
-                    self.increment_strip();
-                }
-            }
-            _ => {}
+            Action::StripWider => self.increment_strip_width(2),
+            Action::StripNarrower => self.increment_strip_width(-2),
+            Action::MeterGrow => self.increment_meter_heigth(1),
+            Action::MeterShrink => self.increment_meter_heigth(-1),
+            Action::Matrix => self.matrix = !self.matrix,
+            Action::BusMeters => self.bus_meters = !self.bus_meters,
+            Action::PostFaderMeters => self.post_fader_meters = !self.post_fader_meters,
+            Action::MixPrevious => self.step_active_mix(-1),
+            Action::MixNext => self.step_active_mix(1),
         }
     }
 
+    fn handle_help_key_event(&mut self, key_event: KeyEvent) {
+        let Some(scroll) = self.help_scroll else {
+            return;
+        };
+        self.help_scroll = match key_event.code {
+            KeyCode::Up => Some(scroll.saturating_sub(1)),
+            KeyCode::Down => Some(scroll + 1),
+            KeyCode::PageUp => Some(scroll.saturating_sub(10)),
+            KeyCode::PageDown => Some(scroll + 10),
+            _ => None,
+        };
+    }
+
     fn exit(&mut self) {
         self.exit = true;
     }
 
     fn clear_clip_indicators(&mut self) {
-        for meter in &mut self.ps.channel_meters {
-            meter.clip = false;
-            meter.max = -f64::INFINITY;
+        for meter in self
+            .ps
+            .channel_meters
+            .iter_mut()
+            .chain(&mut self.ps.bus_meters)
+        {
+            meter.clear();
+        }
+    }
+
+    /// Clear the clip indicator of one meter, bus meters follow the channel meters
+    fn clear_clip_indicator(&mut self, index: usize) {
+        let channels = self.ps.channel_meters.len();
+        if index < channels {
+            self.ps.channel_meters[index].clear();
+        } else {
+            self.ps.bus_meters[self.active_mix_index * 2 + index - channels].clear();
+        }
+    }
+
+    /// Clear the meters of the active strip
+    fn clear_strip_meters(&mut self) {
+        let index = self.active_strip_index;
+        for meter in self.strip_meters_mut(index) {
+            meter.clear();
+        }
+        self.status_line = format!("Cleared meters of {}", self.strip_name(index));
+    }
+
+    /// Meters of a strip of the active mix, the bus strip has the left and right bus meters
+    fn strip_meters(&self, index: usize) -> &[usb::Meter] {
+        match self.ps.channel_meters.get(index) {
+            Some(meter) => std::slice::from_ref(meter),
+            None => &self.ps.bus_meters[self.active_mix_index * 2..][..2],
         }
-        for meter in &mut self.ps.bus_meters {
-            meter.clip = false;
-            meter.max = -f64::INFINITY;
+    }
+
+    fn strip_meters_mut(&mut self, index: usize) -> &mut [usb::Meter] {
+        if index < self.ps.channel_meters.len() {
+            std::slice::from_mut(&mut self.ps.channel_meters[index])
+        } else {
+            &mut self.ps.bus_meters[self.active_mix_index * 2..][..2]
+        }
+    }
+
+    fn strip_name(&self, index: usize) -> &str {
+        if index < self.ps.channel_names.len() {
+            self.ps.channel_label(index)
+        } else {
+            &self.ps.mixes[self.active_mix_index].name
         }
     }
 
@@ -773,6 +1596,17 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
         self.write_active_fader();
     }
 
+    /// Set the fader of the active strip from the height of a click on its bar
+    fn set_active_fader(&mut self, level: f64) {
+        let strip = self.ps.mixes[self.active_mix_index]
+            .strips
+            .iter_mut()
+            .nth(self.active_strip_index)
+            .unwrap();
+        strip.set_fader(mouse::fader_value(level, strip.min, strip.max));
+        self.write_active_fader();
+    }
+
     fn write_active_fader(&mut self) {
         self.ps
             .write_channel_fader(self.active_mix_index, self.active_strip_index);
@@ -803,9 +1637,13 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
     }
 
     fn center_balance(&mut self) {
+        self.set_balance(0.0);
+    }
+
+    fn set_balance(&mut self, balance: f64) {
         let strip = &mut self.ps.mixes[self.active_mix_index].strips.channel_strips
             [self.active_strip_index];
-        strip.balance = 0.0;
+        strip.balance = balance;
 
         self.write_active_fader();
     }
@@ -863,28 +1701,58 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
     }
 
     fn toggle_bypass(&mut self) {
-        self.bypass = !self.bypass;
-        if self.bypass {
+        self.ps.bypass = !self.ps.bypass;
+        if self.ps.bypass {
             self.ps.bypass_mixer();
         } else {
             self.ps.write_state();
         }
     }
 
+    fn toggle_ab(&mut self) {
+        self.ab.toggle(&mut self.ps.mixes);
+        self.write_ab_state();
+        self.status_line = format!("A/B: {:?} is live", self.ab.active);
+    }
+
+    fn copy_a_to_b(&mut self) {
+        if self.ab.copy_a_to_b(&mut self.ps.mixes) {
+            self.write_ab_state();
+        }
+        self.status_line = "A/B: Copied A to B".to_string();
+    }
+
+    fn write_ab_state(&mut self) {
+        self.set_active_strip(self.active_strip_index as isize);
+        if !self.ps.bypass {
+            self.ps.write_state();
+        }
+    }
+
     fn set_active_mix(&mut self, index: usize) {
         self.active_mix_index = index;
         self.set_active_strip(self.active_strip_index as isize);
     }
 
+    fn step_active_mix(&mut self, delta: isize) {
+        self.set_active_mix(matrix::step(
+            self.active_mix_index,
+            delta,
+            self.ps.mixes.len(),
+        ));
+    }
+
     fn faders_barchart(&self, mix: &usb::Mix) -> BarChart<'_> {
         let mut bars: Vec<Bar> = mix
             .strips
             .channel_strips
             .iter()
             .enumerate()
-            .map(|(i, strip)| self.fader_bar(strip, self.ps.channel_names[i].as_str()))
+            .map(|(i, strip)| {
+                self.fader_bar(strip, self.ps.channel_label(i), self.ps.channel_colors[i])
+            })
             .collect();
-        bars.push(self.fader_bar(&mix.strips.bus_strip, &mix.name));
+        bars.push(self.fader_bar(&mix.strips.bus_strip, &mix.name, mix.color));
         let title = self.ps.mixes[self.active_mix_index].name.as_str();
         let title = Line::from(title).centered().bold();
 
@@ -895,7 +1763,7 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
             .max(500)
     }
 
-    fn fader_bar(&self, strip: &usb::Strip, name: &str) -> Bar<'_> {
+    fn fader_bar(&self, strip: &usb::Strip, name: &str, color: Option<usb::StripColor>) -> Bar<'_> {
         let a = strip.min;
         let b = strip.max;
         let c = 20.0;
@@ -923,6 +1791,9 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
         if strip.active {
             strip_fg_color = Color::Green;
         }
+        if let Some(color) = color {
+            (label_fg_color, label_bg_color) = label_colors(color);
+        }
         if strip.mute_by_solo {
             label_bg_color = Color::Reset;
             label_fg_color = Color::Red;
@@ -968,29 +1839,40 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
             .iter()
             .enumerate()
             .map(|(i, meter)| {
-                self.meter_bar(
-                    meter.clip,
-                    self.ps.channel_names[i].as_str(),
-                    meter.value,
-                    meter.max,
-                )
+                let name = self.ps.channel_label(i);
+                let color = self.ps.channel_colors[i];
+                let scale = self.ps.metering.inputs.scale;
+                if self.post_fader_meters {
+                    // The louder side of the strip in the mix
+                    let gains = post_fader_gains(&mix.strips.channel_strips[i], self.ps.pan_law);
+                    let meter = meter.with_gain(gains.map(|(left, right)| left.max(right)));
+                    self.meter_bar(&meter, name, color, scale)
+                } else {
+                    self.meter_bar(meter, name, color, scale)
+                }
             })
             .collect();
+        let outputs = self.ps.metering.outputs;
         let bus_meter_left = &self.ps.bus_meters[self.active_mix_index * 2];
         let bus_meter_right = &self.ps.bus_meters[self.active_mix_index * 2 + 1];
-        bars.push(self.meter_bar(
-            bus_meter_left.clip,
-            &mix.name,
-            bus_meter_left.value,
-            bus_meter_left.max,
-        ));
-        bars.push(self.meter_bar(
-            bus_meter_right.clip,
-            &mix.name,
-            bus_meter_right.value,
-            bus_meter_right.max,
-        ));
-        let title = "Meters";
+        bars.push(self.meter_bar(bus_meter_left, &mix.name, mix.color, outputs.scale));
+        bars.push(self.meter_bar(bus_meter_right, &mix.name, mix.color, outputs.scale));
+        let inputs = self.ps.metering.inputs;
+        let meters = if self.post_fader_meters {
+            "Post-fader meters"
+        } else {
+            "Meters"
+        };
+        let title = if inputs.scale == outputs.scale {
+            format!("{} ({})", meters, inputs.scale.name())
+        } else {
+            format!(
+                "{} (inputs {}, outputs {})",
+                meters,
+                inputs.scale.name(),
+                outputs.scale.name()
+            )
+        };
         let title = Line::from(title).centered().bold();
 
         BarChart::default()
@@ -1000,46 +1882,95 @@ fn render_pan_widgets(&self, frame: &mut Frame, pan_area: Rect) {
             .max(500)
     }
 
-    fn meter_bar(&self, clip: bool, name: &str, meter_value: f64, meter_max_value: f64) -> Bar<'_> {
+    /// Meters of all output pairs, grouped by mix
+    fn bus_meters_barchart(&self, width: u16) -> BarChart<'_> {
+        let group_gap = 2;
+        let pairs = self.ps.mixes.len() as u16;
+        // Fit all pairs in the pane, up to the strip width
+        let fit = (width.saturating_sub(2) / pairs.max(1)).saturating_sub(group_gap + 1) / 2;
+        let bar_width = fit.clamp(1, self.strip_width);
+
+        let scale = self.ps.metering.outputs.scale;
+        let title = format!("Outputs ({})", scale.name());
+        let mut chart = BarChart::default()
+            .block(Block::bordered().title(Line::from(title).centered().bold()))
+            .bar_width(bar_width)
+            .group_gap(group_gap)
+            .max(500);
+        for (i, mix) in self.ps.mixes.iter().enumerate() {
+            let bars: Vec<Bar> = ["L", "R"]
+                .iter()
+                .zip(&self.ps.bus_meters[i * 2..i * 2 + 2])
+                .map(|(side, meter)| self.meter_bar(meter, side, mix.color, scale))
+                .collect();
+            let mut label = Line::from(mix.name.as_str()).centered();
+            if i == self.active_mix_index {
+                label = label.bold();
+            }
+            chart = chart.data(BarGroup::default().label(label).bars(&bars));
+        }
+        chart
+    }
+
+    /// Meter bar showing the held peak, with the clip count before the name
+    fn meter_bar(
+        &self,
+        meter: &usb::Meter,
+        name: &str,
+        color: Option<usb::StripColor>,
+        scale: Scale,
+    ) -> Bar<'_> {
+        // The bar reaches 50 dB below the 0 of the scale and ends at full scale
         let a = -50.0;
-        let b = 0.0;
+        let b = scale.reading(0.0);
         let c = 0.0;
         let d = 500.0;
-        let t = meter_value;
+        let t = scale.reading(meter.value);
 
         let value: u64 = (c + ((d - c) / (b - a)) * (t - a)) as u64;
 
-        let mut strip_fg_color = Color::Rgb(0, 185, 0);
+        let strip_fg_color = match scale.zone(t) {
+            Zone::Low => Color::Rgb(0, 185, 0),
+            Zone::Normal => Color::Green,
+            Zone::High => Color::Yellow,
+            Zone::Hot => Color::Rgb(255, 165, 0),
+            Zone::Over => Color::Red,
+        };
         let mut label_fg_color = Color::White;
         let strip_bg_color = Color::DarkGray;
-        let label_bg_color = Color::Reset;
+        let mut label_bg_color = Color::Reset;
 
-        if clip {
-            label_fg_color = Color::Red;
-        }
-        if meter_value > -18.0 {
-            strip_fg_color = Color::Green;
+        if let Some(color) = color {
+            (label_fg_color, label_bg_color) = label_colors(color);
         }
-        if meter_value > -9.0 {
-            strip_fg_color = Color::Yellow;
-        }
-        if meter_value > -6.0 {
-            strip_fg_color = Color::Rgb(255, 165, 0);
-        }
-        if meter_value > -3.0 {
-            strip_fg_color = Color::Red;
+        if meter.clip {
+            label_fg_color = Color::Red;
         }
 
         let style = Style::new().fg(strip_fg_color).bg(strip_bg_color);
 
+        let label = if meter.clips > 0 {
+            format!("{}x {}", meter.clips, name)
+        } else {
+            name.to_string()
+        };
+
         Bar::default()
             .value(value)
-            .label(
-                Line::from(name.to_string())
-                    .fg(label_fg_color)
-                    .bg(label_bg_color),
-            )
-            .text_value(format!("{0:>5.1}", meter_max_value))
+            .label(Line::from(label).fg(label_fg_color).bg(label_bg_color))
+            .text_value(format!("{0:>5.1}", scale.reading(meter.hold)))
             .style(style)
     }
 }
+
+/// Label foreground and background colours for a custom strip colour
+fn label_colors(color: usb::StripColor) -> (Color, Color) {
+    // Black text on light colours, white text on dark colours
+    let luma = 0.299 * color.r as f64 + 0.587 * color.g as f64 + 0.114 * color.b as f64;
+    let fg = if luma > 127.5 {
+        Color::Black
+    } else {
+        Color::White
+    };
+    (fg, Color::Rgb(color.r, color.g, color.b))
+}
@@ -0,0 +1,42 @@
+//! Routing matrix of every input to every mix.
+//! The matrix cursor is the active mix and strip,
+//! so all strip actions work on the selected crosspoint.
+
+/// Move an index by `delta`, staying within `0..len`
+pub fn step(index: usize, delta: isize, len: usize) -> usize {
+    (index as isize + delta).clamp(0, len.saturating_sub(1) as isize) as usize
+}
+
+/// Short pan text: `L30`, `C` or `R30`
+pub fn format_pan(balance: f64) -> String {
+    let balance = balance.round();
+    if balance < 0.0 {
+        format!("L{}", -balance)
+    } else if balance > 0.0 {
+        format!("R{}", balance)
+    } else {
+        "C".to_string()
+    }
+}
+
+/// Short fader text, `-inf` at the bottom of the fader
+pub fn format_level(fader: f64, min: f64) -> String {
+    if fader <= min {
+        "-inf".to_string()
+    } else {
+        format!("{:.1}", fader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        assert_eq!(step(0, -1, 9), 0);
+        assert_eq!(step(3, 1, 9), 4);
+        assert_eq!(step(8, 1, 9), 8);
+        assert_eq!(step(0, 1, 0), 0);
+    }
+}
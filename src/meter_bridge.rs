@@ -0,0 +1,272 @@
+use crate::metering::{Scale, Zone};
+use crate::usb;
+use eframe::egui;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Meter values above this are counted as clipping
+const CLIP_THRESHOLD: f64 = -0.1;
+/// How long a peak is held before it follows the meter again
+const PEAK_HOLD: Duration = Duration::from_secs(2);
+/// Bottom and top of the meter scale in dB
+const SCALE_MIN: f64 = -50.0;
+const SCALE_MAX: f64 = 10.0;
+
+/// Peak hold and clip latch of one meter
+#[derive(Debug, Clone, Copy)]
+struct MeterState {
+    peak: f64,
+    peak_time: Instant,
+    clip: bool,
+}
+
+impl MeterState {
+    fn new(now: Instant) -> Self {
+        MeterState {
+            peak: SCALE_MIN,
+            peak_time: now,
+            clip: false,
+        }
+    }
+
+    /// Hold the sample `peak` and latch clips
+    fn update(&mut self, peak: f64, now: Instant) {
+        if peak >= self.peak || now.duration_since(self.peak_time) >= PEAK_HOLD {
+            self.peak = peak;
+            self.peak_time = now;
+        }
+        if peak > CLIP_THRESHOLD {
+            self.clip = true;
+        }
+    }
+}
+
+/// All input and bus meters in a window of their own
+pub struct MeterBridge {
+    pub open: bool,
+    inputs: Vec<MeterState>,
+    buses: Vec<MeterState>,
+}
+
+impl MeterBridge {
+    pub fn new() -> Self {
+        MeterBridge {
+            open: false,
+            inputs: Vec::new(),
+            buses: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, ps: &usb::PreSonusStudio1824c, now: Instant) {
+        for (states, meters) in [
+            (&mut self.inputs, &ps.channel_meters),
+            (&mut self.buses, &ps.bus_meters),
+        ] {
+            states.resize(meters.len(), MeterState::new(now));
+            for (state, meter) in states.iter_mut().zip(meters) {
+                state.update(meter.peak, now);
+            }
+        }
+    }
+
+    fn clear_clips(&mut self, ps: &mut usb::PreSonusStudio1824c) {
+        for state in self.inputs.iter_mut().chain(self.buses.iter_mut()) {
+            state.clip = false;
+        }
+        for meter in ps.channel_meters.iter_mut().chain(&mut ps.bus_meters) {
+            meter.clear();
+        }
+    }
+
+    /// Show the meter bridge viewport while it is open
+    pub fn show(&mut self, ctx: &egui::Context, ps: &Arc<Mutex<usb::PreSonusStudio1824c>>) {
+        if !self.open {
+            return;
+        }
+
+        let guard = ps.lock().unwrap();
+        self.update(&guard, Instant::now());
+        let input_names = guard.channel_labels();
+        let bus_names: Vec<String> = guard
+            .mixes
+            .iter()
+            .flat_map(|mix| [format!("{} L", mix.name), format!("{} R", mix.name)])
+            .collect();
+        let values: Vec<(f64, u32)> = guard
+            .channel_meters
+            .iter()
+            .chain(&guard.bus_meters)
+            .map(|m| (m.value, m.clips))
+            .collect();
+        let scales = (guard.metering.inputs.scale, guard.metering.outputs.scale);
+        drop(guard);
+        let mut clear_all = false;
+        let mut cleared = Vec::new();
+
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("meter_bridge"),
+            egui::ViewportBuilder::default()
+                .with_title("Baton Meter Bridge")
+                .with_inner_size([1100.0, 360.0])
+                .with_min_inner_size([400.0, 200.0]),
+            |ctx, _class| {
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    self.open = false;
+                }
+
+                egui::TopBottomPanel::top("meter_bridge_top").show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        clear_all = ui.button("Clear clips").clicked();
+                        ui.label("Click a meter to clear its clip indicator");
+                    });
+                });
+
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let count = self.inputs.len() + self.buses.len();
+                    let gap = 12.0;
+                    let spacing = ui.spacing().item_spacing.x;
+                    let width = ((ui.available_width() - gap - spacing * count as f32)
+                        / count.max(1) as f32)
+                        .max(4.0);
+                    let height = ui.available_height();
+
+                    ui.horizontal(|ui| {
+                        let states = self.inputs.iter_mut().chain(self.buses.iter_mut());
+                        let names = input_names.iter().chain(&bus_names);
+                        for (i, ((state, name), value)) in
+                            states.zip(names).zip(&values).enumerate()
+                        {
+                            if i == input_names.len() {
+                                ui.add_space(gap);
+                            }
+                            let scale = if i < input_names.len() {
+                                scales.0
+                            } else {
+                                scales.1
+                            };
+                            let size = egui::vec2(width, height);
+                            if draw_meter(ui, state, name, *value, scale, size) {
+                                cleared.push(i);
+                            }
+                        }
+                    });
+                });
+            },
+        );
+
+        if clear_all || !cleared.is_empty() {
+            let mut ps = ps.lock().unwrap();
+            if clear_all {
+                self.clear_clips(&mut ps);
+            }
+            let channels = ps.channel_meters.len();
+            for i in cleared {
+                if i < channels {
+                    ps.channel_meters[i].clear();
+                } else {
+                    ps.bus_meters[i - channels].clear();
+                }
+            }
+        }
+    }
+}
+
+/// Meter bar with peak hold line, clip indicator and name.
+/// Clicking it clears the clip, returns true if it was clicked.
+fn draw_meter(
+    ui: &mut egui::Ui,
+    state: &mut MeterState,
+    name: &str,
+    (value, clips): (f64, u32),
+    scale: Scale,
+    size: egui::Vec2,
+) -> bool {
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    let response = response.on_hover_text(format!(
+        "{}: {:.1} {}, peak {:.1}, {} clips",
+        name,
+        scale.reading(value),
+        scale.name(),
+        scale.reading(state.peak),
+        clips
+    ));
+    if response.clicked() {
+        state.clip = false;
+    }
+
+    let painter = ui.painter_at(rect);
+    let label_height = 14.0;
+    let clip_height = 8.0;
+    let meter_rect = egui::Rect::from_min_max(
+        egui::pos2(rect.min.x, rect.min.y + clip_height + 2.0),
+        egui::pos2(rect.max.x, rect.max.y - label_height),
+    );
+    let y_of = |db: f64| {
+        let normalized = ((db - SCALE_MIN) / (SCALE_MAX - SCALE_MIN)).clamp(0.0, 1.0);
+        meter_rect.max.y - normalized as f32 * meter_rect.height()
+    };
+
+    painter.rect_filled(meter_rect, 0.0, egui::Color32::from_gray(20));
+    let color = match scale.zone(scale.reading(value)) {
+        Zone::Over => egui::Color32::RED,
+        Zone::Hot => egui::Color32::from_rgb(255, 165, 0),
+        Zone::High => egui::Color32::YELLOW,
+        Zone::Normal => egui::Color32::GREEN,
+        Zone::Low => egui::Color32::from_rgb(0, 185, 0),
+    };
+    let bar = egui::Rect::from_min_max(egui::pos2(meter_rect.min.x, y_of(value)), meter_rect.max);
+    painter.rect_filled(bar, 0.0, color);
+
+    if state.peak > SCALE_MIN {
+        let y = y_of(state.peak);
+        painter.line_segment(
+            [
+                egui::pos2(meter_rect.min.x, y),
+                egui::pos2(meter_rect.max.x, y),
+            ],
+            egui::Stroke::new(2.0, egui::Color32::WHITE),
+        );
+    }
+
+    let clip_rect = egui::Rect::from_min_size(rect.min, egui::vec2(rect.width(), clip_height));
+    let clip_color = if state.clip {
+        egui::Color32::RED
+    } else {
+        egui::Color32::from_gray(40)
+    };
+    painter.rect_filled(clip_rect, 0.0, clip_color);
+
+    painter.text(
+        egui::pos2(rect.center().x, rect.max.y - label_height / 2.0),
+        egui::Align2::CENTER_CENTER,
+        name,
+        egui::FontId::proportional(9.0),
+        egui::Color32::LIGHT_GRAY,
+    );
+    response.clicked()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_hold() {
+        let start = Instant::now();
+        let mut state = MeterState::new(start);
+        state.update(-6.0, start);
+        state.update(-20.0, start + Duration::from_secs(1));
+        assert_eq!(state.peak, -6.0);
+        state.update(-20.0, start + PEAK_HOLD);
+        assert_eq!(state.peak, -20.0);
+    }
+
+    #[test]
+    fn test_clip_latch() {
+        let now = Instant::now();
+        let mut state = MeterState::new(now);
+        state.update(0.0, now);
+        state.update(-40.0, now);
+        assert!(state.clip);
+    }
+}
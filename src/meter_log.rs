@@ -0,0 +1,411 @@
+//! Optional logging of the meter levels to CSV or JSON Lines files,
+//! with a summary per meter when logging stops.
+
+use crate::metering::FLOOR_DB;
+use crate::usb::CLIP_THRESHOLD;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl LogFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// The `log` section of `metering.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub format: LogFormat,
+    /// Time between rows in milliseconds
+    pub interval_ms: u64,
+    /// Directory of the log files, `meter_logs` in the config directory if not set
+    pub directory: Option<PathBuf>,
+    /// Size in bytes after which a new file is started
+    pub max_file_bytes: u64,
+    /// Number of files kept of a session, older files are deleted
+    pub max_files: usize,
+    /// Level in dBFS for the time above threshold of the summary
+    pub threshold_db: f64,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            format: LogFormat::Csv,
+            interval_ms: 1000,
+            directory: None,
+            max_file_bytes: 10_000_000,
+            max_files: 10,
+            threshold_db: -6.0,
+        }
+    }
+}
+
+/// Levels of one meter over the whole session
+#[derive(Debug, Clone)]
+struct Summary {
+    max_peak: f64,
+    clips: u32,
+    above_threshold: Duration,
+    over: bool,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            max_peak: FLOOR_DB,
+            clips: 0,
+            above_threshold: Duration::ZERO,
+            over: false,
+        }
+    }
+}
+
+/// Writes the sample peaks of all meters to rotated log files
+pub struct MeterLogger {
+    settings: LogSettings,
+    dir: PathBuf,
+    /// Start time in file names, like `20261018T093000`
+    stamp: String,
+    names: Vec<String>,
+    file: BufWriter<File>,
+    /// Files of this session, oldest first
+    files: VecDeque<PathBuf>,
+    part: usize,
+    bytes: u64,
+    start: (Instant, SystemTime),
+    last_poll: Option<Instant>,
+    last_row: Option<Instant>,
+    /// Highest peak of each meter since the last row
+    row_peaks: Vec<f64>,
+    summaries: Vec<Summary>,
+}
+
+impl MeterLogger {
+    /// Start logging the meters `names` to `settings.directory`, or `default_dir`
+    pub fn start(
+        settings: LogSettings,
+        default_dir: &Path,
+        names: Vec<String>,
+    ) -> io::Result<Self> {
+        let dir = settings
+            .directory
+            .clone()
+            .unwrap_or_else(|| default_dir.to_path_buf());
+        fs::create_dir_all(&dir)?;
+        let wall = SystemTime::now();
+        let stamp: String = format_utc(wall)[..19]
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == 'T')
+            .collect();
+        let path = dir.join(format!("meters-{}.{}", stamp, settings.format.extension()));
+        let file = BufWriter::new(File::create(&path)?);
+        let count = names.len();
+        let mut logger = MeterLogger {
+            settings,
+            dir,
+            stamp,
+            names,
+            file,
+            files: VecDeque::from([path]),
+            part: 0,
+            bytes: 0,
+            start: (Instant::now(), wall),
+            last_poll: None,
+            last_row: None,
+            row_peaks: vec![FLOOR_DB; count],
+            summaries: vec![Summary::default(); count],
+        };
+        logger.write_header()?;
+        Ok(logger)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Add the sample `peaks` of all meters polled at `now`, writing a row every interval
+    pub fn sample(&mut self, peaks: &[f64], now: Instant) -> io::Result<()> {
+        let elapsed = self
+            .last_poll
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_poll = Some(now);
+
+        let threshold = self.settings.threshold_db;
+        for ((summary, row_peak), &peak) in self
+            .summaries
+            .iter_mut()
+            .zip(&mut self.row_peaks)
+            .zip(peaks)
+        {
+            let over = peak > CLIP_THRESHOLD;
+            if over && !summary.over {
+                summary.clips += 1;
+            }
+            summary.over = over;
+            summary.max_peak = summary.max_peak.max(peak);
+            if peak > threshold {
+                summary.above_threshold += elapsed;
+            }
+            *row_peak = row_peak.max(peak);
+        }
+
+        let interval = Duration::from_millis(self.settings.interval_ms);
+        if self
+            .last_row
+            .is_none_or(|last| now.duration_since(last) >= interval)
+        {
+            self.last_row = Some(now);
+            self.write_row(now)?;
+            self.row_peaks.fill(FLOOR_DB);
+        }
+        Ok(())
+    }
+
+    /// Stop logging and write the summary file, returns its path
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        self.file.flush()?;
+        let format = self.settings.format;
+        let path = self.dir.join(format!(
+            "meters-{}-summary.{}",
+            self.stamp,
+            format.extension()
+        ));
+        let mut out = String::new();
+        if format == LogFormat::Csv {
+            out.push_str("meter,max_peak_db,clips,seconds_above_threshold\n");
+        }
+        for (name, summary) in self.names.iter().zip(&self.summaries) {
+            let seconds = summary.above_threshold.as_secs_f64();
+            match format {
+                LogFormat::Csv => out.push_str(&format!(
+                    "{},{:.1},{},{:.1}\n",
+                    csv_field(name),
+                    summary.max_peak,
+                    summary.clips,
+                    seconds
+                )),
+                LogFormat::Jsonl => {
+                    let line = json!({
+                        "meter": name,
+                        "max_peak_db": summary.max_peak,
+                        "clips": summary.clips,
+                        "seconds_above_threshold": seconds,
+                        "threshold_db": self.settings.threshold_db,
+                    });
+                    out.push_str(&format!("{}\n", line));
+                }
+            }
+        }
+        fs::write(&path, out)?;
+        Ok(path)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.settings.format == LogFormat::Csv {
+            let names: Vec<String> = self.names.iter().map(|n| csv_field(n)).collect();
+            self.write(&format!("time,{}\n", names.join(",")))?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, now: Instant) -> io::Result<()> {
+        let time = format_utc(self.start.1 + now.duration_since(self.start.0));
+        let row = match self.settings.format {
+            LogFormat::Csv => {
+                let peaks: Vec<String> =
+                    self.row_peaks.iter().map(|p| format!("{:.1}", p)).collect();
+                format!("{},{}\n", time, peaks.join(","))
+            }
+            LogFormat::Jsonl => {
+                let peaks: serde_json::Map<String, serde_json::Value> = self
+                    .names
+                    .iter()
+                    .zip(&self.row_peaks)
+                    .map(|(name, peak)| (name.clone(), json!((peak * 10.0).round() / 10.0)))
+                    .collect();
+                format!("{}\n", json!({ "time": time, "peaks": peaks }))
+            }
+        };
+        self.write(&row)?;
+        self.file.flush()?;
+        if self.bytes >= self.settings.max_file_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        self.bytes += text.len() as u64;
+        Ok(())
+    }
+
+    /// Continue in a new file and delete the oldest files above `max_files`
+    fn rotate(&mut self) -> io::Result<()> {
+        self.part += 1;
+        let path = self.dir.join(format!(
+            "meters-{}.{}.{}",
+            self.stamp,
+            self.part,
+            self.settings.format.extension()
+        ));
+        self.file = BufWriter::new(File::create(&path)?);
+        self.files.push_back(path);
+        self.bytes = 0;
+        while self.files.len() > self.settings.max_files.max(1) {
+            if let Some(oldest) = self.files.pop_front() {
+                fs::remove_file(oldest)?;
+            }
+        }
+        self.write_header()
+    }
+}
+
+/// Quote a CSV field if needed
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// RFC 3339 UTC time with milliseconds, like `2026-10-18T09:30:00.000Z`
+fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of a day number counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("baton-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_322_096_250);
+        assert_eq!(format_utc(time), "2026-10-18T11:14:56.250Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(format_utc(leap_day), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_csv_rows_and_summary() {
+        let dir = test_dir("meter-log");
+        let settings = LogSettings {
+            interval_ms: 100,
+            ..LogSettings::default()
+        };
+        let names = vec!["Kick".to_string(), "Vox, lead".to_string()];
+        let mut logger = MeterLogger::start(settings, &dir, names).unwrap();
+        let start = Instant::now();
+        let step = Duration::from_millis(50);
+        for (i, peaks) in [[-20.0, -3.0], [0.0, -2.0], [0.0, -2.0], [-30.0, -50.0]]
+            .iter()
+            .enumerate()
+        {
+            logger.sample(peaks, start + step * i as u32).unwrap();
+        }
+        let summary = fs::read_to_string(logger.finish().unwrap()).unwrap();
+
+        let log = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| !p.to_string_lossy().contains("summary"))
+            .unwrap();
+        let log = fs::read_to_string(log).unwrap();
+        let rows: Vec<&str> = log.lines().collect();
+        assert_eq!(rows[0], "time,Kick,\"Vox, lead\"");
+        // Rows every 100 ms with the highest peak since the previous row
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].ends_with(",-20.0,-3.0"));
+        assert!(rows[2].ends_with(",0.0,-2.0"));
+
+        let summary: Vec<&str> = summary.lines().collect();
+        // Two polls over in a row are one clip, -6 dB is exceeded for 50 ms per poll
+        assert_eq!(summary[1], "Kick,0.0,1,0.1");
+        assert_eq!(summary[2], "\"Vox, lead\",-2.0,0,0.1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = test_dir("meter-log-rotation");
+        let settings = LogSettings {
+            format: LogFormat::Jsonl,
+            interval_ms: 0,
+            max_file_bytes: 1,
+            max_files: 2,
+            ..LogSettings::default()
+        };
+        let mut logger = MeterLogger::start(settings, &dir, vec!["Kick".to_string()]).unwrap();
+        let start = Instant::now();
+        for i in 0..5 {
+            logger
+                .sample(&[-12.0], start + Duration::from_millis(i))
+                .unwrap();
+        }
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 2);
+        // The newest file was just started and is still empty
+        let row = fs::read_to_string(&logger.files[0]).unwrap();
+        let row: serde_json::Value = serde_json::from_str(row.trim()).unwrap();
+        assert_eq!(row["peaks"]["Kick"], json!(-12.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
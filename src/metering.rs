@@ -0,0 +1,361 @@
+use crate::meter_log::LogSettings;
+use crate::usb::Strip;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Lowest meter level in dB, also used for silence
+pub const FLOOR_DB: f64 = -96.0;
+
+/// Release of a digital PPM: 20 dB in 1.7 seconds
+const PPM_RELEASE_DB_PER_SECOND: f64 = 20.0 / 1.7;
+/// Time constant of a VU meter, reaching 99% of a step in 300 ms
+const VU_TIME_CONSTANT: f64 = 0.3 / 4.605_170_185_988_091;
+/// How long a peak is held before it decays
+pub const PEAK_HOLD: Duration = Duration::from_secs(2);
+/// Fall of a held peak after the hold time
+const PEAK_DECAY_DB_PER_SECOND: f64 = 20.0;
+
+/// How meter readings follow the signal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Ballistics {
+    /// Instantaneous sample peak as reported by the interface
+    #[default]
+    SamplePeak,
+    /// Digital peak programme meter, instant attack and slow release
+    Ppm,
+    /// Volume unit meter, 300 ms integration
+    Vu,
+    /// Root mean square over a sliding window
+    Rms { window_ms: u64 },
+}
+
+/// Reference level of the displayed meter values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scale {
+    #[default]
+    #[serde(rename = "dbfs")]
+    Dbfs,
+    #[serde(rename = "k-12")]
+    K12,
+    #[serde(rename = "k-14")]
+    K14,
+    #[serde(rename = "k-20")]
+    K20,
+}
+
+impl Scale {
+    /// Level in dBFS that reads 0 on this scale
+    fn reference(self) -> f64 {
+        match self {
+            Scale::Dbfs => 0.0,
+            Scale::K12 => -12.0,
+            Scale::K14 => -14.0,
+            Scale::K20 => -20.0,
+        }
+    }
+
+    /// Reading of a dBFS level on this scale
+    pub fn reading(self, dbfs: f64) -> f64 {
+        dbfs - self.reference()
+    }
+
+    /// Colour zone of a `reading` on this scale. K-scales follow the K-System,
+    /// yellow from 0 and red above +4.
+    pub fn zone(self, reading: f64) -> Zone {
+        let limits = match self {
+            Scale::Dbfs => [-18.0, -9.0, -6.0, -3.0],
+            Scale::K12 | Scale::K14 | Scale::K20 => [-10.0, 0.0, 4.0, 4.0],
+        };
+        match limits.iter().filter(|&&limit| reading > limit).count() {
+            0 => Zone::Low,
+            1 => Zone::Normal,
+            2 => Zone::High,
+            3 => Zone::Hot,
+            _ => Zone::Over,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scale::Dbfs => "dBFS",
+            Scale::K12 => "K-12",
+            Scale::K14 => "K-14",
+            Scale::K20 => "K-20",
+        }
+    }
+}
+
+/// Colour zone of a meter reading, from quiet to close to full scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Low,
+    Normal,
+    High,
+    Hot,
+    Over,
+}
+
+/// Ballistics and scale of a group of meters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeterSettings {
+    pub ballistics: Ballistics,
+    pub scale: Scale,
+}
+
+/// Meter settings for the input and output meters, stored in `metering.json`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub inputs: MeterSettings,
+    pub outputs: MeterSettings,
+    /// Meter logging, off if not set
+    pub log: Option<LogSettings>,
+}
+
+/// State of one meter between polls
+#[derive(Debug, Clone)]
+pub struct Ballistic {
+    last: Option<Instant>,
+    /// PPM reading in dB
+    ppm: f64,
+    /// VU reading as linear amplitude
+    vu: f64,
+    /// Recent power values for RMS
+    history: VecDeque<(Instant, f64)>,
+}
+
+impl Default for Ballistic {
+    fn default() -> Self {
+        Ballistic {
+            last: None,
+            ppm: FLOOR_DB,
+            vu: 0.0,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl Ballistic {
+    /// Next reading in dBFS for a sample peak level `db` polled at `now`
+    pub fn process(&mut self, ballistics: Ballistics, db: f64, now: Instant) -> f64 {
+        let dt = self
+            .last
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+        self.last = Some(now);
+        let amplitude = db_to_gain(db);
+
+        let reading = match ballistics {
+            Ballistics::SamplePeak => db,
+            Ballistics::Ppm => {
+                self.ppm = db.max(self.ppm - PPM_RELEASE_DB_PER_SECOND * dt);
+                self.ppm
+            }
+            Ballistics::Vu => {
+                let k = if dt > 0.0 {
+                    1.0 - (-dt / VU_TIME_CONSTANT).exp()
+                } else {
+                    1.0
+                };
+                self.vu += k * (amplitude - self.vu);
+                gain_to_db(self.vu)
+            }
+            Ballistics::Rms { window_ms } => {
+                let window = std::time::Duration::from_millis(window_ms);
+                self.history.push_back((now, amplitude * amplitude));
+                while let Some(&(time, _)) = self.history.front() {
+                    if now.duration_since(time) < window || self.history.len() == 1 {
+                        break;
+                    }
+                    self.history.pop_front();
+                }
+                let power: f64 =
+                    self.history.iter().map(|(_, p)| p).sum::<f64>() / self.history.len() as f64;
+                gain_to_db(power.sqrt())
+            }
+        };
+        reading.max(FLOOR_DB)
+    }
+}
+
+/// Peak that is held for `PEAK_HOLD`, then decays towards the signal
+#[derive(Debug, Clone)]
+pub struct PeakHold {
+    value: f64,
+    held_at: Option<Instant>,
+}
+
+impl Default for PeakHold {
+    fn default() -> Self {
+        PeakHold {
+            value: FLOOR_DB,
+            held_at: None,
+        }
+    }
+}
+
+impl PeakHold {
+    /// Held peak in dBFS after a sample peak `db` polled at `now`
+    pub fn update(&mut self, db: f64, now: Instant) -> f64 {
+        let held = self
+            .held_at
+            .map_or(Duration::ZERO, |t| now.duration_since(t));
+        let decayed = if held > PEAK_HOLD {
+            self.value - PEAK_DECAY_DB_PER_SECOND * (held - PEAK_HOLD).as_secs_f64()
+        } else {
+            self.value
+        };
+        if db >= decayed || self.held_at.is_none() {
+            self.value = db;
+            self.held_at = Some(now);
+        }
+        decayed.max(db).max(FLOOR_DB)
+    }
+}
+
+/// Gains in dB from an input to the left and right of a mix, after the fader, pan
+/// and mute of its strip. None if the strip is silent in the mix.
+pub fn post_fader_gains(strip: &Strip, pan_law: f64) -> Option<(f64, f64)> {
+    // Same rule as the device: a soloed strip plays even if muted
+    let muted = (strip.mute || strip.mute_by_solo) && !strip.solo;
+    if muted || strip.fader <= strip.min {
+        return None;
+    }
+    Some(strip.pan_levels(pan_law))
+}
+
+fn db_to_gain(db: f64) -> f64 {
+    if db <= FLOOR_DB {
+        0.0
+    } else {
+        10f64.powf(db / 20.0)
+    }
+}
+
+fn gain_to_db(gain: f64) -> f64 {
+    if gain <= 0.0 {
+        FLOOR_DB
+    } else {
+        (20.0 * gain.log10()).max(FLOOR_DB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn run(ballistics: Ballistics, levels: &[f64], step: Duration) -> f64 {
+        let start = Instant::now();
+        let mut meter = Ballistic::default();
+        let mut reading = FLOOR_DB;
+        for (i, db) in levels.iter().enumerate() {
+            reading = meter.process(ballistics, *db, start + step * i as u32);
+        }
+        reading
+    }
+
+    #[test]
+    fn test_sample_peak() {
+        let reading = run(
+            Ballistics::SamplePeak,
+            &[-6.0, -40.0],
+            Duration::from_millis(100),
+        );
+        assert_eq!(reading, -40.0);
+    }
+
+    #[test]
+    fn test_ppm_release() {
+        // 20 dB of release takes 1.7 seconds
+        let mut levels = vec![-10.0];
+        levels.extend([FLOOR_DB; 17]);
+        let reading = run(Ballistics::Ppm, &levels, Duration::from_millis(100));
+        assert!((reading - -30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_vu_integration() {
+        // A step reaches 99% of its amplitude after 300 ms
+        let levels = [FLOOR_DB, -20.0, -20.0, -20.0, -20.0];
+        let vu = run(Ballistics::Vu, &levels[..2], Duration::from_millis(100));
+        assert!(vu < -20.0);
+        let vu = run(Ballistics::Vu, &levels, Duration::from_millis(100));
+        assert!((vu - -20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_rms_window() {
+        // Full scale and silence average to half the power
+        let rms = Ballistics::Rms { window_ms: 300 };
+        let reading = run(rms, &[0.0, FLOOR_DB], Duration::from_millis(100));
+        assert!((reading - -3.0103).abs() < 1e-3);
+        // Values older than the window are dropped
+        let reading = run(
+            rms,
+            &[0.0, -20.0, -20.0, -20.0, -20.0],
+            Duration::from_millis(100),
+        );
+        assert!((reading - -20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_peak_hold() {
+        let start = Instant::now();
+        let mut hold = PeakHold::default();
+        assert_eq!(hold.update(-6.0, start), -6.0);
+        assert_eq!(hold.update(-40.0, start + PEAK_HOLD), -6.0);
+        // Decays at 20 dB per second after the hold time
+        let reading = hold.update(-40.0, start + PEAK_HOLD + Duration::from_millis(500));
+        assert!((reading - -16.0).abs() < 1e-9);
+        assert_eq!(hold.update(-40.0, start + PEAK_HOLD * 3), -40.0);
+        // A new peak restarts the hold
+        assert_eq!(hold.update(-3.0, start + PEAK_HOLD * 4), -3.0);
+    }
+
+    #[test]
+    fn test_post_fader_gains() {
+        let mut strip = crate::usb::Mix::new(String::new(), crate::usb::StripKind::Bus, 0, 1)
+            .strips
+            .channel_strips
+            .remove(0);
+        strip.fader = -10.0;
+        assert_eq!(post_fader_gains(&strip, -3.0), Some((-13.0, -13.0)));
+        strip.balance = -100.0;
+        let (left, right) = post_fader_gains(&strip, -3.0).unwrap();
+        assert_eq!(left, -10.0);
+        assert!(right < -100.0);
+
+        strip.mute = true;
+        assert_eq!(post_fader_gains(&strip, 0.0), None);
+        strip.solo = true;
+        assert!(post_fader_gains(&strip, 0.0).is_some());
+        strip.fader = strip.min;
+        assert_eq!(post_fader_gains(&strip, 0.0), None);
+    }
+
+    #[test]
+    fn test_scale_and_settings() {
+        assert_eq!(Scale::K20.reading(-20.0), 0.0);
+        assert_eq!(Scale::Dbfs.reading(-20.0), -20.0);
+        assert_eq!(Scale::Dbfs.zone(-20.0), Zone::Low);
+        assert_eq!(Scale::Dbfs.zone(-4.0), Zone::Hot);
+        assert_eq!(Scale::Dbfs.zone(-1.0), Zone::Over);
+        assert_eq!(Scale::K14.zone(Scale::K14.reading(-20.0)), Zone::Normal);
+        assert_eq!(Scale::K14.zone(Scale::K14.reading(-12.0)), Zone::High);
+        assert_eq!(Scale::K14.zone(Scale::K14.reading(-6.0)), Zone::Over);
+
+        let settings: Settings = serde_json::from_str(
+            r#"{ "inputs": { "ballistics": { "type": "rms", "window_ms": 300 }, "scale": "k-14" } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.inputs.ballistics,
+            Ballistics::Rms { window_ms: 300 }
+        );
+        assert_eq!(settings.inputs.scale, Scale::K14);
+        assert_eq!(settings.outputs, MeterSettings::default());
+    }
+}
@@ -0,0 +1,274 @@
+//! Optional HTTP endpoint with the meters and states of the interface
+//! in the Prometheus text format, enabled by `metrics.json`.
+
+use crate::usb::Meter;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Settings of `metrics.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Address to listen on
+    pub address: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            address: "127.0.0.1:9184".to_string(),
+        }
+    }
+}
+
+/// Values exported at one poll
+pub struct Snapshot<'a> {
+    /// Names and meters of the inputs
+    pub inputs: Vec<(&'a str, &'a Meter)>,
+    /// Names and meters of the outputs
+    pub outputs: Vec<(String, &'a Meter)>,
+    pub phantom_power: bool,
+    pub in_1_2_line: bool,
+    pub main_mute: bool,
+    pub main_mono: bool,
+    pub poll_errors: u64,
+    pub write_errors: u64,
+    pub poll_latency: Duration,
+}
+
+/// Name, help text and value of a metric of every meter
+type MeterMetric = (&'static str, &'static str, fn(&Meter) -> f64);
+
+/// Serves the page of the last `update` on `/metrics`
+pub struct MetricsServer {
+    page: Arc<Mutex<String>>,
+    address: SocketAddr,
+}
+
+impl MetricsServer {
+    pub fn start(settings: &MetricsSettings) -> io::Result<Self> {
+        let listener = TcpListener::bind(&settings.address)?;
+        let address = listener.local_addr()?;
+        let page = Arc::new(Mutex::new(String::new()));
+        let served = page.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve(stream, &served));
+                if let Err(e) = result {
+                    log::warn!("Metrics request failed: {}", e);
+                }
+            }
+        });
+        Ok(MetricsServer { page, address })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn update(&self, snapshot: &Snapshot) {
+        *self.page.lock().unwrap() = render(snapshot);
+    }
+}
+
+/// Answer one HTTP request
+fn serve(mut stream: TcpStream, page: &Mutex<String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let response = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = page.lock().unwrap().clone();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())
+}
+
+/// The snapshot in the Prometheus text format
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut out = String::new();
+    let meters: Vec<(&str, usize, &str, &Meter)> = snapshot
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, (name, meter))| ("input", i + 1, *name, *meter))
+        .chain(
+            snapshot
+                .outputs
+                .iter()
+                .enumerate()
+                .map(|(i, (name, meter))| ("output", i + 1, name.as_str(), *meter)),
+        )
+        .collect();
+
+    let families: [MeterMetric; 4] = [
+        (
+            "baton_meter_level_dbfs",
+            "Meter reading with its ballistics",
+            |m| m.value,
+        ),
+        (
+            "baton_meter_peak_dbfs",
+            "Sample peak of the last poll",
+            |m| m.peak,
+        ),
+        (
+            "baton_meter_max_dbfs",
+            "Highest sample peak since the meter was cleared",
+            |m| m.max,
+        ),
+        (
+            "baton_meter_clips",
+            "Overs since the meter was cleared",
+            |m| m.clips as f64,
+        ),
+    ];
+    for (metric, help, value) in families {
+        let _ = writeln!(out, "# HELP {} {}", metric, help);
+        let _ = writeln!(out, "# TYPE {} gauge", metric);
+        for (group, channel, name, meter) in &meters {
+            let _ = writeln!(
+                out,
+                "{}{{group=\"{}\",channel=\"{}\",name=\"{}\"}} {}",
+                metric,
+                group,
+                channel,
+                escape_label(name),
+                value(meter)
+            );
+        }
+    }
+
+    let states = [
+        (
+            "baton_phantom_power",
+            "48V phantom power",
+            snapshot.phantom_power,
+        ),
+        (
+            "baton_line_1_2",
+            "Inputs 1-2 at line level",
+            snapshot.in_1_2_line,
+        ),
+        ("baton_main_mute", "Main output muted", snapshot.main_mute),
+        ("baton_main_mono", "Main output mono", snapshot.main_mono),
+    ];
+    for (metric, help, on) in states {
+        let _ = writeln!(out, "# HELP {} {}", metric, help);
+        let _ = writeln!(out, "# TYPE {} gauge", metric);
+        let _ = writeln!(out, "{} {}", metric, on as u8);
+    }
+
+    let _ = writeln!(out, "# HELP baton_usb_errors_total Failed USB transfers");
+    let _ = writeln!(out, "# TYPE baton_usb_errors_total counter");
+    let _ = writeln!(
+        out,
+        "baton_usb_errors_total{{operation=\"poll\"}} {}",
+        snapshot.poll_errors
+    );
+    let _ = writeln!(
+        out,
+        "baton_usb_errors_total{{operation=\"write\"}} {}",
+        snapshot.write_errors
+    );
+    let _ = writeln!(
+        out,
+        "# HELP baton_poll_latency_seconds Duration of the last state poll"
+    );
+    let _ = writeln!(out, "# TYPE baton_poll_latency_seconds gauge");
+    let _ = writeln!(
+        out,
+        "baton_poll_latency_seconds {}",
+        snapshot.poll_latency.as_secs_f64()
+    );
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot<'a>(input: &'a Meter, output: &'a Meter) -> Snapshot<'a> {
+        Snapshot {
+            inputs: vec![("Mic \"1\"", input)],
+            outputs: vec![("Main L".to_string(), output)],
+            phantom_power: true,
+            in_1_2_line: false,
+            main_mute: false,
+            main_mono: true,
+            poll_errors: 2,
+            write_errors: 0,
+            poll_latency: Duration::from_millis(3),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut input = Meter::new();
+        input.value = -12.5;
+        input.clips = 3;
+        let output = Meter::new();
+        let page = render(&snapshot(&input, &output));
+
+        assert!(page.contains(
+            "baton_meter_level_dbfs{group=\"input\",channel=\"1\",name=\"Mic \\\"1\\\"\"} -12.5\n"
+        ));
+        assert!(
+            page.contains("baton_meter_clips{group=\"output\",channel=\"1\",name=\"Main L\"} 0\n")
+        );
+        assert!(page.contains("baton_phantom_power 1\n"));
+        assert!(page.contains("baton_main_mute 0\n"));
+        assert!(page.contains("baton_usb_errors_total{operation=\"poll\"} 2\n"));
+        assert!(page.contains("baton_poll_latency_seconds 0.003\n"));
+    }
+
+    #[test]
+    fn test_serve() {
+        let settings = MetricsSettings {
+            address: "127.0.0.1:0".to_string(),
+        };
+        let server = MetricsServer::start(&settings).unwrap();
+        let meter = Meter::new();
+        server.update(&snapshot(&meter, &meter));
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(server.address()).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("baton_main_mono 1\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
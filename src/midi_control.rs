@@ -33,6 +33,8 @@ pub enum GlobalControl {
     MainMono,
     ActiveMixSelect,
     ActiveStripSelect,
+    AbToggle,
+    AbCopyAToB,
 }
 
 /// What a MIDI control maps to
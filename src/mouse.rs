@@ -0,0 +1,171 @@
+use ratatui::layout::Rect;
+
+/// Screen areas of the last drawn frame, used to find what was clicked
+#[derive(Debug, Default, Clone)]
+pub struct MouseAreas {
+    pub meters: Rect,
+    pub pans: Rect,
+    pub faders: Rect,
+    /// Column ranges of the state bar toggles
+    pub toggles: Vec<(u16, u16, crate::keymap::Action)>,
+    pub state: Rect,
+    pub matrix: Rect,
+    /// First mix shown in the routing matrix
+    pub matrix_first_mix: usize,
+}
+
+/// What a mouse drag started on, so it keeps changing the same strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drag {
+    Fader(usize),
+    Pan(usize),
+}
+
+/// Layout of a row of bars of `width` columns with one column gap,
+/// starting with bar `first` at `x`
+#[derive(Debug, Clone, Copy)]
+pub struct Bars {
+    pub x: u16,
+    pub first: usize,
+    pub width: u16,
+    pub count: usize,
+}
+
+impl Bars {
+    /// Bar at column `x` and the column within the bar, or None for gaps
+    pub fn at(&self, x: u16) -> Option<(usize, u16)> {
+        let offset = x.checked_sub(self.x)?;
+        let index = self.first + (offset / (self.width + 1)) as usize;
+        let column = offset % (self.width + 1);
+        (index < self.count && column < self.width).then_some((index, column))
+    }
+
+    /// Column within bar `index` for `x`, clamped to the bar while dragging
+    pub fn column(&self, index: usize, x: u16) -> u16 {
+        let start = self.x as i32 + (index as i32 - self.first as i32) * (self.width as i32 + 1);
+        (x as i32 - start).clamp(0, self.width as i32 - 1) as u16
+    }
+}
+
+/// Bars of a bordered bar chart
+pub fn chart_bars(area: Rect, first: usize, width: u16, count: usize) -> Bars {
+    Bars {
+        x: area.x + 1,
+        first,
+        width,
+        count,
+    }
+}
+
+/// Height of a bordered bar chart at row `y`, from 0.0 at the bottom to 1.0 at the top.
+/// None for the label row and the border.
+pub fn chart_level(area: Rect, y: u16) -> Option<f64> {
+    // Inside the border, the bottom row holds the labels
+    let top = area.y + 1;
+    let bottom = area.bottom().checked_sub(2)?;
+    if y < top || y >= bottom || bottom <= top {
+        return None;
+    }
+    let height = (bottom - top) as f64;
+    Some((bottom - y) as f64 / height)
+}
+
+/// Like `chart_level`, but rows above and below the bars give 1.0 and 0.0 while dragging
+pub fn drag_level(area: Rect, y: u16) -> Option<f64> {
+    let bottom = area.bottom().checked_sub(3)?;
+    chart_level(area, y.clamp(area.y + 1, bottom)).map(|level| if y > bottom { 0.0 } else { level })
+}
+
+/// Mix of the routing matrix row `y`, each mix takes two rows below the header
+pub fn matrix_row(area: Rect, y: u16, first_mix: usize) -> Option<usize> {
+    let offset = y.checked_sub(area.y + 2)?;
+    (y < area.bottom().saturating_sub(1)).then_some(first_mix + (offset / 2) as usize)
+}
+
+/// True if `y` is the label row of a bordered bar chart
+pub fn is_chart_label(area: Rect, y: u16) -> bool {
+    area.height >= 3 && y == area.bottom() - 2
+}
+
+/// Balance for a click at `column` of a pan widget `width` columns wide
+pub fn pan_balance(column: u16, width: u16) -> f64 {
+    // The widget draws an odd number of columns with the centre in the middle
+    let width = if width.is_multiple_of(2) {
+        width - 1
+    } else {
+        width
+    };
+    if width <= 1 || column == width / 2 {
+        return 0.0;
+    }
+    // The edge columns are hard left and right
+    let balance = column.min(width - 1) as f64 / (width - 1) as f64 * 200.0 - 100.0;
+    balance.round().clamp(-100.0, 100.0)
+}
+
+/// Fader value in dB for a bar chart level, matching the scaling of the fader bars
+pub fn fader_value(level: f64, min: f64, max: f64) -> f64 {
+    let value = level * 500.0;
+    min + (value - 20.0) / (500.0 - 20.0) * (max - min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bars_at() {
+        let bars = chart_bars(Rect::new(0, 0, 80, 20), 2, 5, 6);
+        assert_eq!(bars.at(0), None);
+        assert_eq!(bars.at(1), Some((2, 0)));
+        assert_eq!(bars.at(5), Some((2, 4)));
+        assert_eq!(bars.at(6), None);
+        assert_eq!(bars.at(7), Some((3, 0)));
+        assert_eq!(bars.at(19), Some((5, 0)));
+        assert_eq!(bars.at(25), None);
+        assert_eq!(bars.column(3, 0), 0);
+        assert_eq!(bars.column(3, 9), 2);
+        assert_eq!(bars.column(3, 40), 4);
+    }
+
+    #[test]
+    fn test_chart_level() {
+        let area = Rect::new(0, 10, 20, 12);
+        assert_eq!(chart_level(area, 10), None);
+        assert_eq!(chart_level(area, 11), Some(1.0));
+        assert_eq!(chart_level(area, 20), None);
+        assert!(is_chart_label(area, 20));
+        assert!(chart_level(area, 19).unwrap() > 0.0);
+        assert_eq!(drag_level(area, 0), Some(1.0));
+        assert_eq!(drag_level(area, 30), Some(0.0));
+    }
+
+    #[test]
+    fn test_matrix_row() {
+        let area = Rect::new(0, 5, 80, 21);
+        assert_eq!(matrix_row(area, 6, 0), None);
+        assert_eq!(matrix_row(area, 7, 0), Some(0));
+        assert_eq!(matrix_row(area, 8, 0), Some(0));
+        assert_eq!(matrix_row(area, 9, 2), Some(3));
+        assert_eq!(matrix_row(area, 25, 0), None);
+    }
+
+    #[test]
+    fn test_pan_balance() {
+        assert_eq!(pan_balance(2, 5), 0.0);
+        assert_eq!(pan_balance(0, 5), -100.0);
+        assert_eq!(pan_balance(1, 5), -50.0);
+        assert_eq!(pan_balance(4, 5), 100.0);
+        assert_eq!(pan_balance(9, 5), 100.0);
+        assert_eq!(pan_balance(0, 3), -100.0);
+        assert_eq!(pan_balance(2, 3), 100.0);
+        assert_eq!(pan_balance(3, 4), 100.0);
+        assert_eq!(pan_balance(0, 1), 0.0);
+    }
+
+    #[test]
+    fn test_fader_value() {
+        assert_eq!(fader_value(1.0, -96.0, 10.0), 10.0);
+        assert_eq!(fader_value(0.04, -96.0, 10.0), -96.0);
+    }
+}
@@ -0,0 +1,211 @@
+//! Optional live labels for the DAW strips, enabled by `ports.json`.
+//! The PipeWire or JACK ports connected to the playback channels of the
+//! interface are listed with `pw-link` or `jack_lsp` in the background.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortSource {
+    #[serde(rename = "pipewire")]
+    PipeWire,
+    Jack,
+}
+
+/// What a DAW strip is labelled with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LabelKind {
+    /// Name of the connected client, like `ardour`
+    Client,
+    /// Name of the connected port without the client, like `Master/audio_out 1`
+    Port,
+}
+
+/// Settings of `ports.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortSettings {
+    pub source: PortSource,
+    /// Part of the port names of the interface, like `Studio_1824c` or `system`
+    pub device: String,
+    pub label: LabelKind,
+    /// How often the ports are listed
+    pub interval_ms: u64,
+}
+
+impl Default for PortSettings {
+    fn default() -> Self {
+        PortSettings {
+            source: PortSource::PipeWire,
+            device: "1824c".to_string(),
+            label: LabelKind::Client,
+            interval_ms: 2000,
+        }
+    }
+}
+
+/// Lists the connected ports in a background thread
+pub struct PortWatcher {
+    labels: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl PortWatcher {
+    /// Start listing the ports connected to the first `channels` playback channels
+    pub fn start(settings: PortSettings, channels: usize) -> Self {
+        let labels = Arc::new(Mutex::new(vec![None; channels]));
+        let shared = labels.clone();
+        std::thread::spawn(move || {
+            let mut failing = false;
+            loop {
+                match list_links(settings.source) {
+                    Ok(output) => {
+                        failing = false;
+                        *shared.lock().unwrap() =
+                            parse_links(&output, &settings.device, settings.label, channels);
+                    }
+                    Err(e) => {
+                        // Log once until the ports can be listed again
+                        if !failing {
+                            log::warn!("Failed to list the audio ports: {}", e);
+                            failing = true;
+                        }
+                        *shared.lock().unwrap() = vec![None; channels];
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(settings.interval_ms.max(100)));
+            }
+        });
+        PortWatcher { labels }
+    }
+
+    /// Label of each playback channel, None if nothing is connected
+    pub fn labels(&self) -> Vec<Option<String>> {
+        self.labels.lock().unwrap().clone()
+    }
+}
+
+fn list_links(source: PortSource) -> io::Result<String> {
+    let output = match source {
+        PortSource::PipeWire => Command::new("pw-link").arg("--links").output()?,
+        PortSource::Jack => Command::new("jack_lsp").arg("--connections").output()?,
+    };
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(error.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Labels of the playback channels of `device` in the output of
+/// `pw-link --links` or `jack_lsp --connections`.
+/// Both list a port, then the ports connected to it indented below.
+pub fn parse_links(
+    output: &str,
+    device: &str,
+    label: LabelKind,
+    channels: usize,
+) -> Vec<Option<String>> {
+    let mut labels: Vec<Option<String>> = vec![None; channels];
+    let mut channel = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            channel = line
+                .contains(device)
+                .then(|| playback_channel(line))
+                .flatten()
+                .filter(|&channel| channel < channels);
+            continue;
+        }
+        let line = line.trim();
+        // pw-link lists the links both ways, only the ones into the port count
+        if line.starts_with("|->") {
+            continue;
+        }
+        let Some(channel) = channel else {
+            continue;
+        };
+        let port = line.trim_start_matches("|<-").trim();
+        let name = match (label, port.split_once(':')) {
+            (LabelKind::Client, Some((client, _))) => client,
+            (LabelKind::Port, Some((_, port))) => port,
+            (_, None) => port,
+        };
+        match &mut labels[channel] {
+            Some(names) if names.split(", ").any(|n| n == name) => (),
+            Some(names) => {
+                names.push_str(", ");
+                names.push_str(name);
+            }
+            None => labels[channel] = Some(name.to_string()),
+        }
+    }
+    labels
+}
+
+/// Channel index of a playback port: `playback_AUX0` (PipeWire pro audio),
+/// `playback_FL` and `playback_FR` (PipeWire stereo), or `playback_1` (JACK)
+fn playback_channel(port: &str) -> Option<usize> {
+    let name = port.rsplit(':').next()?.strip_prefix("playback_")?;
+    match name {
+        "FL" => Some(0),
+        "FR" => Some(1),
+        _ => match name.strip_prefix("AUX") {
+            Some(number) => number.parse().ok(),
+            None => name.parse::<usize>().ok()?.checked_sub(1),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PW_LINK: &str = "\
+alsa_output.usb-PreSonus_Studio_1824c_SC1-00.pro-output-0:playback_AUX0
+  |<- ardour:Master/audio_out 1
+alsa_output.usb-PreSonus_Studio_1824c_SC1-00.pro-output-0:playback_AUX1
+  |<- ardour:Master/audio_out 2
+alsa_output.usb-PreSonus_Studio_1824c_SC1-00.pro-output-0:playback_AUX4
+  |<- Firefox:output_FL
+  |<- ardour:Click/audio_out 1
+ardour:Master/audio_out 1
+  |-> alsa_output.usb-PreSonus_Studio_1824c_SC1-00.pro-output-0:playback_AUX0
+alsa_output.pci-0000_00_1f.3.analog-stereo:playback_FL
+  |<- Firefox:output_FL
+";
+
+    #[test]
+    fn test_parse_pw_link() {
+        let labels = parse_links(PW_LINK, "Studio_1824c", LabelKind::Client, 18);
+        assert_eq!(labels[0].as_deref(), Some("ardour"));
+        assert_eq!(labels[1].as_deref(), Some("ardour"));
+        assert_eq!(labels[2], None);
+        assert_eq!(labels[4].as_deref(), Some("Firefox, ardour"));
+
+        let labels = parse_links(PW_LINK, "Studio_1824c", LabelKind::Port, 18);
+        assert_eq!(labels[0].as_deref(), Some("Master/audio_out 1"));
+    }
+
+    #[test]
+    fn test_parse_jack_lsp() {
+        let output = "\
+system:capture_1
+system:playback_1
+   ardour:Master/audio_out 1
+system:playback_2
+   ardour:Master/audio_out 2
+   mpv:out_1
+system:playback_19
+   mpv:out_2
+";
+        let labels = parse_links(output, "system", LabelKind::Client, 18);
+        assert_eq!(labels[0].as_deref(), Some("ardour"));
+        assert_eq!(labels[1].as_deref(), Some("ardour, mpv"));
+        assert!(labels[2..].iter().all(Option::is_none));
+    }
+}
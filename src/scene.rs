@@ -0,0 +1,95 @@
+use crate::config::{ConfigError, Paths};
+use crate::usb::PreSonusStudio1824c;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Scenes are named snapshots of the mixer config,
+/// stored in the `scenes` directory next to the config file
+pub fn dir(paths: &Paths) -> PathBuf {
+    paths.dir().join("scenes")
+}
+
+/// File of the scene called `name`
+pub fn file(paths: &Paths, name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid scene name '{}'", name),
+        ));
+    }
+    Ok(dir(paths).join(format!("{}.json", name)))
+}
+
+/// Save the current mixer state as a scene
+pub fn save(ps: &PreSonusStudio1824c, paths: &Paths, name: &str) -> Result<PathBuf, ConfigError> {
+    let path = file(paths, name)?;
+    fs::create_dir_all(dir(paths))?;
+    ps.config().save(&path)?;
+    Ok(path)
+}
+
+/// Load a scene into the mixer state.
+/// The caller is responsible for writing the new state to the device.
+pub fn load(ps: &mut PreSonusStudio1824c, paths: &Paths, name: &str) -> Result<(), ConfigError> {
+    let path = file(paths, name)?;
+    let contents = match fs::read_to_string(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(e.kind(), format!("no scene named '{}'", name)).into());
+        }
+        result => result?,
+    };
+    ps.load_config(&contents)
+}
+
+/// Names of all saved scenes, sorted
+pub fn list(paths: &Paths) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(dir(paths)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        result => result?,
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Some(name) = path.file_stem().and_then(|s| s.to_str())
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_rejects_paths() {
+        let paths = Paths::new(Some(PathBuf::from("/tmp/baton")));
+        assert_eq!(
+            file(&paths, "live").unwrap(),
+            PathBuf::from("/tmp/baton/scenes/live.json")
+        );
+        assert!(file(&paths, "").is_err());
+        assert!(file(&paths, "../config").is_err());
+        assert!(file(&paths, ".hidden").is_err());
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = std::env::temp_dir().join(format!("baton_scene_test_{}", std::process::id()));
+        let paths = Paths::new(Some(dir.clone()));
+        assert!(list(&paths).unwrap().is_empty());
+
+        fs::create_dir_all(super::dir(&paths)).unwrap();
+        for name in ["verse.json", "intro.json", "notes.txt"] {
+            fs::write(super::dir(&paths).join(name), "{}").unwrap();
+        }
+        assert_eq!(list(&paths).unwrap(), vec!["intro", "verse"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
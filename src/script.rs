@@ -0,0 +1,536 @@
+use crate::config_watch::FileWatcher;
+use crate::usb::{Meter, Mix, PreSonusStudio1824c, Strip, StripKind};
+use rhai::module_resolvers::FileModuleResolver;
+use rhai::{
+    AST, Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FLOAT, FnPtr, FuncArgs, INT, Map,
+    Scope,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const SCRIPT_FILE: &str = "script.rhai";
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Effect of a script call on the mixer
+#[derive(Default)]
+pub struct Outcome {
+    /// Strips changed by the script as (mix index, strip index)
+    pub changed: Vec<(usize, usize)>,
+    pub status: Option<String>,
+}
+
+/// Mixer state lent to the script functions for the duration of a call
+#[derive(Default)]
+struct Mixer {
+    mixes: Vec<Mix>,
+    channel_meters: Vec<Meter>,
+    bus_meters: Vec<Meter>,
+    channel_names: Vec<String>,
+    changed: Vec<(usize, usize)>,
+    status: Option<String>,
+}
+
+impl Mixer {
+    /// Swap the state with the device, once to lend it and once to give it back
+    fn swap(&mut self, ps: &mut PreSonusStudio1824c) {
+        std::mem::swap(&mut self.mixes, &mut ps.mixes);
+        std::mem::swap(&mut self.channel_meters, &mut ps.channel_meters);
+        std::mem::swap(&mut self.bus_meters, &mut ps.bus_meters);
+        std::mem::swap(&mut self.channel_names, &mut ps.channel_names);
+    }
+
+    /// Mix and strip index of a mix number and strip number.
+    /// Both are counted from 1, strip 0 is the bus strip of the mix.
+    fn index(&self, mix: INT, strip: INT) -> ScriptResult<(usize, usize)> {
+        let mix_index = mix
+            .checked_sub(1)
+            .and_then(|m| usize::try_from(m).ok())
+            .filter(|&m| m < self.mixes.len())
+            .ok_or_else(|| format!("no mix {}", mix))?;
+        let channels = self.mixes[mix_index].strips.channel_strips.len();
+        match usize::try_from(strip) {
+            Ok(0) => Ok((mix_index, channels)),
+            Ok(s) if s <= channels => Ok((mix_index, s - 1)),
+            _ => Err(format!("no strip {}", strip).into()),
+        }
+    }
+
+    fn strip(&self, mix: INT, strip: INT) -> ScriptResult<&Strip> {
+        let (m, s) = self.index(mix, strip)?;
+        Ok(self.mixes[m].strips.iter().nth(s).unwrap())
+    }
+
+    fn set(&mut self, mix: INT, strip: INT, f: impl FnOnce(&mut Strip)) -> ScriptResult<()> {
+        let (m, s) = self.index(mix, strip)?;
+        f(self.mixes[m].strips.iter_mut().nth(s).unwrap());
+        self.changed.push((m, s));
+        Ok(())
+    }
+
+    fn set_channel(
+        &mut self,
+        mix: INT,
+        strip: INT,
+        what: &str,
+        f: impl FnOnce(&mut Strip),
+    ) -> ScriptResult<()> {
+        if self.strip(mix, strip)?.kind != StripKind::Channel {
+            return Err(format!("bus strips have no {}", what).into());
+        }
+        self.set(mix, strip, f)
+    }
+
+    fn set_solo(&mut self, mix: INT, strip: INT, on: bool) -> ScriptResult<()> {
+        self.set_channel(mix, strip, "solo", |s| s.solo = on)?;
+        // Solo changes the muting of every strip in the mix
+        let (m, _) = self.index(mix, strip)?;
+        self.mixes[m].sync_solo();
+        let strips = self.mixes[m].strips.channel_strips.len();
+        self.changed.extend((0..=strips).map(|s| (m, s)));
+        Ok(())
+    }
+
+    fn take_changed(&mut self) -> Vec<(usize, usize)> {
+        let mut changed = std::mem::take(&mut self.changed);
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+}
+
+fn meter(meters: &[Meter], number: INT) -> ScriptResult<&Meter> {
+    number
+        .checked_sub(1)
+        .and_then(|i| usize::try_from(i).ok())
+        .and_then(|i| meters.get(i))
+        .ok_or_else(|| format!("no meter {}", number).into())
+}
+
+fn number_of(names: impl Iterator<Item = impl AsRef<str>>, name: &str) -> ScriptResult<INT> {
+    names
+        .into_iter()
+        .position(|n| n.as_ref() == name)
+        .map(|i| i as INT + 1)
+        .ok_or_else(|| format!("no strip named '{}'", name).into())
+}
+
+/// A compiled script with its bindings to the mixer
+struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// `this` of the hooks and commands, kept between calls
+    state: Dynamic,
+    mixer: Rc<RefCell<Mixer>>,
+    /// Command line commands registered by the script, to function names
+    commands: Rc<RefCell<BTreeMap<String, String>>>,
+    on_tick: bool,
+    on_midi: bool,
+}
+
+impl Script {
+    /// Compile a script. Modules are imported relative to `dir`.
+    fn compile(source: &str, dir: &Path) -> ScriptResult<Self> {
+        let mixer = Rc::new(RefCell::new(Mixer::default()));
+        let commands = Rc::new(RefCell::new(BTreeMap::new()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(100_000);
+        engine.set_module_resolver(FileModuleResolver::new_with_path(dir));
+        engine.on_print(|s| log::info!("Script: {}", s));
+        engine.on_debug(|s, _, pos| log::debug!("Script {}: {}", pos, s));
+        register_api(&mut engine, &mixer, &commands);
+
+        let ast = engine.compile(source)?;
+        let has_fn = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == params)
+        };
+        let on_tick = has_fn("on_tick", 0);
+        let on_midi = has_fn("on_midi", 3);
+
+        Ok(Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            state: Dynamic::from_map(Map::new()),
+            mixer,
+            commands,
+            on_tick,
+            on_midi,
+        })
+    }
+
+    /// Run the top level statements of the script
+    fn run(&mut self) -> ScriptResult<()> {
+        self.engine.run_ast_with_scope(&mut self.scope, &self.ast)
+    }
+
+    /// Call a script function with `this` bound to the script state
+    fn invoke(&mut self, name: &str, args: impl FuncArgs) -> ScriptResult<Dynamic> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        self.engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, name, args)
+    }
+
+    /// Run `f` with the mixer state of the device lent to the script
+    fn with_mixer<T>(
+        &mut self,
+        ps: &mut PreSonusStudio1824c,
+        f: impl FnOnce(&mut Self) -> ScriptResult<T>,
+    ) -> (Option<T>, Outcome) {
+        self.mixer.borrow_mut().swap(ps);
+        let result = f(self);
+        let mut mixer = self.mixer.borrow_mut();
+        mixer.swap(ps);
+
+        let mut outcome = Outcome {
+            changed: mixer.take_changed(),
+            status: mixer.status.take(),
+        };
+        match result {
+            Ok(value) => (Some(value), outcome),
+            Err(e) => {
+                log::error!("Script error: {}", e);
+                outcome.status = Some(format!("Script error: {}", e));
+                (None, outcome)
+            }
+        }
+    }
+}
+
+fn register_api(
+    engine: &mut Engine,
+    mixer: &Rc<RefCell<Mixer>>,
+    commands: &Rc<RefCell<BTreeMap<String, String>>>,
+) {
+    // Meters, numbered from 1
+    let m = mixer.clone();
+    engine.register_fn("channel_meter", move |n: INT| -> ScriptResult<FLOAT> {
+        Ok(meter(&m.borrow().channel_meters, n)?.value)
+    });
+    let m = mixer.clone();
+    engine.register_fn("channel_peak", move |n: INT| -> ScriptResult<FLOAT> {
+        Ok(meter(&m.borrow().channel_meters, n)?.max)
+    });
+    let m = mixer.clone();
+    engine.register_fn("channel_clip", move |n: INT| -> ScriptResult<bool> {
+        Ok(meter(&m.borrow().channel_meters, n)?.clip)
+    });
+    let m = mixer.clone();
+    engine.register_fn("bus_meter", move |n: INT| -> ScriptResult<FLOAT> {
+        Ok(meter(&m.borrow().bus_meters, n)?.value)
+    });
+    let m = mixer.clone();
+    engine.register_fn("bus_peak", move |n: INT| -> ScriptResult<FLOAT> {
+        Ok(meter(&m.borrow().bus_meters, n)?.max)
+    });
+    let m = mixer.clone();
+    engine.register_fn("bus_clip", move |n: INT| -> ScriptResult<bool> {
+        Ok(meter(&m.borrow().bus_meters, n)?.clip)
+    });
+
+    // Numbers of mixes and strips by name
+    let m = mixer.clone();
+    engine.register_fn("mix_number", move |name: &str| -> ScriptResult<INT> {
+        number_of(m.borrow().mixes.iter().map(|mix| mix.name.as_str()), name)
+    });
+    let m = mixer.clone();
+    engine.register_fn("strip_number", move |name: &str| -> ScriptResult<INT> {
+        number_of(m.borrow().channel_names.iter(), name)
+    });
+
+    // Strip values, by mix number and strip number
+    let m = mixer.clone();
+    engine.register_fn(
+        "fader",
+        move |mix: INT, strip: INT| -> ScriptResult<FLOAT> {
+            Ok(m.borrow().strip(mix, strip)?.fader)
+        },
+    );
+    let m = mixer.clone();
+    engine.register_fn("pan", move |mix: INT, strip: INT| -> ScriptResult<FLOAT> {
+        Ok(m.borrow().strip(mix, strip)?.balance)
+    });
+    let m = mixer.clone();
+    engine.register_fn("mute", move |mix: INT, strip: INT| -> ScriptResult<bool> {
+        Ok(m.borrow().strip(mix, strip)?.mute)
+    });
+    let m = mixer.clone();
+    engine.register_fn("solo", move |mix: INT, strip: INT| -> ScriptResult<bool> {
+        Ok(m.borrow().strip(mix, strip)?.solo)
+    });
+
+    let m = mixer.clone();
+    engine.register_fn("set_fader", move |mix: INT, strip: INT, db: FLOAT| {
+        m.borrow_mut().set(mix, strip, |s| s.set_fader(db))
+    });
+    let m = mixer.clone();
+    engine.register_fn("set_fader", move |mix: INT, strip: INT, db: INT| {
+        m.borrow_mut().set(mix, strip, |s| s.set_fader(db as FLOAT))
+    });
+    let m = mixer.clone();
+    engine.register_fn("set_pan", move |mix: INT, strip: INT, pan: FLOAT| {
+        m.borrow_mut()
+            .set_channel(mix, strip, "pan", |s| s.balance = pan.clamp(-100.0, 100.0))
+    });
+    let m = mixer.clone();
+    engine.register_fn("set_pan", move |mix: INT, strip: INT, pan: INT| {
+        m.borrow_mut().set_channel(mix, strip, "pan", |s| {
+            s.balance = (pan as FLOAT).clamp(-100.0, 100.0)
+        })
+    });
+    let m = mixer.clone();
+    engine.register_fn("set_mute", move |mix: INT, strip: INT, on: bool| {
+        m.borrow_mut().set(mix, strip, |s| s.mute = on)
+    });
+    let m = mixer.clone();
+    engine.register_fn("set_solo", move |mix: INT, strip: INT, on: bool| {
+        m.borrow_mut().set_solo(mix, strip, on)
+    });
+
+    let m = mixer.clone();
+    engine.register_fn("status", move |message: &str| {
+        m.borrow_mut().status = Some(message.to_string());
+    });
+
+    let c = commands.clone();
+    engine.register_fn("register_command", move |name: &str, f: FnPtr| {
+        c.borrow_mut()
+            .insert(name.to_string(), f.fn_name().to_string());
+    });
+}
+
+/// Runs the user script `script.rhai` from the config directory.
+///
+/// The script can define `fn on_tick()`, called on every tick,
+/// and `fn on_midi(channel, cc, value)`, called for every MIDI CC message.
+/// `on_midi` returns true if it handled the message,
+/// which then skips the MIDI mapping.
+pub struct ScriptHost {
+    watcher: FileWatcher,
+    script: Option<Script>,
+}
+
+impl ScriptHost {
+    pub fn new(dir: &Path) -> Self {
+        ScriptHost {
+            watcher: FileWatcher::new(dir.join(SCRIPT_FILE)),
+            script: None,
+        }
+    }
+
+    /// Load the script, if there is one
+    pub fn load(&mut self, ps: &mut PreSonusStudio1824c) -> Outcome {
+        self.script = None;
+        let path: PathBuf = self.watcher.path().to_path_buf();
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Outcome::default(),
+            Err(e) => {
+                log::error!("Failed to read script {}: {}", path.display(), e);
+                return Outcome {
+                    status: Some(format!("Script not loaded: {}", e)),
+                    ..Default::default()
+                };
+            }
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut script = match Script::compile(&source, dir) {
+            Ok(script) => script,
+            Err(e) => {
+                log::error!("Failed to compile script {}: {}", path.display(), e);
+                return Outcome {
+                    status: Some(format!("Script not loaded: {}", e)),
+                    ..Default::default()
+                };
+            }
+        };
+
+        let (result, outcome) = script.with_mixer(ps, |script| script.run());
+        if result.is_some() {
+            log::info!("Loaded script {}", path.display());
+            self.script = Some(script);
+        }
+        outcome
+    }
+
+    /// Reload the script if the file changed
+    pub fn poll(&mut self, ps: &mut PreSonusStudio1824c) -> Option<Outcome> {
+        if !self.watcher.changed() {
+            return None;
+        }
+        let mut outcome = self.load(ps);
+        if self.script.is_some() && outcome.status.is_none() {
+            outcome.status = Some("Script reloaded".to_string());
+        }
+        Some(outcome)
+    }
+
+    pub fn tick(&mut self, ps: &mut PreSonusStudio1824c) -> Outcome {
+        let Some(script) = self.script.as_mut().filter(|s| s.on_tick) else {
+            return Outcome::default();
+        };
+        let (result, mut outcome) = script.with_mixer(ps, |script| script.invoke("on_tick", ()));
+        if result.is_none() {
+            // Don't repeat the error on every tick
+            script.on_tick = false;
+            if let Some(status) = outcome.status.as_mut() {
+                status.push_str(", on_tick disabled");
+            }
+        }
+        outcome
+    }
+
+    /// Returns true if the script handled the message
+    pub fn midi(
+        &mut self,
+        ps: &mut PreSonusStudio1824c,
+        channel: u8,
+        cc: u8,
+        value: u8,
+    ) -> (bool, Outcome) {
+        let Some(script) = self.script.as_mut().filter(|s| s.on_midi) else {
+            return (false, Outcome::default());
+        };
+        let args = (channel as INT, cc as INT, value as INT);
+        let (result, outcome) = script.with_mixer(ps, |script| script.invoke("on_midi", args));
+        let handled = result.and_then(|r| r.as_bool().ok()).unwrap_or(false);
+        (handled, outcome)
+    }
+
+    /// Names of the commands registered by the script
+    pub fn commands(&self) -> Vec<String> {
+        self.script
+            .as_ref()
+            .map(|s| s.commands.borrow().keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Run a command registered by the script.
+    /// Returns None if there is no such command.
+    pub fn command(
+        &mut self,
+        ps: &mut PreSonusStudio1824c,
+        name: &str,
+        args: &[&str],
+    ) -> Option<Outcome> {
+        let script = self.script.as_mut()?;
+        let function = script.commands.borrow().get(name).cloned()?;
+        let args: Array = args.iter().map(|a| Dynamic::from(a.to_string())).collect();
+        let (_, outcome) = script.with_mixer(ps, |script| script.invoke(&function, (args,)));
+        Some(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> Script {
+        let script = Script::compile(source, Path::new(".")).unwrap();
+        {
+            let mut mixer = script.mixer.borrow_mut();
+            mixer.mixes = vec![
+                Mix::new(String::from("MAIN 1-2"), StripKind::Main, 0, 2),
+                Mix::new(String::from("MIX 3-4"), StripKind::Bus, 1, 2),
+            ];
+            mixer.channel_names = vec!["Mic 1".to_string(), "DAW 1".to_string()];
+            let mut meter = Meter::new();
+            meter.value = -20.0;
+            meter.max = -3.0;
+            mixer.channel_meters = vec![meter.clone(); 2];
+            mixer.bus_meters = vec![meter; 4];
+        }
+        script
+    }
+
+    #[test]
+    fn test_hooks_change_strips() {
+        let mut script = compile(
+            r#"
+            fn on_tick() {
+                if channel_meter(strip_number("DAW 1")) > -30.0 {
+                    set_fader(mix_number("MIX 3-4"), 0, -20);
+                    set_pan(1, 2, -30.5);
+                }
+            }
+            "#,
+        );
+        assert!(script.on_tick);
+        assert!(!script.on_midi);
+
+        let _ = script.invoke("on_tick", ()).unwrap();
+        let mut mixer = script.mixer.borrow_mut();
+        assert_eq!(mixer.mixes[1].strips.bus_strip.fader, -20.0);
+        assert_eq!(mixer.mixes[0].strips.channel_strips[1].balance, -30.5);
+        assert_eq!(mixer.take_changed(), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_state_and_commands() {
+        let mut script = compile(
+            r#"
+            register_command("dim", Fn("dim"));
+
+            fn dim(args) {
+                this.dimmed = !(this.dimmed ?? false);
+                set_fader(1, 0, if this.dimmed { parse_float(args[0]) } else { 0.0 });
+                status(`dimmed: ${this.dimmed}`);
+            }
+            "#,
+        );
+        script.run().unwrap();
+        let function = script.commands.borrow().get("dim").cloned().unwrap();
+
+        let args: Array = vec![Dynamic::from("-12".to_string())];
+        let _ = script.invoke(&function, (args.clone(),)).unwrap();
+        assert_eq!(script.mixer.borrow().mixes[0].strips.bus_strip.fader, -12.0);
+        let _ = script.invoke(&function, (args,)).unwrap();
+        assert_eq!(script.mixer.borrow().mixes[0].strips.bus_strip.fader, 0.0);
+        assert_eq!(
+            script.mixer.borrow().status.as_deref(),
+            Some("dimmed: false")
+        );
+    }
+
+    #[test]
+    fn test_solo_changes_whole_mix() {
+        let mut script =
+            compile("fn on_midi(channel, cc, value) { set_solo(2, 1, value > 63); true }");
+        let handled = script
+            .invoke("on_midi", (0 as INT, 7 as INT, 127 as INT))
+            .unwrap();
+        assert!(handled.as_bool().unwrap());
+
+        let mut mixer = script.mixer.borrow_mut();
+        assert!(mixer.mixes[1].strips.channel_strips[1].mute_by_solo);
+        assert_eq!(mixer.take_changed(), vec![(1, 0), (1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut script = compile("fn on_tick() { set_pan(1, 0, 10) }");
+        assert!(script.invoke("on_tick", ()).is_err());
+
+        let mut script = compile("fn on_tick() { fader(3, 1) }");
+        assert!(script.invoke("on_tick", ()).is_err());
+
+        // The smallest integer must not overflow when counted from 1
+        let mut script = compile("fn on_tick() { fader(-9223372036854775807 - 1, 1) }");
+        assert!(script.invoke("on_tick", ()).is_err());
+        let mut script = compile("fn on_tick() { channel_meter(-9223372036854775807 - 1) }");
+        assert!(script.invoke("on_tick", ()).is_err());
+
+        let mut script = compile("fn on_tick() { loop {} }");
+        assert!(script.invoke("on_tick", ()).is_err());
+    }
+}
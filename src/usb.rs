@@ -1,111 +1,269 @@
+use crate::access::{Control, Permissions, RemoteChange, StripChange};
+use crate::config::{self, Config, ConfigError, Paths};
+use crate::connection::Connection;
+use crate::dbus::{DbusCall, DbusServer, MixerState, StripState};
+use crate::meter_log::MeterLogger;
+use crate::metering::{Ballistic, Ballistics, FLOOR_DB, PeakHold};
+use crate::metrics::{MetricsServer, Snapshot};
+use crate::ports::PortWatcher;
+use crate::scene;
+use crate::web::WebServer;
 use baton_studio::*;
 use core::time::Duration;
 use nusb::{Device, MaybeFuture};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::error::Error;
+use std::fmt;
 use std::num::NonZero;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Sample peaks above this are counted as overs
+pub const CLIP_THRESHOLD: f64 = -0.001;
+
+/// Playback channels of the computer, the last input channels
+pub const DAW_CHANNELS: usize = 18;
 
 #[derive(Clone)]
 pub struct Meter {
+    /// Reading with the ballistics of the meter group, in dBFS
     pub value: f64,
+    /// Sample peak of the last poll in dBFS
+    pub peak: f64,
+    /// Highest sample peak since the meter was cleared
     pub max: f64,
+    /// Sample peak held for a while, then decaying, in dBFS
+    pub hold: f64,
     pub clip: bool,
+    /// Number of overs since the meter was cleared
+    pub clips: u32,
+    /// Time of the last over
+    pub last_clip: Option<Instant>,
+    ballistic: Ballistic,
+    peak_hold: PeakHold,
 }
 
-#[derive(Deserialize, Serialize)]
-#[serde(default)]
-pub struct PreSonusStudio1824c {
-    #[serde(skip)]
+impl Meter {
+    pub fn new() -> Self {
+        Meter {
+            value: FLOOR_DB,
+            peak: FLOOR_DB,
+            max: FLOOR_DB,
+            hold: FLOOR_DB,
+            clip: false,
+            clips: 0,
+            last_clip: None,
+            ballistic: Ballistic::default(),
+            peak_hold: PeakHold::default(),
+        }
+    }
+
+    fn update(&mut self, db: f64, ballistics: Ballistics, now: Instant) {
+        let over = db > CLIP_THRESHOLD;
+        // Count each over once, however many polls it lasts
+        if over && self.peak <= CLIP_THRESHOLD {
+            self.clips += 1;
+        }
+        if over {
+            self.clip = true;
+            self.last_clip = Some(now);
+        }
+        self.peak = db;
+        self.value = self.ballistic.process(ballistics, db, now);
+        self.hold = self.peak_hold.update(db, now);
+        if db > self.max {
+            self.max = db;
+        }
+    }
+
+    /// Copy of the meter with the levels moved by `gain` dB, or silent for None.
+    /// The clip indicators stay those of the meter.
+    pub fn with_gain(&self, gain: Option<f64>) -> Meter {
+        let shift = |db: f64| gain.map_or(FLOOR_DB, |gain| (db + gain).max(FLOOR_DB));
+        Meter {
+            value: shift(self.value),
+            peak: shift(self.peak),
+            max: shift(self.max),
+            hold: shift(self.hold),
+            ..self.clone()
+        }
+    }
+
+    /// Reset the clip latch, clip counter and peaks
+    pub fn clear(&mut self) {
+        self.max = FLOOR_DB;
+        self.hold = FLOOR_DB;
+        self.clip = false;
+        self.clips = 0;
+        self.last_clip = None;
+        self.peak_hold = PeakHold::default();
+    }
+}
+
+/// The interface found by `open`
+struct Opened {
     device: Device,
-    #[serde(skip)]
+    serial: String,
+    /// Names of the hardware input channels
+    channel_names: Vec<String>,
+}
+
+/// Open the interface picked by `selector`, a serial number or a number from 1,
+/// or the first one found. See `device::select`.
+fn open(selector: Option<&str>) -> Result<Opened, Box<dyn Error>> {
+    let mut devices = crate::device::list()?;
+    let serials: Vec<&str> = devices.iter().map(|d| d.serial.as_str()).collect();
+    let index = crate::device::select(&serials, selector)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))?;
+    let entry = devices.swap_remove(index);
+    let device_info = entry.info;
+
+    log::info!(
+        "Found Manufacturer: {}, Product: {}, Serial: {}",
+        device_info.manufacturer_string().unwrap_or("unknown"),
+        device_info.product_string().unwrap_or("unknown"),
+        device_info.serial_number().unwrap_or("unknown"),
+    );
+
+    let interfaces = device_info.interfaces();
+    for i in interfaces {
+        log::debug!(
+            "Interface: {} {}",
+            i.interface_number(),
+            i.interface_string().unwrap_or_default()
+        );
+    }
+
+    let device = device_info.open().wait()?;
+    log::info!("Opened device");
+
+    let number_of_channels = 18;
+
+    // # Read all string descriptors from device
+    // Channel name descriptors start at this index
+    let input_channel_name_index = 33;
+    let mut channel_names: Vec<String> = vec![];
+    let mut desc: Vec<String> = vec![];
+    // Descriptor at index 0 is reserved for Language Table, we skip it.
+    desc.push(String::from("LT"));
+
+    let timeout = Duration::from_millis(100);
+    let mut i = 1;
+    while let Ok(d) = device
+        .get_string_descriptor(NonZero::new(i).unwrap(), 0, timeout)
+        .wait()
+    {
+        log::debug!("Descriptor {}: {}", i, d);
+        desc.push(d);
+        i += 1;
+    }
+
+    for i in 0..number_of_channels {
+        let name = desc[input_channel_name_index + i].clone();
+        channel_names.push(name);
+    }
+
+    Ok(Opened {
+        device,
+        serial: entry.serial,
+        channel_names,
+    })
+}
+
+pub struct PreSonusStudio1824c {
+    connection: Connection,
+    /// How the interface was picked, to find it again after a disconnect
+    selector: Option<String>,
+    /// Serial number of the interface, names its config directory.
+    /// Empty if the interface was offline at start and picked without it.
+    pub serial: String,
     pub command: Command,
-    #[serde(skip)]
     pub state: State,
-    #[serde(skip)]
     pub channel_meters: Vec<Meter>,
-    #[serde(skip)]
     pub bus_meters: Vec<Meter>,
     pub channel_names: Vec<String>,
+    /// Names of the hardware inputs read from the interface, empty until it's found
+    interface_names: Vec<String>,
+    pub channel_colors: Vec<Option<StripColor>>,
+    /// Level of a centred channel in dB, see `Strip::pan_levels`
+    pub pan_law: f64,
+    pub metering: crate::metering::Settings,
+    /// Logger of the meter levels, see `start_meter_log`
+    pub meter_log: Option<MeterLogger>,
+    /// HTTP endpoint updated after every poll
+    pub metrics: Option<MetricsServer>,
+    /// Web mixer published to and taking changes after every poll
+    pub web: Option<WebServer>,
+    /// D-Bus service published to and taking calls after every poll
+    pub dbus: Option<DbusServer>,
+    /// Audio ports connected to the DAW channels, see `channel_label`
+    pub ports: Option<PortWatcher>,
+    /// Labels of the DAW channels from the last poll
+    port_labels: Vec<Option<String>>,
+    /// Failed state polls since start
+    pub poll_errors: u64,
+    /// Failed writes of faders and buttons since start
+    pub write_errors: u64,
+    /// Duration of the last state poll
+    pub poll_latency: Duration,
     pub mixes: Vec<Mix>,
-    #[serde(skip)]
     pub in_1_2_line: bool,
-    #[serde(skip)]
     pub main_mute: bool,
-    #[serde(skip)]
     pub main_mono: bool,
-    #[serde(skip)]
     pub phantom_power: bool,
-    #[serde(skip)]
-    descriptor: Vec<String>,
+    /// Whether the mixer is bypassed, written on reconnect instead of the state
+    pub bypass: bool,
 }
 
-impl Default for PreSonusStudio1824c {
-    fn default() -> Self {
-        PreSonusStudio1824c::new().unwrap()
-    }
+/// Name of hardware input `index` until the interface is found
+fn placeholder_name(index: usize) -> String {
+    format!("Input {}", index + 1)
 }
 
 impl PreSonusStudio1824c {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        let device_info = nusb::list_devices()
-            .wait()?
-            .find(|dev| dev.vendor_id() == 0x194f && dev.product_id() == 0x010d)
-            .ok_or(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "device not found",
-            ))?;
-
-        log::info!(
-            "Found Manufacturer: {}, Product: {}, Serial: {}",
-            device_info.manufacturer_string().unwrap_or("unknown"),
-            device_info.product_string().unwrap_or("unknown"),
-            device_info.serial_number().unwrap_or("unknown"),
+    /// Open the interface picked by `device`, a serial number or a number from 1,
+    /// or the first one found. See `device::select`.
+    pub fn new(device: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let opened = open(device)?;
+        let mut ps = Self::with_connection(
+            Connection::online(opened.device),
+            device,
+            opened.serial,
+            opened.channel_names.clone(),
         );
+        ps.interface_names = opened.channel_names;
+        Ok(ps)
+    }
 
-        let interfaces = device_info.interfaces();
-        for i in interfaces {
-            log::debug!(
-                "Interface: {} {}",
-                i.interface_number(),
-                i.interface_string().unwrap_or_default()
-            );
-        }
-
-        let device = device_info.open().wait()?;
-        log::info!("Opened device");
-
-        let number_of_channels = 18;
-
-        // # Read all string descriptors from device
-        // Channel name descriptors start at this index
-        let input_channel_name_index = 33;
-        let mut channel_name: Vec<String> = vec![];
-        let mut desc: Vec<String> = vec![];
-        // Descriptor at index 0 is reserved for Language Table, we skip it.
-        desc.push(String::from("LT"));
-
-        let timeout = Duration::from_millis(100);
-        let mut i = 1;
-        while let Ok(d) = device
-            .get_string_descriptor(NonZero::new(i).unwrap(), 0, timeout)
-            .wait()
-        {
-            log::debug!("Descriptor {}: {}", i, d);
-            desc.push(d);
-            i += 1;
-        }
-
-        for i in 0..number_of_channels {
-            let name = desc[input_channel_name_index + i].clone();
-            channel_name.push(name);
-        }
+    /// The interface picked by `device` while it's not connected.
+    /// It's looked for on every poll and set up with the mixer state when found.
+    pub fn offline(device: Option<&str>) -> Self {
+        // Only a serial number names the config directory, not a number from 1
+        let serial = device.filter(|device| device.parse::<usize>().is_err());
+        let channel_names = (0..18).map(placeholder_name).collect();
+        Self::with_connection(
+            Connection::offline(),
+            device,
+            serial.unwrap_or_default().to_string(),
+            channel_names,
+        )
+    }
 
-        for i in 1..=18 {
+    fn with_connection(
+        connection: Connection,
+        selector: Option<&str>,
+        serial: String,
+        mut channel_name: Vec<String>,
+    ) -> Self {
+        for i in 1..=DAW_CHANNELS {
             channel_name.push(format!("DAW {}", i));
         }
 
-        Ok(PreSonusStudio1824c {
-            device,
+        PreSonusStudio1824c {
+            connection,
+            selector: selector.map(str::to_string),
+            serial,
             command: Command::new(),
             state: State::new(),
             mixes: vec![
@@ -164,155 +322,644 @@ impl PreSonusStudio1824c {
                     channel_name.len(),
                 ),
             ],
+            channel_colors: vec![None; channel_name.len()],
+            pan_law: 0.0,
             channel_names: channel_name,
-            channel_meters: vec![
-                Meter {
-                    value: -96.0,
-                    max: -96.0,
-                    clip: false
-                };
-                36
-            ],
-            bus_meters: vec![
-                Meter {
-                    value: -96.0,
-                    max: -96.0,
-                    clip: false
-                };
-                18
-            ],
+            interface_names: Vec::new(),
+            metering: Default::default(),
+            meter_log: None,
+            metrics: None,
+            web: None,
+            dbus: None,
+            ports: None,
+            port_labels: Vec::new(),
+            poll_errors: 0,
+            write_errors: 0,
+            poll_latency: Duration::ZERO,
+            channel_meters: vec![Meter::new(); 36],
+            bus_meters: vec![Meter::new(); 18],
             in_1_2_line: false,
             main_mute: false,
             main_mono: false,
             phantom_power: false,
-            descriptor: desc,
-        })
+            bypass: false,
+        }
+    }
+
+    /// Whether the interface is connected
+    pub fn is_online(&self) -> bool {
+        self.connection.is_online()
+    }
+
+    /// Look for the offline interface and write the mixer state to it when found
+    fn reconnect(&mut self) {
+        let selector = match self.serial.as_str() {
+            "" => self.selector.clone(),
+            serial => Some(serial.to_string()),
+        };
+        match open(selector.as_deref()) {
+            Ok(opened) => {
+                log::info!("Interface {} reconnected", opened.serial);
+                if self.serial.is_empty() {
+                    self.serial = opened.serial;
+                }
+                self.interface_names = opened.channel_names;
+                self.replace_placeholder_names();
+                self.connection.reconnected(opened.device);
+                if self.bypass {
+                    self.bypass_mixer();
+                } else {
+                    self.write_state();
+                }
+            }
+            Err(e) => log::debug!("Interface not found: {}", e),
+        }
+    }
+
+    /// Name the inputs still called by their placeholder after the interface,
+    /// keeping the names given by the config
+    pub fn replace_placeholder_names(&mut self) {
+        for (i, (name, interface_name)) in self
+            .channel_names
+            .iter_mut()
+            .zip(&self.interface_names)
+            .enumerate()
+        {
+            if *name == placeholder_name(i) {
+                *name = interface_name.clone();
+            }
+        }
     }
 
     pub fn set_1_2_line(&mut self, on: bool) {
-        match self.command.set_button(Button::Line, on).send(&self.device) {
+        match self
+            .connection
+            .send(self.command.set_button(Button::Line, on))
+        {
             Ok(_) => log::debug!("Set 1/2 line to {}", on),
-            Err(e) => log::error!("Error setting 1/2 line: {}", e),
+            Err(e) => {
+                self.write_errors += 1;
+                log::error!("Error setting 1/2 line: {}", e)
+            }
         }
     }
 
     pub fn set_main_mute(&mut self, on: bool) {
-        match self.command.set_button(Button::Mute, on).send(&self.device) {
+        match self
+            .connection
+            .send(self.command.set_button(Button::Mute, on))
+        {
             Ok(_) => log::debug!("Set main mute to {}", on),
-            Err(e) => log::error!("Error setting main mute: {}", e),
+            Err(e) => {
+                self.write_errors += 1;
+                log::error!("Error setting main mute: {}", e)
+            }
         }
     }
 
     pub fn set_main_mono(&mut self, on: bool) {
-        match self.command.set_button(Button::Mono, on).send(&self.device) {
+        match self
+            .connection
+            .send(self.command.set_button(Button::Mono, on))
+        {
             Ok(_) => log::debug!("Set main mono to {}", on),
-            Err(e) => log::error!("Error setting main mono: {}", e),
+            Err(e) => {
+                self.write_errors += 1;
+                log::error!("Error setting main mono: {}", e)
+            }
         }
     }
 
     pub fn set_phantom_power(&mut self, on: bool) {
         match self
-            .command
-            .set_button(Button::Phantom, on)
-            .send(&self.device)
+            .connection
+            .send(self.command.set_button(Button::Phantom, on))
         {
             Ok(_) => log::debug!("Set phantom power to {}", on),
-            Err(e) => log::error!("Error setting phantom power: {}", e),
+            Err(e) => {
+                self.write_errors += 1;
+                log::error!("Error setting phantom power: {}", e)
+            }
         }
     }
 
     pub fn poll_state(&mut self) {
-        match self.state.poll(&self.device) {
-            Ok(_) => {
-                // synch meters
-                let mut channel_index = 0;
-                for v in self.state.mic.iter().map(|g| gain_to_db(*g)) {
-                    self.channel_meters[channel_index].value = v;
-                    if v > self.channel_meters[channel_index].max {
-                        self.channel_meters[channel_index].max = v;
-                    }
-                    if self.channel_meters[channel_index].value > -0.001 {
-                        self.channel_meters[channel_index].clip = true;
-                    }
-                    channel_index += 1;
-                }
-                for v in self.state.spdif.iter().map(|g| gain_to_db(*g)) {
-                    self.channel_meters[channel_index].value = v;
-                    if v > self.channel_meters[channel_index].max {
-                        self.channel_meters[channel_index].max = v;
+        if self.connection.retry_due(Instant::now()) {
+            self.reconnect();
+        }
+        if self.connection.is_online() {
+            let started = Instant::now();
+            let result = self.connection.poll(&mut self.state);
+            self.poll_latency = started.elapsed();
+            match result {
+                Ok(_) => {
+                    // synch meters
+                    let now = Instant::now();
+                    let inputs = self
+                        .state
+                        .mic
+                        .iter()
+                        .chain(&self.state.spdif)
+                        .chain(&self.state.adat)
+                        .chain(&self.state.daw);
+                    let ballistics = self.metering.inputs.ballistics;
+                    for (meter, gain) in self.channel_meters.iter_mut().zip(inputs) {
+                        meter.update(gain_to_db(*gain), ballistics, now);
                     }
-                    if self.channel_meters[channel_index].value > -0.001 {
-                        self.channel_meters[channel_index].clip = true;
+                    let ballistics = self.metering.outputs.ballistics;
+                    for (meter, gain) in self.bus_meters.iter_mut().zip(&self.state.bus) {
+                        meter.update(gain_to_db(*gain), ballistics, now);
                     }
-                    channel_index += 1;
-                }
-                for v in self.state.adat.iter().map(|g| gain_to_db(*g)) {
-                    self.channel_meters[channel_index].value = v;
-                    if v > self.channel_meters[channel_index].max {
-                        self.channel_meters[channel_index].max = v;
-                    }
-                    if self.channel_meters[channel_index].value > -0.001 {
-                        self.channel_meters[channel_index].clip = true;
+                    if let Some(logger) = &mut self.meter_log {
+                        let peaks: Vec<f64> = self
+                            .channel_meters
+                            .iter()
+                            .chain(&self.bus_meters)
+                            .map(|m| m.peak)
+                            .collect();
+                        if let Err(e) = logger.sample(&peaks, now) {
+                            log::error!("Stopped logging meters: {}", e);
+                            self.meter_log = None;
+                        }
                     }
-                    channel_index += 1;
+
+                    // synch button states
+                    self.phantom_power = self.state.phantom == 0x01;
+                    self.in_1_2_line = self.state.line == 0x01;
+                    self.main_mute = self.state.mute == 0x01;
+                    self.main_mono = self.state.mono == 0x01;
                 }
-                for v in self.state.daw.iter().map(|g| gain_to_db(*g)) {
-                    self.channel_meters[channel_index].value = v;
-                    if v > self.channel_meters[channel_index].max {
-                        self.channel_meters[channel_index].max = v;
-                    }
-                    if self.channel_meters[channel_index].value > -0.001 {
-                        self.channel_meters[channel_index].clip = true;
-                    }
-                    channel_index += 1;
+                Err(e) => {
+                    self.poll_errors += 1;
+                    log::error!("Error polling state: {}", e)
                 }
+            }
+        } else {
+            // Let the meters fall while the interface is gone
+            let now = Instant::now();
+            let ballistics = self.metering.inputs.ballistics;
+            for meter in &mut self.channel_meters {
+                meter.update(FLOOR_DB, ballistics, now);
+            }
+            let ballistics = self.metering.outputs.ballistics;
+            for meter in &mut self.bus_meters {
+                meter.update(FLOOR_DB, ballistics, now);
+            }
+        }
 
-                let mut bus_index = 0;
-                for v in self.state.bus.iter().map(|g| gain_to_db(*g)) {
-                    self.bus_meters[bus_index].value = v;
-                    if v > self.bus_meters[bus_index].max {
-                        self.bus_meters[bus_index].max = v;
-                    }
-                    if self.bus_meters[bus_index].value > -0.001 {
-                        self.bus_meters[bus_index].clip = true;
-                    }
-                    bus_index += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.update(&self.metrics_snapshot());
+        }
+        self.process_web_commands();
+        self.process_dbus_requests();
+        if let Some(ports) = &self.ports {
+            self.port_labels = ports.labels();
+        }
+    }
+
+    /// Name shown for the input channel at `index`: the audio ports connected to
+    /// a DAW channel if they are watched, otherwise the stored channel name
+    pub fn channel_label(&self, index: usize) -> &str {
+        let daw_index = index.checked_sub(self.channel_names.len() - DAW_CHANNELS);
+        daw_index
+            .and_then(|i| self.port_labels.get(i)?.as_deref())
+            .unwrap_or(&self.channel_names[index])
+    }
+
+    /// Names shown for all input channels, see `channel_label`
+    pub fn channel_labels(&self) -> Vec<String> {
+        (0..self.channel_names.len())
+            .map(|i| self.channel_label(i).to_string())
+            .collect()
+    }
+
+    /// Apply the calls on the D-Bus service and publish the new state
+    fn process_dbus_requests(&mut self) {
+        let Some(dbus) = &self.dbus else {
+            return;
+        };
+        let paths = dbus.paths().clone();
+        let permissions = dbus.permissions().clone();
+        let mut replies = Vec::new();
+        for request in dbus.requests() {
+            let result = match &request.call {
+                DbusCall::Change(change) => self.apply_remote_change(&permissions, *change),
+                DbusCall::SaveScene(name) => scene::save(self, &paths, name)
+                    .map(|_| log::info!("Saved scene {} over D-Bus", name))
+                    .map_err(|e| e.to_string()),
+                DbusCall::RecallScene(name) => self
+                    .recall_remote_scene(&permissions, &paths, name)
+                    .map(|()| log::info!("Loaded scene {} over D-Bus", name)),
+            };
+            replies.push((request, result));
+        }
+
+        let state = self.dbus_state();
+        let input_names = self.channel_labels();
+        let inputs: Vec<(&str, u32)> = input_names
+            .iter()
+            .map(String::as_str)
+            .zip(self.channel_meters.iter().map(|m| m.clips))
+            .collect();
+        let output_names: Vec<String> = self
+            .mixes
+            .iter()
+            .flat_map(|mix| [format!("{} L", mix.name), format!("{} R", mix.name)])
+            .collect();
+        let outputs: Vec<(&str, u32)> = output_names
+            .iter()
+            .map(String::as_str)
+            .zip(self.bus_meters.iter().map(|m| m.clips))
+            .collect();
+        if let Some(dbus) = &mut self.dbus {
+            dbus.publish(state);
+            dbus.signal_clips("input", &inputs);
+            dbus.signal_clips("output", &outputs);
+        }
+        // Reply once the state is published, so callers read back their change
+        for (request, result) in replies {
+            request.reply(result);
+        }
+    }
+
+    /// Mixer state published on the D-Bus service
+    fn dbus_state(&self) -> MixerState {
+        let mixes = self
+            .mixes
+            .iter()
+            .map(|mix| {
+                let names = (0..self.channel_names.len())
+                    .map(|i| self.channel_label(i))
+                    .chain([mix.name.as_str()]);
+                let strips = mix
+                    .strips
+                    .iter()
+                    .zip(names)
+                    .map(|(strip, name)| StripState {
+                        name: name.to_string(),
+                        fader: strip.fader,
+                        pan: strip.balance,
+                        mute: if strip.kind == StripKind::Main {
+                            self.main_mute
+                        } else {
+                            strip.mute
+                        },
+                        solo: strip.solo,
+                    })
+                    .collect();
+                (mix.name.clone(), strips)
+            })
+            .collect();
+        MixerState {
+            mixes,
+            phantom_power: self.phantom_power,
+            line: self.in_1_2_line,
+            main_mute: self.main_mute,
+            main_mono: self.main_mono,
+        }
+    }
+
+    /// Apply the changes of the web clients and publish the new state
+    fn process_web_commands(&mut self) {
+        let Some(web) = &self.web else {
+            return;
+        };
+        for command in web.commands() {
+            if let Err(e) = self.apply_remote_change(&command.permissions, command.change) {
+                log::warn!("Rejected change from web client {}: {}", command.client, e);
+            }
+        }
+        if let Some(web) = &self.web {
+            web.publish(self.web_state(), self.web_meters());
+        }
+    }
+
+    /// Recall the scene `name` for a remote client if its `permissions` allow
+    /// changing every strip of every mix. All remote scene recalls go through here.
+    pub fn recall_remote_scene(
+        &mut self,
+        permissions: &Permissions,
+        paths: &Paths,
+        name: &str,
+    ) -> Result<(), String> {
+        permissions
+            .check_scene(self.mixes.len())
+            .map_err(|e| e.to_string())?;
+        scene::load(self, paths, name).map_err(|e| e.to_string())?;
+        self.write_state();
+        Ok(())
+    }
+
+    /// Apply a change of a remote client if its `permissions` allow it.
+    /// All remote changes go through here.
+    pub fn apply_remote_change(
+        &mut self,
+        permissions: &Permissions,
+        change: RemoteChange,
+    ) -> Result<(), String> {
+        let (mix_index, strip_index, change) = match change {
+            RemoteChange::Strip { mix, strip, change } => (mix, strip, change),
+            RemoteChange::MainMono(on) => {
+                permissions
+                    .check(None, Control::MainMono)
+                    .map_err(|e| e.to_string())?;
+                self.set_main_mono(on);
+                return Ok(());
+            }
+            RemoteChange::Phantom(on) => {
+                permissions
+                    .check(None, Control::Phantom)
+                    .map_err(|e| e.to_string())?;
+                self.set_phantom_power(on);
+                return Ok(());
+            }
+            RemoteChange::Line(on) => {
+                permissions
+                    .check(None, Control::Line)
+                    .map_err(|e| e.to_string())?;
+                self.set_1_2_line(on);
+                return Ok(());
+            }
+        };
+
+        let kind = self
+            .mixes
+            .get(mix_index)
+            .and_then(|mix| mix.strips.iter().nth(strip_index))
+            .map(|strip| strip.kind.clone())
+            .ok_or_else(|| format!("no strip {} in mix {}", strip_index + 1, mix_index + 1))?;
+        let control = match change {
+            StripChange::Fader(_) => Control::Fader,
+            StripChange::Pan(_) => Control::Pan,
+            StripChange::Mute(_) if kind == StripKind::Main => Control::MainMute,
+            StripChange::Mute(_) => Control::Mute,
+            StripChange::Solo(_) => Control::Solo,
+        };
+        permissions
+            .check(Some(mix_index), control)
+            .map_err(|e| e.to_string())?;
+
+        let mix = &mut self.mixes[mix_index];
+        match change {
+            StripChange::Fader(db) => {
+                mix.strips
+                    .iter_mut()
+                    .nth(strip_index)
+                    .unwrap()
+                    .set_fader(db);
+            }
+            StripChange::Pan(pan) if kind == StripKind::Channel => {
+                mix.strips.channel_strips[strip_index].balance = pan.clamp(-100.0, 100.0);
+            }
+            StripChange::Mute(on) if kind == StripKind::Main => {
+                self.set_main_mute(on);
+                return Ok(());
+            }
+            StripChange::Mute(on) => mix.strips.iter_mut().nth(strip_index).unwrap().mute = on,
+            StripChange::Solo(on) if kind == StripKind::Channel => {
+                mix.strips.channel_strips[strip_index].solo = on;
+                mix.sync_solo();
+                // Solo changes the muting of every channel of the mix
+                for i in 0..mix.strips.channel_strips.len() {
+                    self.write_channel_fader(mix_index, i);
                 }
+                return Ok(());
+            }
+            StripChange::Pan(_) | StripChange::Solo(_) => {
+                return Err("only channel strips can be panned and soloed".to_string());
+            }
+        }
+        self.write_channel_fader(mix_index, strip_index);
+        Ok(())
+    }
+
+    /// Mixer state sent to the web clients
+    fn web_state(&self) -> serde_json::Value {
+        let labels = self.channel_labels();
+        let mixes: Vec<serde_json::Value> = self
+            .mixes
+            .iter()
+            .enumerate()
+            .map(|(i, mix)| {
+                let strips: Vec<serde_json::Value> = mix
+                    .strips
+                    .iter()
+                    .zip(labels.iter().map(String::as_str).chain([mix.name.as_str()]))
+                    .map(|(strip, name)| {
+                        serde_json::json!({
+                            "name": name,
+                            "bus": strip.kind != StripKind::Channel,
+                            "main": strip.kind == StripKind::Main,
+                            "fader": strip.fader,
+                            "min": strip.min,
+                            "max": strip.max,
+                            "pan": strip.balance,
+                            "mute": if strip.kind == StripKind::Main {
+                                self.main_mute
+                            } else {
+                                strip.mute
+                            },
+                            "solo": strip.solo,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({ "number": i + 1, "name": mix.name, "strips": strips })
+            })
+            .collect();
+        serde_json::json!({
+            "mixes": mixes,
+            "main_mute": self.main_mute,
+            "main_mono": self.main_mono,
+            "phantom_power": self.phantom_power,
+            "line": self.in_1_2_line,
+        })
+    }
+
+    /// Meter readings sent to the web clients, one per input and one pair per mix
+    fn web_meters(&self) -> serde_json::Value {
+        let inputs: Vec<f64> = self.channel_meters.iter().map(|m| m.value).collect();
+        let buses: Vec<[f64; 2]> = self
+            .bus_meters
+            .chunks(2)
+            .map(|pair| [pair[0].value, pair[1].value])
+            .collect();
+        serde_json::json!({ "inputs": inputs, "buses": buses })
+    }
 
-                // synch button states
-                self.phantom_power = self.state.phantom == 0x01;
-                self.in_1_2_line = self.state.line == 0x01;
-                self.main_mute = self.state.mute == 0x01;
-                self.main_mono = self.state.mono == 0x01;
+    fn metrics_snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            inputs: (0..self.channel_names.len())
+                .map(|i| self.channel_label(i))
+                .zip(&self.channel_meters)
+                .collect(),
+            outputs: self
+                .mixes
+                .iter()
+                .flat_map(|mix| [format!("{} L", mix.name), format!("{} R", mix.name)])
+                .zip(&self.bus_meters)
+                .collect(),
+            phantom_power: self.phantom_power,
+            in_1_2_line: self.in_1_2_line,
+            main_mute: self.main_mute,
+            main_mono: self.main_mono,
+            poll_errors: self.poll_errors,
+            write_errors: self.write_errors,
+            poll_latency: self.poll_latency,
+        }
+    }
+
+    /// Load the metering settings in `paths` and start the meter log, metrics endpoint,
+    /// web mixer, D-Bus service and audio port watcher set up there.
+    /// Returns the status message of the last one that failed.
+    pub fn start_services(&mut self, paths: &Paths) -> Option<String> {
+        let mut status = None;
+        let mixes = self.mixes.len();
+        match config::load_json(&paths.metering_file(), |_| Vec::new()) {
+            Ok(settings) => self.metering = settings.unwrap_or_default(),
+            Err(e) => {
+                log::error!("Failed to load metering settings: {}", e);
+                status = Some(format!("Metering settings not loaded: {}", e));
+            }
+        }
+        if let Err(e) = self.start_meter_log(paths.dir()) {
+            log::error!("Failed to start the meter log: {}", e);
+            status = Some(format!("Meter log not started: {}", e));
+        }
+        match config::load_json(&paths.metrics_file(), |_| Vec::new()) {
+            Ok(Some(settings)) => match MetricsServer::start(&settings) {
+                Ok(server) => {
+                    log::info!("Serving metrics on http://{}/metrics", server.address());
+                    self.metrics = Some(server);
+                }
+                Err(e) => {
+                    log::error!("Failed to start the metrics endpoint: {}", e);
+                    status = Some(format!("Metrics endpoint not started: {}", e));
+                }
+            },
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to load metrics settings: {}", e);
+                status = Some(format!("Metrics settings not loaded: {}", e));
+            }
+        }
+        match config::load_json(&paths.web_file(), |s| config::web_errors(s, mixes)) {
+            Ok(Some(settings)) => match WebServer::start(&settings) {
+                Ok(server) => {
+                    log::info!("Serving the web mixer on http://{}/", server.address());
+                    self.web = Some(server);
+                }
+                Err(e) => {
+                    log::error!("Failed to start the web mixer: {}", e);
+                    status = Some(format!("Web mixer not started: {}", e));
+                }
+            },
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to load web mixer settings: {}", e);
+                status = Some(format!("Web mixer settings not loaded: {}", e));
+            }
+        }
+        match config::load_json(&paths.dbus_file(), |s| config::dbus_errors(s, mixes)) {
+            Ok(Some(settings)) => match DbusServer::start(&settings, paths) {
+                Ok(server) => {
+                    log::info!("Serving {} on the D-Bus session bus", settings.name);
+                    self.dbus = Some(server);
+                }
+                Err(e) => {
+                    log::error!("Failed to start the D-Bus service: {}", e);
+                    status = Some(format!("D-Bus service not started: {}", e));
+                }
+            },
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to load D-Bus settings: {}", e);
+                status = Some(format!("D-Bus settings not loaded: {}", e));
+            }
+        }
+        match config::load_json(&paths.ports_file(), |_| Vec::new()) {
+            Ok(Some(settings)) => self.ports = Some(PortWatcher::start(settings, DAW_CHANNELS)),
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to load audio port settings: {}", e);
+                status = Some(format!("Audio port settings not loaded: {}", e));
             }
-            Err(e) => log::error!("Error polling state: {}", e),
         }
+        status
     }
 
-    pub fn load_config(&mut self, config: &str) {
-        let ps_state = serde_json::from_str::<PreSonusStudio1824c>(config).unwrap_or_default();
-        self.channel_names = ps_state.channel_names;
+    /// Start logging the meters if `metering.json` has a `log` section.
+    /// The logs go to `meter_logs` in `config_dir` unless another directory is set.
+    pub fn start_meter_log(&mut self, config_dir: &Path) -> std::io::Result<()> {
+        let Some(settings) = self.metering.log.clone() else {
+            return Ok(());
+        };
+        let names = self
+            .channel_names
+            .iter()
+            .cloned()
+            .chain(
+                self.mixes
+                    .iter()
+                    .flat_map(|mix| [format!("{} L", mix.name), format!("{} R", mix.name)]),
+            )
+            .collect();
+        let logger = MeterLogger::start(settings, &config_dir.join("meter_logs"), names)?;
+        log::info!("Logging meters to {}", logger.dir().display());
+        self.meter_log = Some(logger);
+        Ok(())
+    }
 
-        let mix_state = ps_state.mixes;
-        for i in 0..self.mixes.len() {
-            for j in 0..self.mixes[i].strips.channel_strips.len() {
-                self.mixes[i].strips.channel_strips[j].fader =
-                    mix_state[i].strips.channel_strips[j].fader;
-                self.mixes[i].strips.channel_strips[j].balance =
-                    mix_state[i].strips.channel_strips[j].balance;
-                self.mixes[i].strips.channel_strips[j].solo =
-                    mix_state[i].strips.channel_strips[j].solo;
-                self.mixes[i].strips.channel_strips[j].mute =
-                    mix_state[i].strips.channel_strips[j].mute;
+    /// Stop logging the meters and write the summary, returns the summary path
+    pub fn stop_meter_log(&mut self) -> Option<std::io::Result<std::path::PathBuf>> {
+        self.meter_log.take().map(MeterLogger::finish)
+    }
+
+    /// Parse, validate and apply a config file.
+    /// Nothing is applied if the config is invalid.
+    pub fn load_config(&mut self, config: &str) -> Result<(), ConfigError> {
+        let config = Config::parse(config)?;
+        config.validate(&self.mixes, self.channel_names.len())?;
+        self.apply_config(config);
+        Ok(())
+    }
+
+    /// Apply a validated config, see `Config::validate`
+    pub fn apply_config(&mut self, config: Config) {
+        self.channel_names = config.channel_names;
+        self.channel_colors = config.channel_colors;
+        self.pan_law = config.pan_law;
+
+        for (mix, mix_state) in self.mixes.iter_mut().zip(config.mixes) {
+            for (strip, strip_state) in mix
+                .strips
+                .channel_strips
+                .iter_mut()
+                .zip(mix_state.strips.channel_strips)
+            {
+                strip.fader = strip_state.fader;
+                strip.balance = strip_state.balance;
+                strip.solo = strip_state.solo;
+                strip.mute = strip_state.mute;
             }
 
-            self.mixes[i].name = mix_state[i].name.clone();
-            self.mixes[i].strips.bus_strip.fader = mix_state[i].strips.bus_strip.fader;
-            self.mixes[i].strips.bus_strip.mute = mix_state[i].strips.bus_strip.mute;
-            self.mixes[i].sync_solo();
+            mix.name = mix_state.name;
+            mix.color = mix_state.color;
+            mix.strips.bus_strip.fader = mix_state.strips.bus_strip.fader;
+            mix.strips.bus_strip.mute = mix_state.strips.bus_strip.mute;
+            mix.sync_solo();
         }
     }
 
+    /// Current mixer state in the persisted config format
+    pub fn config(&self) -> Config {
+        Config::new(
+            self.channel_names.clone(),
+            self.channel_colors.clone(),
+            self.pan_law,
+            self.mixes.clone(),
+        )
+    }
+
     pub fn write_state(&mut self) {
         for i in 0..self.mixes.len() {
             let mut bus_index = 0;
@@ -334,18 +981,17 @@ impl PreSonusStudio1824c {
         let soloed = strip.solo;
 
         let fader = strip.fader;
-        let (left, right) = strip.pan_rule(PanLaw::Exponential);
+        let (left, right) = strip.pan_levels(self.pan_law);
         match strip.kind {
             StripKind::Main | StripKind::Bus => {
                 let mut value = Value::DB(fader);
                 if muted {
                     value = Value::Muted;
                 }
-                match self
-                    .command
-                    .set_output_fader(self.mixes[mix_index].strips.bus_strip.number, value)
-                    .send(&self.device)
-                {
+                match self.connection.send(
+                    self.command
+                        .set_output_fader(self.mixes[mix_index].strips.bus_strip.number, value),
+                ) {
                     Ok(_) => {
                         log::debug!(
                             "Set output fader mix {} to {} dB",
@@ -353,7 +999,10 @@ impl PreSonusStudio1824c {
                             fader
                         );
                     }
-                    Err(e) => log::error!("Error setting output fader: {}", e),
+                    Err(e) => {
+                        self.write_errors += 1;
+                        log::error!("Error setting output fader: {}", e)
+                    }
                 }
             }
             StripKind::Channel => {
@@ -361,16 +1010,12 @@ impl PreSonusStudio1824c {
                 if muted & !soloed {
                     value = Value::Muted;
                 }
-                match self
-                    .command
-                    .set_input_fader(
-                        channel_index as u32,
-                        self.mixes[mix_index].strips.bus_strip.number,
-                        Channel::Left,
-                        value,
-                    )
-                    .send(&self.device)
-                {
+                match self.connection.send(self.command.set_input_fader(
+                    channel_index as u32,
+                    self.mixes[mix_index].strips.bus_strip.number,
+                    Channel::Left,
+                    value,
+                )) {
                     Ok(_) => {
                         log::debug!(
                             "Set input fader channel {} mix {} left to {} dB",
@@ -379,23 +1024,22 @@ impl PreSonusStudio1824c {
                             left
                         );
                     }
-                    Err(e) => log::error!("Error setting input fader: {}", e),
+                    Err(e) => {
+                        self.write_errors += 1;
+                        log::error!("Error setting input fader: {}", e)
+                    }
                 }
 
                 value = Value::DB(right);
                 if muted & !soloed {
                     value = Value::Muted;
                 }
-                match self
-                    .command
-                    .set_input_fader(
-                        channel_index as u32,
-                        self.mixes[mix_index].strips.bus_strip.number,
-                        Channel::Right,
-                        value,
-                    )
-                    .send(&self.device)
-                {
+                match self.connection.send(self.command.set_input_fader(
+                    channel_index as u32,
+                    self.mixes[mix_index].strips.bus_strip.number,
+                    Channel::Right,
+                    value,
+                )) {
                     Ok(_) => {
                         log::debug!(
                             "Set input fader channel {} mix {} right to {} dB",
@@ -404,7 +1048,10 @@ impl PreSonusStudio1824c {
                             right
                         );
                     }
-                    Err(e) => log::error!("Error setting input fader: {}", e),
+                    Err(e) => {
+                        self.write_errors += 1;
+                        log::error!("Error setting input fader: {}", e)
+                    }
                 }
             }
         }
@@ -416,14 +1063,16 @@ impl PreSonusStudio1824c {
         // Set all stereo bus faders to unity gain
         for m in 0..9 {
             match self
-                .command
-                .set_output_fader(m, Value::Unity)
-                .send(&self.device)
+                .connection
+                .send(self.command.set_output_fader(m, Value::Unity))
             {
                 Ok(_) => {
                     log::debug!("Set output fader mix {} to unity", m);
                 }
-                Err(e) => log::error!("Error setting output fader: {}", e),
+                Err(e) => {
+                    self.write_errors += 1;
+                    log::error!("Error setting output fader: {}", e)
+                }
             }
         }
 
@@ -447,67 +1096,91 @@ impl PreSonusStudio1824c {
             daw_channel_right = daw_channel_left + 1;
             for c in 0..35 {
                 if c == daw_channel_left {
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Left, Value::Unity)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Left,
+                        Value::Unity,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} left to unity", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Right, Value::Muted)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Right,
+                        Value::Muted,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} right to muted", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
                 } else if c == daw_channel_right {
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Left, Value::Muted)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Left,
+                        Value::Muted,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} left to muted", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Right, Value::Unity)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Right,
+                        Value::Unity,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} right to unity", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
                 } else {
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Left, Value::Muted)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Left,
+                        Value::Muted,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} left to muted", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
-                    match self
-                        .command
-                        .set_input_fader(c, m, Channel::Right, Value::Muted)
-                        .send(&self.device)
-                    {
+                    match self.connection.send(self.command.set_input_fader(
+                        c,
+                        m,
+                        Channel::Right,
+                        Value::Muted,
+                    )) {
                         Ok(_) => {
                             log::debug!("Set input fader channel {} mix {} right to muted", c, m);
                         }
-                        Err(e) => log::error!("Error setting input fader: {}", e),
+                        Err(e) => {
+                            self.write_errors += 1;
+                            log::error!("Error setting input fader: {}", e)
+                        }
                     }
                 }
             }
@@ -515,7 +1188,7 @@ impl PreSonusStudio1824c {
     }
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Clone, Default, PartialEq)]
 pub enum StripKind {
     #[default]
     Channel,
@@ -523,12 +1196,15 @@ pub enum StripKind {
     Main,
 }
 
+/// Range of the pan law in dB
+pub const PAN_LAW_RANGE: std::ops::RangeInclusive<f64> = -6.0..=0.0;
+
 pub enum PanLaw {
     Simple,
     Exponential,
 }
 
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Strip {
     /// Volume fader in dB.
     pub fader: f64,
@@ -581,9 +1257,18 @@ impl Strip {
 
         (left, right)
     }
+
+    /// Left and right levels in dB.
+    /// A centred strip is attenuated by `pan_law` dB,
+    /// fading out to no attenuation when panned hard to one side.
+    pub fn pan_levels(&self, pan_law: f64) -> (f64, f64) {
+        let (left, right) = self.pan_rule(PanLaw::Exponential);
+        let attenuation = pan_law * (1.0 - self.balance.abs() / 100.0);
+        (left + attenuation, right + attenuation)
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct MixStrips {
     pub channel_strips: Vec<Strip>,
     pub bus_strip: Strip,
@@ -658,9 +1343,11 @@ impl MixStrips {
 /// and one destination or bus strip.
 /// The strips are channels
 /// that route to the destination.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Mix {
     pub name: String,
+    /// Custom colour of the bus strip
+    pub color: Option<StripColor>,
     pub strips: MixStrips,
 }
 
@@ -705,6 +1392,7 @@ impl Mix {
 
         Mix {
             name: mix_name,
+            color: None,
             strips: MixStrips {
                 channel_strips,
                 bus_strip,
@@ -761,3 +1449,53 @@ impl Mix {
         self.strips.bus_strip.mute = false;
     }
 }
+
+/// Custom strip colour, persisted as `#rrggbb`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StripColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl StripColor {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        StripColor { r, g, b }
+    }
+}
+
+impl fmt::Display for StripColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for StripColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|h| h.len() == 6 && h.is_ascii())
+            .ok_or_else(|| format!("expected a colour like #rrggbb, found {:?}", s))?;
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("expected a colour like #rrggbb, found {:?}", s))
+        };
+
+        Ok(StripColor::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl Serialize for StripColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for StripColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
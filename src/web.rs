@@ -0,0 +1,469 @@
+//! Optional web mixer, enabled by `web.json`. Serves a mixer page and streams
+//! the state and meters over a WebSocket, which also takes strip changes.
+//! Clients with a token can be limited to some mixes and controls.
+
+use crate::access::{Permissions, RemoteChange, StripChange};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::{Message, WebSocket};
+
+const PAGE: &str = include_str!("web/index.html");
+
+/// A client allowed by a token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebClient {
+    /// Name in the log
+    pub name: String,
+    pub token: String,
+    /// Mixes the client can see and change and the controls it can use
+    #[serde(flatten)]
+    pub permissions: Permissions,
+}
+
+/// Settings of `web.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSettings {
+    /// Address to listen on
+    pub address: String,
+    /// Clients with their tokens. Without clients anyone can change everything.
+    pub clients: Vec<WebClient>,
+}
+
+impl Default for WebSettings {
+    fn default() -> Self {
+        WebSettings {
+            address: "127.0.0.1:8080".to_string(),
+            clients: Vec::new(),
+        }
+    }
+}
+
+/// A change from a web client, to be applied by `PreSonusStudio1824c::poll_state`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebCommand {
+    pub client: String,
+    pub permissions: Permissions,
+    pub change: RemoteChange,
+}
+
+/// Change as sent by the page, mixes and strips numbered from 1.
+/// The bus strip comes after the channel strips.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClientMessage {
+    mix: Option<usize>,
+    strip: Option<usize>,
+    fader: Option<f64>,
+    pan: Option<f64>,
+    mute: Option<bool>,
+    solo: Option<bool>,
+    mono: Option<bool>,
+    phantom: Option<bool>,
+    line: Option<bool>,
+}
+
+/// The published state, with a version that changes on every publish
+#[derive(Default)]
+struct Published {
+    version: u64,
+    state: Value,
+    meters: Value,
+}
+
+pub struct WebServer {
+    published: Arc<Mutex<Published>>,
+    commands: Receiver<WebCommand>,
+    address: SocketAddr,
+}
+
+impl WebServer {
+    pub fn start(settings: &WebSettings) -> io::Result<Self> {
+        let listener = TcpListener::bind(&settings.address)?;
+        let address = listener.local_addr()?;
+        let published = Arc::new(Mutex::new(Published::default()));
+        let (sender, commands) = mpsc::channel();
+        let shared = published.clone();
+        let clients = settings.clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("Web connection failed: {}", e);
+                        continue;
+                    }
+                };
+                let (shared, sender, clients) = (shared.clone(), sender.clone(), clients.clone());
+                std::thread::spawn(move || {
+                    if let Err(e) = serve(stream, &shared, &sender, &clients) {
+                        log::warn!("Web request failed: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(WebServer {
+            published,
+            commands,
+            address,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Publish the mixer `state` and `meters` to all clients
+    pub fn publish(&self, state: Value, meters: Value) {
+        let mut published = self.published.lock().unwrap();
+        published.version += 1;
+        published.state = state;
+        published.meters = meters;
+    }
+
+    /// Changes received since the last call
+    pub fn commands(&self) -> Vec<WebCommand> {
+        self.commands.try_iter().collect()
+    }
+}
+
+/// Serve the page, or a WebSocket for requests that ask for one
+fn serve(
+    stream: TcpStream,
+    published: &Mutex<Published>,
+    commands: &Sender<WebCommand>,
+    clients: &[WebClient],
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let (headers, peeked) = peek_headers(&stream)?;
+    if headers.to_ascii_lowercase().contains("upgrade: websocket") {
+        serve_websocket(stream, published, commands, clients)
+    } else {
+        serve_page(stream, &headers, peeked)
+    }
+}
+
+/// The request line and headers, left in the stream for the WebSocket handshake,
+/// and the number of bytes they were read from
+fn peek_headers(stream: &TcpStream) -> io::Result<(String, usize)> {
+    let mut buffer = [0; 8192];
+    for _ in 0..50 {
+        let read = stream.peek(&mut buffer)?;
+        let headers = String::from_utf8_lossy(&buffer[..read]);
+        if read == 0 || headers.contains("\r\n\r\n") || read == buffer.len() {
+            return Ok((headers.into_owned(), read));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Err(io::ErrorKind::TimedOut.into())
+}
+
+fn serve_page(mut stream: TcpStream, headers: &str, peeked: usize) -> io::Result<()> {
+    // Consume the request
+    let mut buffer = vec![0; peeked];
+    stream.read_exact(&mut buffer)?;
+
+    let path = headers.split_whitespace().nth(1).unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+    let response = if path == "/" {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            PAGE.len(),
+            PAGE
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+/// Client of a request with `token` in the query, None if it isn't allowed
+fn find_client(query: Option<&str>, clients: &[WebClient]) -> Option<WebClient> {
+    if clients.is_empty() {
+        return Some(WebClient {
+            name: "web".to_string(),
+            token: String::new(),
+            permissions: Permissions::default(),
+        });
+    }
+    let token = query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))?;
+    clients.iter().find(|c| c.token == token).cloned()
+}
+
+fn serve_websocket(
+    stream: TcpStream,
+    published: &Mutex<Published>,
+    commands: &Sender<WebCommand>,
+    clients: &[WebClient],
+) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut client = None;
+    // The error type is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        client = find_client(request.uri().query(), clients);
+        if client.is_some() {
+            Ok(response)
+        } else {
+            let mut error = ErrorResponse::new(Some("Unknown token".to_string()));
+            *error.status_mut() = tungstenite::http::StatusCode::FORBIDDEN;
+            Err(error)
+        }
+    };
+    let mut socket =
+        tungstenite::accept_hdr(stream, callback).map_err(|e| io::Error::other(e.to_string()))?;
+    let Some(client) = client else {
+        return Ok(());
+    };
+    log::info!("Web client {} connected from {}", client.name, peer);
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(50)))?;
+
+    let mut version = 0;
+    let mut last_state = Value::Null;
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => match parse_message(&text) {
+                Ok(change) => {
+                    let command = WebCommand {
+                        client: client.name.clone(),
+                        permissions: client.permissions.clone(),
+                        change,
+                    };
+                    if commands.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => log::warn!("Ignoring message from web client {}: {}", client.name, e),
+            },
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => break,
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        }
+
+        let (state, meters) = {
+            let published = published.lock().unwrap();
+            if published.version == version {
+                continue;
+            }
+            version = published.version;
+            (published.state.clone(), published.meters.clone())
+        };
+        let state = client_view(state, &client.permissions);
+        if state != last_state {
+            send(&mut socket, json!({ "type": "state", "state": &state }))?;
+            last_state = state;
+        }
+        let meters = client_meters(meters, &client.permissions);
+        send(&mut socket, json!({ "type": "meters", "meters": meters }))?;
+    }
+    log::info!("Web client {} disconnected", client.name);
+    Ok(())
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: Value) -> io::Result<()> {
+    socket
+        .send(Message::text(message.to_string()))
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// The change of a message from the page
+fn parse_message(text: &str) -> Result<RemoteChange, String> {
+    let message: ClientMessage = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let changes = [
+        message.fader.map(StripChange::Fader),
+        message.pan.map(StripChange::Pan),
+        message.mute.map(StripChange::Mute),
+        message.solo.map(StripChange::Solo),
+    ];
+    let buttons = [
+        message.mono.map(RemoteChange::MainMono),
+        message.phantom.map(RemoteChange::Phantom),
+        message.line.map(RemoteChange::Line),
+    ];
+    let mut changes = changes.into_iter().flatten();
+    let mut buttons = buttons.into_iter().flatten();
+    match (
+        changes.next(),
+        buttons.next(),
+        changes.next(),
+        buttons.next(),
+    ) {
+        (Some(change), None, None, None) => {
+            let mix = message.mix.and_then(|mix| mix.checked_sub(1));
+            let strip = message.strip.and_then(|strip| strip.checked_sub(1));
+            match (mix, strip) {
+                (Some(mix), Some(strip)) => Ok(RemoteChange::Strip { mix, strip, change }),
+                _ => Err("strip changes need a mix and strip numbered from 1".to_string()),
+            }
+        }
+        (None, Some(button), None, None) => Ok(button),
+        _ => Err("expected one of fader, pan, mute, solo, mono, phantom or line".to_string()),
+    }
+}
+
+/// The state with only the mixes a client is allowed, and the controls it can use
+fn client_view(mut state: Value, permissions: &Permissions) -> Value {
+    if let Some(mixes) = state["mixes"].as_array_mut() {
+        mixes.retain(|mix| {
+            mix["number"]
+                .as_u64()
+                .is_some_and(|number| permissions.allows_mix(number as usize - 1))
+        });
+    }
+    if let Some(state) = state.as_object_mut() {
+        state.insert("controls".to_string(), json!(permissions.controls()));
+    }
+    state
+}
+
+/// The meters with the buses of the mixes a client isn't allowed left out
+fn client_meters(mut meters: Value, permissions: &Permissions) -> Value {
+    if let Some(buses) = meters["buses"].as_array_mut() {
+        for (i, bus) in buses.iter_mut().enumerate() {
+            if !permissions.allows_mix(i) {
+                *bus = Value::Null;
+            }
+        }
+    }
+    meters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::Control;
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(
+            parse_message(r#"{ "mix": 2, "strip": 5, "fader": -6.5 }"#),
+            Ok(RemoteChange::Strip {
+                mix: 1,
+                strip: 4,
+                change: StripChange::Fader(-6.5)
+            })
+        );
+        assert_eq!(
+            parse_message(r#"{ "mix": 1, "strip": 1, "solo": true }"#),
+            Ok(RemoteChange::Strip {
+                mix: 0,
+                strip: 0,
+                change: StripChange::Solo(true)
+            })
+        );
+        assert_eq!(
+            parse_message(r#"{ "phantom": false }"#),
+            Ok(RemoteChange::Phantom(false))
+        );
+        assert!(parse_message(r#"{ "mix": 1, "strip": 1, "fader": 0, "pan": 0 }"#).is_err());
+        assert!(parse_message(r#"{ "mix": 1, "strip": 1, "mute": true, "line": true }"#).is_err());
+        assert!(parse_message(r#"{ "mix": 0, "strip": 1, "pan": 0 }"#).is_err());
+        assert!(parse_message(r#"{ "fader": 0 }"#).is_err());
+    }
+
+    #[test]
+    fn test_serve_page_consumes_invalid_utf8() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /\xff\xfe HTTP/1.1\r\n\r\n").unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let (headers, peeked) = peek_headers(&server).unwrap();
+        serve_page(server, &headers, peeked).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_clients() {
+        let clients = vec![WebClient {
+            name: "drums".to_string(),
+            token: "s3cret".to_string(),
+            permissions: Permissions {
+                mixes: Some(vec![3]),
+                controls: Some(vec![Control::Fader]),
+            },
+        }];
+        assert_eq!(
+            find_client(Some("token=s3cret"), &clients),
+            Some(clients[0].clone())
+        );
+        assert_eq!(find_client(Some("token=guess"), &clients), None);
+        assert_eq!(find_client(None, &clients), None);
+        assert!(find_client(None, &[]).is_some_and(|c| c.permissions == Permissions::default()));
+
+        let permissions = &clients[0].permissions;
+        let state = json!({ "mixes": [{ "number": 1 }, { "number": 3 }] });
+        assert_eq!(
+            client_view(state.clone(), permissions),
+            json!({ "mixes": [{ "number": 3 }], "controls": ["fader"] })
+        );
+        assert_eq!(
+            client_view(state, &Permissions::default())["mixes"]
+                .as_array()
+                .map(Vec::len),
+            Some(2)
+        );
+        let buses = json!({ "buses": [[-1.0, -2.0], [-3.0, -4.0], [-5.0, -6.0]] });
+        assert_eq!(
+            client_meters(buses, permissions),
+            json!({ "buses": [null, null, [-5.0, -6.0]] })
+        );
+    }
+
+    #[test]
+    fn test_websocket() {
+        let settings = WebSettings {
+            address: "127.0.0.1:0".to_string(),
+            clients: Vec::new(),
+        };
+        let server = WebServer::start(&settings).unwrap();
+        server.publish(json!({ "mixes": [] }), json!({ "inputs": [-12.0] }));
+
+        let url = format!("ws://{}/ws", server.address());
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert!(message.contains(r#""type":"state""#));
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert!(message.contains("-12.0"));
+
+        socket
+            .send(Message::text(r#"{ "mix": 1, "strip": 2, "pan": -30 }"#))
+            .unwrap();
+        for _ in 0..100 {
+            if let Some(command) = server.commands().pop() {
+                assert_eq!(
+                    command.change,
+                    RemoteChange::Strip {
+                        mix: 0,
+                        strip: 1,
+                        change: StripChange::Pan(-30.0)
+                    }
+                );
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no command received");
+    }
+}